@@ -0,0 +1,530 @@
+use crate::{hittable::*, interval::*, ray::*, vec3::*};
+use std::ops::{Add, Mul, Sub};
+use std::rc::Rc;
+
+/// How values change between keyframes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    /// Straight from one key to the next, changing speed abruptly at every key.
+    Linear,
+    /// Smooth curve through the keys, aimed along the line between the neighbouring keys at
+    /// every key, and towards the next key at the first and last one.
+    CatmullRom,
+    /// Cubic Bezier curve between the keys, pulled towards their handles, which sit a third of
+    /// the way to the neighbouring keys in time. Handles a key leaves out follow the Catmull-Rom curve
+    /// between inner keys and are flat at the first and last key, so motion eases in at the
+    /// start and out at the end.
+    Bezier,
+}
+
+impl Interpolation {
+    /// Parses an interpolation name as used in animation files.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Interpolation::Linear),
+            "catmull-rom" => Some(Interpolation::CatmullRom),
+            "bezier" => Some(Interpolation::Bezier),
+            _ => None,
+        }
+    }
+}
+
+/// Values that can be keyframed: blended by adding scaled differences.
+pub trait Keyable:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self>
+{
+}
+
+impl<T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>> Keyable for T {}
+
+/// Values of the Bezier handles before and after a key, where the key sets them.
+pub type Handles<T> = (Option<T>, Option<T>);
+
+// Keyframe of an animated value.
+#[derive(Debug, Copy, Clone)]
+struct Key<T> {
+    time: f64,
+    value: T,
+    // Bezier handles before and after the key.
+    handles: Handles<T>,
+}
+
+/// Keyframes of one animated value, sorted by time.
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    keys: Vec<Key<T>>,
+}
+
+impl<T: Keyable> Track<T> {
+    /// Creates a track without keys.
+    pub fn new() -> Self {
+        Track { keys: Vec::new() }
+    }
+
+    /// Sets the value at `time`, with the values of the Bezier handles before and after it if
+    /// any, replacing a key at the same time.
+    pub fn insert(&mut self, time: f64, value: T, handles: Handles<T>) {
+        let key = Key {
+            time,
+            value,
+            handles,
+        };
+        match self.keys.binary_search_by(|key| key.time.total_cmp(&time)) {
+            Ok(index) => self.keys[index] = key,
+            Err(index) => self.keys.insert(index, key),
+        }
+    }
+
+    /// Returns the value at `time`, held at the first and last key outside them, or `None`
+    /// without keys.
+    pub fn value_at(&self, time: f64, interpolation: Interpolation) -> Option<T> {
+        let (first, last) = (self.keys.first()?, self.keys.last()?);
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+        // The key starting the segment holding `time`
+        let k = self.keys.partition_point(|key| key.time <= time) - 1;
+        let (start, end) = (self.keys[k], self.keys[k + 1]);
+        let (p0, p3) = (start.value, end.value);
+        let duration = end.time - start.time;
+        let s = (time - start.time) / duration;
+        if interpolation == Interpolation::Linear {
+            return Some(p0 + (p3 - p0) * s);
+        }
+
+        // Cubic Bezier segment, with the handles a third of the tangents along unless the
+        // keys set them
+        let mut p1 = p0 + self.tangent(k, interpolation) * (duration / 3.0);
+        let mut p2 = p3 - self.tangent(k + 1, interpolation) * (duration / 3.0);
+        if interpolation == Interpolation::Bezier {
+            p1 = start.handles.1.unwrap_or(p1);
+            p2 = end.handles.0.unwrap_or(p2);
+        }
+        let r = 1.0 - s;
+        Some(p0 * (r * r * r) + p1 * (3.0 * r * r * s) + p2 * (3.0 * r * s * s) + p3 * (s * s * s))
+    }
+
+    // Returns the rate of change of the curve at key `k`.
+    fn tangent(&self, k: usize, interpolation: Interpolation) -> T {
+        let last = self.keys.len() - 1;
+        let end = k == 0 || k == last;
+        if end && interpolation == Interpolation::Bezier {
+            return self.keys[k].value * 0.0;
+        }
+        let (before, after) = (self.keys[k.saturating_sub(1)], self.keys[(k + 1).min(last)]);
+        (after.value - before.value) * (1.0 / (after.time - before.time))
+    }
+}
+
+/// Keyframed transform of a scene object: moved by a translation, turned about the vertical
+/// axis through its center and scaled about its center.
+#[derive(Debug, Clone)]
+pub struct ObjectAnimation {
+    /// Offset from the object's place in the scene.
+    pub translate: Track<Vec3>,
+
+    /// Angle in degrees about the vertical axis, counterclockwise seen from above.
+    pub rotate: Track<f64>,
+
+    /// Uniform scale.
+    pub scale: Track<f64>,
+}
+
+impl ObjectAnimation {
+    /// Creates an animation without keys, which leaves the object in place.
+    pub fn new() -> Self {
+        ObjectAnimation {
+            translate: Track::new(),
+            rotate: Track::new(),
+            scale: Track::new(),
+        }
+    }
+}
+
+/// Keyframes of the camera and of named scene objects, with times in seconds.
+#[derive(Debug, Clone)]
+pub struct Animation {
+    /// How all tracks change between their keys.
+    pub interpolation: Interpolation,
+
+    /// Position of the camera.
+    pub lookfrom: Track<Vec3>,
+
+    /// Point the camera looks at.
+    pub lookat: Track<Vec3>,
+
+    /// Vertical field of view in degrees, set through the focal length of a physical camera.
+    pub vfov: Track<f64>,
+
+    /// Focus distance.
+    pub focus_dist: Track<f64>,
+
+    /// Animations of the objects with the given names.
+    pub objects: Vec<(String, ObjectAnimation)>,
+}
+
+impl Animation {
+    /// Loads an animation from a text file holding one key per line as its time, `camera` or
+    /// an object name, the property and its value. Camera properties are `lookfrom` and
+    /// `lookat`, with three values, and `vfov` and `focus_dist`; object properties are
+    /// `translate`, with three values, `rotate` and `scale`. The value may be followed by `in`
+    /// and `out` with the values of the key's Bezier handles. A line `interpolation NAME`
+    /// chooses `linear`, the default, `catmull-rom` or `bezier`. Lines starting with `#` are
+    /// comments.
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let invalid =
+            |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+        let text = std::fs::read_to_string(path)?;
+        let mut animation = Animation {
+            interpolation: Interpolation::Linear,
+            lookfrom: Track::new(),
+            lookat: Track::new(),
+            vfov: Track::new(),
+            focus_dist: Track::new(),
+            objects: Vec::new(),
+        };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if let ["interpolation", name] = fields[..] {
+                animation.interpolation = Interpolation::parse(name).ok_or_else(|| {
+                    invalid(format!(
+                        "line {}: unknown interpolation {}",
+                        number + 1,
+                        name
+                    ))
+                })?;
+                continue;
+            }
+            let [time, target, property, ref values @ ..] = fields[..] else {
+                return Err(invalid(format!(
+                    "line {}: expected time, target, property and value",
+                    number + 1
+                )));
+            };
+            let time: f64 = time
+                .parse()
+                .map_err(|_| invalid(format!("line {}: invalid time {}", number + 1, time)))?;
+            // The key's values, then those of its handles after `in` and `out`
+            let mut groups: Vec<(&str, Vec<f64>)> = vec![("", Vec::new())];
+            for &field in values {
+                if field == "in" || field == "out" {
+                    if groups.iter().any(|(label, _)| *label == field) {
+                        return Err(invalid(format!(
+                            "line {}: repeated handle {}",
+                            number + 1,
+                            field
+                        )));
+                    }
+                    groups.push((field, Vec::new()));
+                    continue;
+                }
+                let value = field
+                    .parse()
+                    .map_err(|_| invalid(format!("line {}: expected numbers", number + 1)))?;
+                groups.last_mut().unwrap().1.push(value);
+            }
+            let wrong_count = || {
+                invalid(format!(
+                    "line {}: wrong number of values for {}",
+                    number + 1,
+                    property
+                ))
+            };
+            let vector = |values: &[f64]| match values {
+                [x, y, z] => Ok(Vec3::new(*x, *y, *z)),
+                _ => Err(wrong_count()),
+            };
+            let scalar = |values: &[f64]| match values {
+                [value] => Ok(*value),
+                _ => Err(wrong_count()),
+            };
+
+            if target == "camera" {
+                match property {
+                    "lookfrom" => {
+                        let (value, handles) = key_values(&groups, vector)?;
+                        animation.lookfrom.insert(time, value, handles);
+                    }
+                    "lookat" => {
+                        let (value, handles) = key_values(&groups, vector)?;
+                        animation.lookat.insert(time, value, handles);
+                    }
+                    "vfov" => {
+                        let (value, handles) = key_values(&groups, scalar)?;
+                        animation.vfov.insert(time, value, handles);
+                    }
+                    "focus_dist" => {
+                        let (value, handles) = key_values(&groups, scalar)?;
+                        animation.focus_dist.insert(time, value, handles);
+                    }
+                    _ => {
+                        return Err(invalid(format!(
+                            "line {}: unknown camera property {}",
+                            number + 1,
+                            property
+                        )))
+                    }
+                }
+                continue;
+            }
+            let index = match animation
+                .objects
+                .iter()
+                .position(|(name, _)| name == target)
+            {
+                Some(index) => index,
+                None => {
+                    animation
+                        .objects
+                        .push((target.to_string(), ObjectAnimation::new()));
+                    animation.objects.len() - 1
+                }
+            };
+            let object = &mut animation.objects[index].1;
+            match property {
+                "translate" => {
+                    let (value, handles) = key_values(&groups, vector)?;
+                    object.translate.insert(time, value, handles);
+                }
+                "rotate" => {
+                    let (value, handles) = key_values(&groups, scalar)?;
+                    object.rotate.insert(time, value, handles);
+                }
+                "scale" => {
+                    // Curves stay within their handles, so positive handles keep it positive
+                    let (scale, handles) = key_values(&groups, scalar)?;
+                    let positive = |scale: Option<f64>| scale.is_none_or(|scale| scale > 0.0);
+                    if !(scale > 0.0 && positive(handles.0) && positive(handles.1)) {
+                        return Err(invalid(format!(
+                            "line {}: the scale must be positive",
+                            number + 1
+                        )));
+                    }
+                    object.scale.insert(time, scale, handles);
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "line {}: unknown object property {}",
+                        number + 1,
+                        property
+                    )))
+                }
+            }
+        }
+        Ok(animation)
+    }
+}
+
+// Reads the value of a key and of its handles from the groups of numbers on its line.
+fn key_values<T>(
+    groups: &[(&str, Vec<f64>)],
+    read: impl Fn(&[f64]) -> std::io::Result<T>,
+) -> std::io::Result<(T, Handles<T>)> {
+    let handle = |label: &str| {
+        groups
+            .iter()
+            .find(|(name, _)| *name == label)
+            .map(|(_, values)| read(values))
+            .transpose()
+    };
+    Ok((read(&groups[0].1)?, (handle("in")?, handle("out")?)))
+}
+
+/// Scene object moved by an animation, placed where the animation has it at the time of each
+/// ray.
+pub struct Animated {
+    // Object in its place in the scene.
+    object: Rc<dyn Hittable>,
+    // Keyframes of the transform.
+    animation: ObjectAnimation,
+    // How the transform changes between keys.
+    interpolation: Interpolation,
+    // Point the object turns and scales about.
+    pivot: Point3,
+}
+
+impl Animated {
+    /// Animates an object, turning and scaling it about its center, or about the origin when
+    /// it has none.
+    pub fn new(
+        object: Rc<dyn Hittable>,
+        animation: ObjectAnimation,
+        interpolation: Interpolation,
+    ) -> Self {
+        let pivot = object.center(0.0).unwrap_or(Point3::zero());
+        Animated {
+            object,
+            animation,
+            interpolation,
+            pivot,
+        }
+    }
+
+    // Returns the translation, angle in radians and scale at `time`.
+    fn transform(&self, time: f64) -> (Vec3, f64, f64) {
+        let translate = self.animation.translate.value_at(time, self.interpolation);
+        let rotate = self.animation.rotate.value_at(time, self.interpolation);
+        let scale = self.animation.scale.value_at(time, self.interpolation);
+        (
+            translate.unwrap_or(Vec3::zero()),
+            rotate.unwrap_or(0.0).to_radians(),
+            scale.unwrap_or(1.0),
+        )
+    }
+}
+
+// Rotates a vector about the vertical axis, counterclockwise seen from above.
+fn rotate_y(v: Vec3, angle: f64) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    Vec3::new(cos * v.x() + sin * v.z(), v.y(), -sin * v.x() + cos * v.z())
+}
+
+impl Hittable for Animated {
+    // Intersects the ray moved into the object's place in the scene, which keeps the ray
+    // parameter of the hit.
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let (translate, angle, scale) = self.transform(r.time());
+        let origin = rotate_y(r.origin() - self.pivot - translate, -angle) / scale + self.pivot;
+        let direction = rotate_y(r.direction(), -angle) / scale;
+        let moved = Ray::new(origin, direction)
+            .with_wavelength(r.wavelength())
+            .with_time(r.time());
+        if !self.object.hit(&moved, ray_t, rec) {
+            return false;
+        }
+        rec.p = r.at(rec.t);
+        rec.normal = rotate_y(rec.normal, angle);
+        true
+    }
+
+    // Returns the center of the object moved by the translation at `time`.
+    fn center(&self, time: f64) -> Option<Point3> {
+        let (translate, _, _) = self.transform(time);
+        self.object.center(time).map(|center| center + translate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Interpolation; 3] = [
+        Interpolation::Linear,
+        Interpolation::CatmullRom,
+        Interpolation::Bezier,
+    ];
+
+    // Returns a track with the given keys.
+    fn track(keys: &[(f64, f64)]) -> Track<f64> {
+        let mut track = Track::new();
+        for &(time, value) in keys {
+            track.insert(time, value, (None, None));
+        }
+        track
+    }
+
+    #[test]
+    fn values_hold_outside_the_keys() {
+        let keys = track(&[(1.0, 2.0), (3.0, 6.0)]);
+        for interpolation in ALL {
+            assert_eq!(Track::<f64>::new().value_at(1.0, interpolation), None);
+            assert_eq!(track(&[(1.0, 5.0)]).value_at(7.0, interpolation), Some(5.0));
+            assert_eq!(keys.value_at(0.0, interpolation), Some(2.0));
+            assert_eq!(keys.value_at(4.0, interpolation), Some(6.0));
+        }
+    }
+
+    #[test]
+    fn curves_pass_through_the_keys() {
+        // Keys inserted out of order, one of them replaced
+        let keys = track(&[(2.0, 1.0), (0.0, 0.0), (1.0, 9.0), (3.0, 4.0), (1.0, 3.0)]);
+        for interpolation in ALL {
+            for (time, value) in [(0.0, 0.0), (1.0, 3.0), (2.0, 1.0), (3.0, 4.0)] {
+                let at = keys.value_at(time, interpolation).unwrap();
+                assert!(
+                    (at - value).abs() < 1e-12,
+                    "{:?} at {}",
+                    interpolation,
+                    time
+                );
+            }
+        }
+        assert_eq!(keys.value_at(0.25, Interpolation::Linear), Some(0.75));
+    }
+
+    #[test]
+    fn catmull_rom_keeps_steady_motion_steady() {
+        let keys = track(&[(0.0, 0.0), (1.0, 2.0), (3.0, 6.0), (4.0, 8.0)]);
+        for time in [0.3, 1.7, 2.5, 3.9] {
+            let value = keys.value_at(time, Interpolation::CatmullRom).unwrap();
+            assert!((value - 2.0 * time).abs() < 1e-12, "{}", time);
+        }
+    }
+
+    #[test]
+    fn bezier_eases_in_and_out_at_the_ends() {
+        let keys = track(&[(0.0, 0.0), (1.0, 2.0), (2.0, 4.0)]);
+        let value = |time| keys.value_at(time, Interpolation::Bezier).unwrap();
+        // Flat ends, so the first and last hundredth of a second move far less than the 0.02
+        // of steady motion
+        assert!(value(0.01) < 0.001);
+        assert!(4.0 - value(1.99) < 0.001);
+        // Slowing down into the last key, it runs ahead of the steady motion before it
+        assert!((value(1.5) - 3.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn bezier_handles_shape_the_curve() {
+        // Handles above the straight line lift the middle of the segment above its 3
+        let mut keys = Track::new();
+        keys.insert(0.0, 0.0, (None, Some(3.0)));
+        keys.insert(3.0, 6.0, (Some(9.0), None));
+        let value = |time, interpolation| keys.value_at(time, interpolation).unwrap();
+        assert!((value(1.5, Interpolation::Bezier) - 5.25).abs() < 1e-12);
+        assert_eq!(value(3.0, Interpolation::Bezier), 6.0);
+        // The curve leaves along its first handle
+        assert!((value(0.003, Interpolation::Bezier) - 0.009).abs() < 1e-4);
+        // Only Bezier curves have handles
+        assert!((value(1.5, Interpolation::CatmullRom) - 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn animation_files_set_keys_and_handles() {
+        let path = std::env::temp_dir().join(format!("keys-{}.txt", std::process::id()));
+        let load = |text: &str| {
+            std::fs::write(&path, text).unwrap();
+            Animation::load(path.to_str().unwrap())
+        };
+        let animation = load(
+            "interpolation bezier\n\
+             # Keys with handles\n\
+             0 camera vfov 40 out 40\n\
+             2 camera vfov 20 in 30\n\
+             1 ball translate 0 1 0 in 0 0 0 out 0 2 0\n",
+        )
+        .unwrap();
+        let invalid = [
+            "0 camera vfov 40 in 1 in 2\n",
+            "0 camera vfov 40 out\n",
+            "0 camera lookat 0 0 0 out 1 1\n",
+            "0 ball scale 1 out -1\n",
+        ]
+        .map(|text| load(text).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(animation.interpolation, Interpolation::Bezier);
+        let vfov = animation.vfov.value_at(1.0, Interpolation::Bezier).unwrap();
+        assert!((vfov - 33.75).abs() < 1e-12, "{}", vfov);
+        let (name, ball) = &animation.objects[0];
+        assert_eq!(name, "ball");
+        assert_eq!(ball.translate.keys[0].handles.1.unwrap().e, [0.0, 2.0, 0.0]);
+        assert_eq!(invalid, [true; 4]);
+    }
+}
@@ -0,0 +1,422 @@
+use crate::{color::*, display::*, film::*, material::*, rtweekend::*, tile::*, vec3::*};
+use exr::prelude::*;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+/// Arbitrary output variable: an image besides the beauty pass, taken from the first hit of
+/// every camera ray or from a part of the light it gathers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Aov {
+    /// Reflectance of the first surface hit.
+    Albedo,
+    /// Shading normal at the first hit, in world space.
+    Normal,
+    /// World-space position of the first hit.
+    Position,
+    /// Distance from the camera ray's origin to the first hit: the hit record's `t` in world
+    /// units.
+    Depth,
+    /// Index of the first object hit in the scene's top-level list.
+    ObjectId,
+    /// Identifier of the material at the first hit, equal for materials with equal parameters.
+    MaterialId,
+    /// Light scattered off a diffuse first hit straight from the environment.
+    DirectDiffuse,
+    /// Light scattered off a diffuse first hit after further bounces.
+    IndirectDiffuse,
+    /// Light reflected or refracted by a specular first hit straight from the environment.
+    DirectSpecular,
+    /// Light reflected or refracted by a specular first hit after further bounces.
+    IndirectSpecular,
+}
+
+impl Aov {
+    /// Every output variable, in the order they are written.
+    pub const ALL: [Aov; 10] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Position,
+        Aov::Depth,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::DirectDiffuse,
+        Aov::IndirectDiffuse,
+        Aov::DirectSpecular,
+        Aov::IndirectSpecular,
+    ];
+
+    /// Returns the name used on the command line, in file names and for EXR layers.
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Depth => "depth",
+            Aov::ObjectId => "object-id",
+            Aov::MaterialId => "material-id",
+            Aov::DirectDiffuse => "direct-diffuse",
+            Aov::IndirectDiffuse => "indirect-diffuse",
+            Aov::DirectSpecular => "direct-specular",
+            Aov::IndirectSpecular => "indirect-specular",
+        }
+    }
+
+    /// Parses an output variable name.
+    pub fn parse(name: &str) -> Option<Self> {
+        Aov::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+}
+
+/// What a camera sample's path found at its first hit.
+#[derive(Clone)]
+pub struct FirstHit {
+    /// Attenuation of the first scattering, before any sampling weights.
+    pub albedo: Color,
+    /// Shading normal.
+    pub normal: Vec3,
+    /// World-space position.
+    pub position: Point3,
+    /// Distance from the ray origin.
+    pub depth: f64,
+    /// Index of the object in the scene's top-level list.
+    pub object_id: usize,
+    /// Material of the surface, if any.
+    pub material: Option<Rc<dyn Material>>,
+    /// Whether the first scattering was specular rather than diffuse.
+    pub specular: bool,
+}
+
+/// Record of one camera sample's path, filled in while tracing it.
+#[derive(Clone)]
+pub struct PathRecord {
+    /// Number of scattering events along the path.
+    pub bounces: u32,
+    /// First hit, if the camera ray hit anything.
+    pub first_hit: Option<FirstHit>,
+}
+
+impl PathRecord {
+    /// Creates the record of a path that has not been traced yet.
+    pub fn new() -> Self {
+        PathRecord {
+            bounces: 0,
+            first_hit: None,
+        }
+    }
+}
+
+/// Output variables accumulated in one pixel.
+#[derive(Clone)]
+pub struct AovPixel {
+    // Samples taken, and how many of them hit something.
+    samples: u32,
+    hits: u32,
+    // Sums over the hits.
+    albedo: Color,
+    normal: Vec3,
+    position: Vec3,
+    depth: f64,
+    // Identifiers seen by the pixel's first hitting sample.
+    object_id: Option<u32>,
+    material_id: Option<u32>,
+    // Sums over all samples of direct diffuse, indirect diffuse, direct specular and
+    // indirect specular light.
+    lighting: [Color; 4],
+}
+
+impl AovPixel {
+    /// Creates a pixel with no samples.
+    pub fn new() -> Self {
+        AovPixel {
+            samples: 0,
+            hits: 0,
+            albedo: Color::zero(),
+            normal: Vec3::zero(),
+            position: Vec3::zero(),
+            depth: 0.0,
+            object_id: None,
+            material_id: None,
+            lighting: [Color::zero(); 4],
+        }
+    }
+
+    /// Adds a sample with the given path record and color.
+    pub fn add(&mut self, path: &PathRecord, color: Color) {
+        self.samples += 1;
+        let Some(hit) = &path.first_hit else {
+            return;
+        };
+        self.hits += 1;
+        self.albedo += hit.albedo;
+        self.normal += hit.normal;
+        self.position += hit.position;
+        self.depth += hit.depth;
+        if self.object_id.is_none() {
+            self.object_id = Some(hit.object_id as u32);
+            self.material_id = Some(material_id(hit.material.as_deref()));
+        }
+
+        // Light that arrives after one scattering is direct, the rest indirect
+        let component = 2 * hit.specular as usize + (path.bounces > 1) as usize;
+        self.lighting[component] += color;
+    }
+
+    /// Returns the value of an output variable, as up to three channels.
+    ///
+    /// Geometric variables average over the samples that hit something and light components
+    /// over all samples. Pixels that hit nothing have infinite depth and no identifiers, which
+    /// read as `u32::MAX`.
+    pub fn value(&self, aov: Aov) -> Vec3 {
+        let per_hit = 1.0 / self.hits.max(1) as f64;
+        let per_sample = 1.0 / self.samples.max(1) as f64;
+        let id = |id: Option<u32>| {
+            let id = id.unwrap_or(u32::MAX) as f64;
+            Vec3::new(id, id, id)
+        };
+        match aov {
+            Aov::Albedo => (self.albedo * per_hit).0,
+            // Opposite normals of the samples may cancel out
+            Aov::Normal if self.normal.length_squared() > 0.0 => Vec3::unit_vector(self.normal),
+            Aov::Normal => Vec3::zero(),
+            Aov::Position => self.position * per_hit,
+            Aov::Depth if self.hits > 0 => {
+                let depth = self.depth * per_hit;
+                Vec3::new(depth, depth, depth)
+            }
+            Aov::Depth => Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            Aov::ObjectId => id(self.object_id),
+            Aov::MaterialId => id(self.material_id),
+            Aov::DirectDiffuse => (self.lighting[0] * per_sample).0,
+            Aov::IndirectDiffuse => (self.lighting[1] * per_sample).0,
+            Aov::DirectSpecular => (self.lighting[2] * per_sample).0,
+            Aov::IndirectSpecular => (self.lighting[3] * per_sample).0,
+        }
+    }
+}
+
+// Identifies a material by its parameters, so equal materials share an identifier in every
+// render and on every machine.
+fn material_id(material: Option<&dyn Material>) -> u32 {
+    let Some(material) = material else {
+        return u32::MAX;
+    };
+    let mut hasher = StableHasher::new();
+    material.hash_parameters(&mut hasher);
+    // Keep identifiers exactly representable as 32-bit floats
+    (hasher.finish() % (1 << 24)) as u32
+}
+
+/// Output variables being accumulated for an area of the frame.
+pub struct AovFilm {
+    /// Pixels of the frame covered.
+    pub area: Tile,
+
+    /// Output variables of every pixel in row-major order, starting at the top left.
+    pub pixels: Vec<AovPixel>,
+}
+
+impl AovFilm {
+    /// Creates an empty film covering an area of the frame.
+    pub fn new(area: Tile) -> Self {
+        AovFilm {
+            area,
+            pixels: vec![AovPixel::new(); (area.width * area.height) as usize],
+        }
+    }
+
+    /// Returns the output variables of a pixel, given in frame coordinates.
+    pub fn pixel(&self, i: i32, j: i32) -> &AovPixel {
+        &self.pixels[((j - self.area.y) * self.area.width + (i - self.area.x)) as usize]
+    }
+
+    /// Returns the output variables of a pixel, given in frame coordinates, for updating.
+    pub fn pixel_mut(&mut self, i: i32, j: i32) -> &mut AovPixel {
+        &mut self.pixels[((j - self.area.y) * self.area.width + (i - self.area.x)) as usize]
+    }
+
+    /// Writes an output variable in `frame` as a PPM image for viewing.
+    ///
+    /// Light components go through the display transform like the beauty image, and albedos
+    /// are only encoded for display. Normals map from [-1, 1] to [0, 1], positions and depths
+    /// are scaled to the range in the image, and identifiers get a random color each.
+    pub fn write_ppm(
+        &self,
+        aov: Aov,
+        path: &str,
+        frame: &Tile,
+        display: &DisplayTransform,
+    ) -> std::io::Result<()> {
+        // Range of the finite values, for scaling positions and depths
+        let finite = self
+            .pixels
+            .iter()
+            .filter(|pixel| pixel.hits > 0)
+            .map(|pixel| pixel.value(aov));
+        let (low, high) = finite.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), value| {
+            let (min, max) = (value.e.iter().copied().fold(f64::INFINITY, f64::min), {
+                value.e.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+            });
+            (low.min(min), high.max(max))
+        });
+        let scale = 1.0 / (high - low).max(1e-9);
+
+        write_frame_ppm(path, frame, &self.area, |i, j| {
+            let pixel = self.pixel(i, j);
+            let value = pixel.value(aov);
+            match aov {
+                Aov::Albedo => display.encode(Color(value)),
+                Aov::Normal => Color(value * 0.5 + Vec3::new(0.5, 0.5, 0.5)),
+                Aov::Position if pixel.hits > 0 => {
+                    Color((value - Vec3::new(low, low, low)) * scale)
+                }
+                Aov::Depth if pixel.hits > 0 => {
+                    let near = 1.0 - (value.x() - low) * scale;
+                    Color::new(near, near, near)
+                }
+                Aov::ObjectId | Aov::MaterialId if pixel.hits > 0 => {
+                    Color(id_color(value.x() as u32))
+                }
+                Aov::Position | Aov::Depth | Aov::ObjectId | Aov::MaterialId => Color::zero(),
+                _ => display.apply(Color(value)),
+            }
+        })
+    }
+
+    /// Writes the beauty image and the given output variables in `frame` as layers of one
+    /// multi-part OpenEXR file, with 32-bit float channels.
+    ///
+    /// Pixels outside the film are zero. Colors have R, G and B channels in the working color
+    /// space, which the file's chromaticities name, normals and positions X, Y and Z, depth a
+    /// Z channel and identifiers an `id` channel.
+    pub fn write_exr(
+        &self,
+        path: &str,
+        beauty: &Film,
+        aovs: &[Aov],
+        frame: &Tile,
+        space: ColorSpace,
+    ) -> std::io::Result<()> {
+        let size = (frame.width as usize, frame.height as usize);
+        let channel = |name: &str, value: &dyn Fn(i32, i32) -> f64| {
+            let samples = frame.pixels().map(|(i, j)| value(i, j) as f32).collect();
+            AnyChannel::new(name, FlatSamples::F32(samples))
+        };
+        let layer = |name: &str, channels: Vec<AnyChannel<FlatSamples>>| {
+            Layer::new(
+                size,
+                LayerAttributes::named(name),
+                Encoding::SMALL_LOSSLESS,
+                AnyChannels::sort(channels.into()),
+            )
+        };
+        let inside = |i: i32, j: i32| self.area.contains(i, j);
+
+        let mut layers = Vec::new();
+        let beauty_channel = |name, k: usize| {
+            channel(name, &|i, j| {
+                if inside(i, j) {
+                    beauty.color(i, j).0.e[k]
+                } else {
+                    0.0
+                }
+            })
+        };
+        layers.push(layer(
+            "beauty",
+            vec![
+                beauty_channel("R", 0),
+                beauty_channel("G", 1),
+                beauty_channel("B", 2),
+            ],
+        ));
+        for &aov in aovs {
+            let names: &[&str] = match aov {
+                Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+                Aov::Depth => &["Z"],
+                Aov::ObjectId | Aov::MaterialId => &["id"],
+                _ => &["R", "G", "B"],
+            };
+            let channels = names
+                .iter()
+                .enumerate()
+                .map(|(k, name)| {
+                    channel(name, &|i, j| {
+                        if inside(i, j) {
+                            self.pixel(i, j).value(aov).e[k]
+                        } else {
+                            0.0
+                        }
+                    })
+                })
+                .collect();
+            layers.push(layer(aov.name(), channels));
+        }
+
+        let mut attributes = ImageAttributes::new(IntegerBounds::from_dimensions(size));
+        let [red, green, blue, white] = space
+            .chromaticities()
+            .map(|(x, y)| Vec2(x as f32, y as f32));
+        attributes.chromaticities = Some(exr::meta::attribute::Chromaticities {
+            red,
+            green,
+            blue,
+            white,
+        });
+        Image::from_layers(attributes, layers)
+            .write()
+            .to_file(path)
+            .map_err(|err| std::io::Error::other(err.to_string()))
+    }
+}
+
+// Returns a random but fixed color for an identifier.
+fn id_color(id: u32) -> Vec3 {
+    let mut hasher = StableHasher::new();
+    id.hash(&mut hasher);
+    let h = hasher.finish();
+    let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f64 / 255.0;
+    Vec3::new(channel(0), channel(8), channel(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records a first hit with the given normal and material.
+    fn path(normal: Vec3, material: Rc<dyn Material>) -> PathRecord {
+        PathRecord {
+            bounces: 1,
+            first_hit: Some(FirstHit {
+                albedo: Color::new(0.5, 0.5, 0.5),
+                normal,
+                position: Point3::zero(),
+                depth: 1.0,
+                object_id: 0,
+                material: Some(material),
+                specular: false,
+            }),
+        }
+    }
+
+    #[test]
+    fn opposite_normals_average_to_zero() {
+        let material: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut pixel = AovPixel::new();
+        pixel.add(
+            &path(Vec3::new(0.0, 0.0, 1.0), material.clone()),
+            Color::zero(),
+        );
+        pixel.add(&path(Vec3::new(0.0, 0.0, -1.0), material), Color::zero());
+        assert_eq!(pixel.value(Aov::Normal).e, [0.0; 3]);
+    }
+
+    #[test]
+    fn material_ids_depend_on_the_parameters() {
+        let id = |material: &dyn Material| material_id(Some(material));
+        let red = Lambertian::new(Color::new(0.8, 0.1, 0.1));
+        assert_eq!(id(&red), id(&Lambertian::new(Color::new(0.8, 0.1, 0.1))));
+        assert_ne!(id(&red), id(&Lambertian::new(Color::new(0.8, 0.1, 0.2))));
+        assert_ne!(id(&red), id(&Metal::new(Color::new(0.8, 0.1, 0.1), 0.0)));
+        assert_ne!(id(&Dielectric::new(1.5)), id(&Dielectric::new(1.33)));
+    }
+}
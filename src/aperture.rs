@@ -0,0 +1,186 @@
+use crate::{hdr_image::*, rtweekend::*, vec3::*};
+use std::f64::consts::PI;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Shape of the lens opening, which gives out-of-focus highlights (bokeh) their shape.
+///
+/// Shapes fill the unit disk: positions on the aperture run from -1 to 1 across its diameter.
+#[derive(Debug, Clone)]
+pub enum ApertureShape {
+    /// Fully open round aperture.
+    Circle,
+    /// Regular polygon formed by straight diaphragm blades, with a corner at the given angle
+    /// in degrees from the u axis.
+    Polygon { blades: u32, rotation: f64 },
+    /// Opening drawn as an image.
+    Mask(Arc<ApertureMask>),
+}
+
+/// Aperture opening loaded from an image, where pixels brighter than one half are open.
+///
+/// The image is centered on the aperture and scaled so its longer side spans the diameter.
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    // Whether each pixel is open, row by row from the top-left corner.
+    open: Vec<bool>,
+    // Indices of the open pixels, which samples pick from uniformly.
+    open_pixels: Vec<usize>,
+}
+
+impl std::fmt::Debug for ApertureMask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ApertureMask({}x{}, {} open)",
+            self.width,
+            self.height,
+            self.open_pixels.len()
+        )
+    }
+}
+
+impl ApertureMask {
+    /// Creates a mask from an image.
+    pub fn new(image: &HdrImage) -> Self {
+        let open: Vec<bool> = image.pixels.iter().map(|c| c.luminance() > 0.5).collect();
+        let open_pixels = (0..open.len()).filter(|&p| open[p]).collect();
+        ApertureMask {
+            width: image.width,
+            height: image.height,
+            open,
+            open_pixels,
+        }
+    }
+
+    /// Loads a mask from a `.hdr` or `.exr` image.
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let mask = ApertureMask::new(&HdrImage::load(path)?);
+        if mask.open_pixels.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "the aperture image has no open pixels",
+            ));
+        }
+        Ok(mask)
+    }
+
+    // Returns the size of a pixel in aperture units.
+    fn pixel_size(&self) -> f64 {
+        2.0 / self.width.max(self.height) as f64
+    }
+}
+
+impl ApertureShape {
+    /// Returns a random position on the opening, uniformly distributed over its area, using
+    /// exactly two random numbers.
+    pub fn sample(&self, rng: &mut Rng) -> (f64, f64) {
+        match self {
+            ApertureShape::Circle => {
+                let p = Vec3::random_in_unit_disk(rng);
+                (p.x(), p.y())
+            }
+            ApertureShape::Polygon { blades, rotation } => {
+                // Pick one of the triangles between the center and a side, by area
+                let n = (*blades).max(3) as f64;
+                let pick = random_double(rng) * n;
+                let side = pick.floor();
+                let (a, b) = (pick - side, random_double(rng));
+                let start = rotation.to_radians() + 2.0 * PI * side / n;
+                let end = start + 2.0 * PI / n;
+                let (s, t) = (a.sqrt() * (1.0 - b), a.sqrt() * b);
+                (
+                    s * start.cos() + t * end.cos(),
+                    s * start.sin() + t * end.sin(),
+                )
+            }
+            ApertureShape::Mask(mask) => {
+                let pick = random_double(rng) * mask.open_pixels.len() as f64;
+                let index = (pick as usize).min(mask.open_pixels.len() - 1);
+                let pixel = mask.open_pixels[index];
+                let (px, py) = (pixel % mask.width, pixel / mask.width);
+                let size = mask.pixel_size();
+                let x = (px as f64 + pick.fract()) * size - 0.5 * mask.width as f64 * size;
+                let y = (py as f64 + random_double(rng)) * size - 0.5 * mask.height as f64 * size;
+                // Image rows run downwards
+                (x, -y)
+            }
+        }
+    }
+
+    /// Returns whether a position on the aperture is open.
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        match self {
+            ApertureShape::Circle => x * x + y * y <= 1.0,
+            ApertureShape::Polygon { blades, rotation } => {
+                // Distance from the center along the normal of the nearest side
+                let n = (*blades).max(3) as f64;
+                let angle = (y.atan2(x) - rotation.to_radians()).rem_euclid(2.0 * PI);
+                let side = (angle * n / (2.0 * PI)).floor();
+                let normal = rotation.to_radians() + 2.0 * PI * (side + 0.5) / n;
+                x * normal.cos() + y * normal.sin() <= (PI / n).cos()
+            }
+            ApertureShape::Mask(mask) => {
+                let size = mask.pixel_size();
+                let px = (x + 0.5 * mask.width as f64 * size) / size;
+                let py = (-y + 0.5 * mask.height as f64 * size) / size;
+                if px < 0.0 || py < 0.0 {
+                    return false;
+                }
+                let (px, py) = (px as usize, py as usize);
+                px < mask.width && py < mask.height && mask.open[py * mask.width + px]
+            }
+        }
+    }
+}
+
+impl Hash for ApertureShape {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            ApertureShape::Circle => 0u8.hash(state),
+            ApertureShape::Polygon { blades, rotation } => {
+                1u8.hash(state);
+                blades.hash(state);
+                rotation.to_bits().hash(state);
+            }
+            ApertureShape::Mask(mask) => {
+                2u8.hash(state);
+                mask.width.hash(state);
+                mask.height.hash(state);
+                mask.open.hash(state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::*, rtweekend::StableHasher};
+
+    // Returns the stable hash of an aperture shape.
+    fn key(shape: &ApertureShape) -> u64 {
+        let mut hasher = StableHasher::new();
+        shape.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn masks_of_the_same_size_and_openness_hash_differently() {
+        let mut left = HdrImage::new(2, 1);
+        left.pixels[0] = Color::new(1.0, 1.0, 1.0);
+        let mut right = HdrImage::new(2, 1);
+        right.pixels[1] = Color::new(1.0, 1.0, 1.0);
+        let left = ApertureShape::Mask(Arc::new(ApertureMask::new(&left)));
+        let right = ApertureShape::Mask(Arc::new(ApertureMask::new(&right)));
+        assert_eq!(format!("{:?}", left), format!("{:?}", right));
+        assert_ne!(key(&left), key(&right));
+        assert_eq!(key(&left), key(&left.clone()));
+        let polygon = |rotation| ApertureShape::Polygon {
+            blades: 6,
+            rotation,
+        };
+        assert_ne!(key(&polygon(0.0)), key(&polygon(15.0)));
+    }
+}
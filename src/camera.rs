@@ -1,6 +1,14 @@
-use crate::{color::*, hittable::*, interval::*, ray::*, rtweekend::*, vec3::*};
-use std::f64::INFINITY;
-use std::io::Write;
+use crate::{
+    animation::*, aov::*, aperture::*, color::*, denoise::*, display::*, distributed::*,
+    environment::*, film::*, filter::*, hittable::*, interval::*, lens::*, medium::*, physical::*,
+    post::*, progress::*, projection::*, ray::*, render_control::*, rtweekend::*, sampler::*,
+    spectrum::*, stats::*, stereo::*, tile::*, vec3::*,
+};
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// Represents a camera used for rendering scenes.
 pub struct Camera {
@@ -13,12 +21,69 @@ pub struct Camera {
     /// Number of samples per pixel for antialiasing.
     pub samples_per_pixel: i32,
 
+    /// Upper limit of samples per pixel for adaptive sampling; pixels whose relative error is
+    /// still above `adaptive_threshold` after `samples_per_pixel` samples keep sampling up to
+    /// this count. Adaptive sampling is off when it is not above `samples_per_pixel`.
+    pub max_samples_per_pixel: i32,
+
+    /// Relative standard error of a pixel's luminance below which it stops sampling.
+    pub adaptive_threshold: f64,
+
+    /// Path of an optional image showing the number of samples taken in every pixel.
+    pub heatmap: Option<String>,
+
+    /// Path of the checkpoint file written periodically with the accumulated samples.
+    pub checkpoint: Option<String>,
+
+    /// Time between writes of the current image and checkpoint.
+    pub checkpoint_interval: Duration,
+
+    /// Continues from the checkpoint file instead of starting an empty image.
+    pub resume: bool,
+
+    /// Identifies the scene in checkpoints, so a checkpoint is not resumed with another scene.
+    pub scene_hash: u64,
+
+    /// Width and height in pixels of the tiles the image is rendered in.
+    pub tile_size: i32,
+
+    /// Order in which the tiles are rendered in every pass.
+    pub tile_order: TileOrder,
+
+    /// Part of the image to render; the whole image when `None`.
+    pub region: Option<Tile>,
+
+    /// Writes only the region as a smaller image, instead of in place in a black frame.
+    pub crop: bool,
+
+    /// Longest a coordinator waits for a worker's answer before giving its tile to another
+    /// worker.
+    pub worker_timeout: Duration,
+
+    /// Path of the written image, a PNG image when it ends in `.png` and a PPM image
+    /// otherwise. Separate stereo views go to the path with `_left` and `_right` added.
+    pub output: String,
+
     /// Maximum recursion depth for ray tracing.
     pub max_depth: i32,
 
+    /// Mapping from the image to rays into the scene.
+    pub projection: Projection,
+
     /// Vertical field of view in degrees.
     pub vfov: f64,
 
+    /// Height of the view of the orthographic projection in scene units; without it, the
+    /// height the perspective view has at the focus distance, so both frame the focused
+    /// subject alike.
+    pub view_height: Option<f64>,
+
+    /// Full field of view of the fisheye projections in degrees.
+    pub fisheye_fov: f64,
+
+    /// Renders a left and right eye view of `image_width` each instead of a single view.
+    pub stereo: Option<Stereo>,
+
     /// Position of the camera.
     pub lookfrom: Point3,
 
@@ -34,6 +99,66 @@ pub struct Camera {
     /// Focus distance of the camera.
     pub focus_dist: f64,
 
+    /// Real camera settings which, when given, replace `vfov` and `defocus_angle` with the
+    /// ones of the focal length, sensor and aperture, and scale the samples by the exposure
+    /// of the shutter speed and sensitivity. A lens system keeps its own aperture stop.
+    pub physical: Option<PhysicalCamera>,
+
+    /// Shape of the lens opening: of the thin lens, or of the lens system's aperture stop.
+    pub aperture: ApertureShape,
+
+    /// Cat-eye vignetting of the thin lens: away from the image center its opening is cut by
+    /// a circle shifted by this fraction of the aperture radius at the image corners.
+    pub cat_eye: f64,
+
+    /// Lens system traced by the rays of the perspective projection instead of the thin lens,
+    /// focused at the focus distance and keeping the field of view at that distance. Stereo
+    /// views of a lens system look in parallel.
+    pub lens: Option<LensSystem>,
+
+    /// Time in seconds at which the image is taken, placing animated objects.
+    pub time: f64,
+
+    /// Time in seconds the shutter stays open from `time`, over which the samples spread
+    /// for motion blur of animated objects; without it every sample sees the scene at
+    /// `time`. The camera itself stays where it is at `time`.
+    pub shutter: Option<f64>,
+
+    /// Traces one sampled wavelength per path instead of RGB.
+    pub spectral: bool,
+
+    /// Light arriving from rays that miss the scene.
+    pub background: Rc<dyn Environment>,
+
+    /// Seed of the random numbers used for sampling; equal seeds give identical images.
+    pub seed: u64,
+
+    /// Generator of the leading sample dimensions of every pixel sample.
+    pub sampler: SamplerKind,
+
+    /// Output variables written besides the beauty image, taken from the first hit of every
+    /// camera ray. They are not kept in checkpoints, so resumed renders cannot write them.
+    pub aovs: Vec<Aov>,
+
+    /// Path of an OpenEXR file holding the beauty image and the output variables as layers;
+    /// without it every output variable goes to a PPM image named after the beauty image, like
+    /// `output_depth.ppm` for `output.ppm`.
+    pub aov_exr: Option<String>,
+
+    /// Filter weighting samples by their distance from the pixel centers.
+    pub filter: Filter,
+
+    /// Lens and film effects applied to the image before the display transform.
+    pub effects: PostEffects,
+
+    /// Transform from rendered radiance to the values of 8-bit images, whose working color
+    /// space is also the one rendering multiplies colors in.
+    pub display: DisplayTransform,
+
+    /// Filters the noise out of the written image, guided by the output variables. The
+    /// checkpoints and the EXR beauty layer keep the unfiltered samples.
+    pub denoiser: Option<Denoiser>,
+
     // Private fields:
     /// Height of the image produced by the camera.
     image_height: i32,
@@ -64,6 +189,13 @@ pub struct Camera {
 
     /// Offset of the defocus disk along the v-axis.
     defocus_disk_v: Vec3,
+
+    /// Width and height of the film behind the lens system, in millimetres.
+    lens_film: (f64, f64),
+
+    /// Scale of every sample: the physical camera's exposure, and making up for the rays the
+    /// lens system blocks in the image center.
+    ray_weight: f64,
 }
 
 impl Camera {
@@ -74,10 +206,46 @@ impl Camera {
             aspect_ratio: 1.0,
             image_width: 100,
             samples_per_pixel: 10,
+            max_samples_per_pixel: 0,
+            adaptive_threshold: 0.05,
+            heatmap: None,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+            resume: false,
+            scene_hash: 0,
+            tile_size: 32,
+            tile_order: TileOrder::Scanline,
+            region: None,
+            crop: false,
+            worker_timeout: Duration::from_secs(300),
+            output: "output.ppm".to_string(),
             max_depth: 10,
+            projection: Projection::Perspective,
             vfov: 90.0,
+            view_height: None,
+            fisheye_fov: 180.0,
+            stereo: None,
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            physical: None,
+            aperture: ApertureShape::Circle,
+            cat_eye: 0.0,
+            lens: None,
+            time: 0.0,
+            shutter: None,
+            spectral: false,
+            seed: 0,
+            sampler: SamplerKind::Independent,
+            aovs: Vec::new(),
+            aov_exr: None,
+            filter: Filter::default(),
+            effects: PostEffects::default(),
+            display: DisplayTransform::default(),
+            denoiser: None,
+            background: Rc::new(Gradient::new(
+                Color::new(1.0, 1.0, 1.0),
+                Color::new(0.5, 0.7, 1.0),
+            )),
             lookfrom: Point3::new(0.0, 0.0, -1.0),
             lookat: Point3::new(0.0, 0.0, 0.0),
             vup: Vec3::new(0.0, 1.0, 0.0),
@@ -93,7 +261,96 @@ impl Camera {
             w: Vec3::new(0.0, 0.0, 0.0),
             defocus_disk_u: Vec3::new(0.0, 0.0, 0.0),
             defocus_disk_v: Vec3::new(0.0, 0.0, 0.0),
+            lens_film: (0.0, 0.0),
+            ray_weight: 1.0,
+        }
+    }
+
+    /// Moves the camera to `time` of an animation, setting the parameters it has keys for.
+    pub fn animate(&mut self, animation: &Animation, time: f64) {
+        let interpolation = animation.interpolation;
+        self.time = time;
+        if let Some(lookfrom) = animation.lookfrom.value_at(time, interpolation) {
+            self.lookfrom = lookfrom;
+        }
+        if let Some(lookat) = animation.lookat.value_at(time, interpolation) {
+            self.lookat = lookat;
+        }
+        if let Some(vfov) = animation.vfov.value_at(time, interpolation) {
+            // A physical camera derives the field of view, so the keys zoom its lens
+            match &mut self.physical {
+                Some(physical) => {
+                    physical.focal_length = physical.focal_length_for(vfov, self.aspect_ratio)
+                }
+                None => self.vfov = vfov,
+            }
         }
+        if let Some(focus_dist) = animation.focus_dist.value_at(time, interpolation) {
+            self.focus_dist = focus_dist;
+        }
+    }
+
+    /// Returns the focus distance that puts `point` in focus: its distance from the camera
+    /// along the view direction, or `None` when the point is not in front of the camera.
+    pub fn focus_distance_to(&self, point: Point3) -> Option<f64> {
+        let distance = Vec3::dot(
+            &(point - self.lookfrom),
+            &Vec3::unit_vector(self.lookat - self.lookfrom),
+        );
+        (distance > 0.0).then_some(distance)
+    }
+
+    /// Returns the focus distance that puts in focus what the center of pixel (`i`, `j`) of the
+    /// view sees, or of the view's center pixel without one, or `None` when the ray through it
+    /// leaves the scene.
+    ///
+    /// The ray leaves the middle of the lens, so it sees what the pixel shows in focus.
+    pub fn autofocus(&mut self, world: &dyn Hittable, pixel: Option<(i32, i32)>) -> Option<f64> {
+        // The lens system would refocus, and only the view geometry is needed
+        let lens = self.lens.take();
+        self.initialize();
+        self.lens = lens;
+
+        let (i, j) = pixel.unwrap_or((self.image_width / 2, self.image_height / 2));
+        if i < 0 || j < 0 || i >= self.image_width || j >= self.image_height {
+            return None;
+        }
+        let r = if self.projection.is_panoramic() {
+            let position = (
+                (i as f64 + 0.5) / self.image_width as f64,
+                (j as f64 + 0.5) / self.image_height as f64,
+            );
+            let aspect_ratio = self.image_width as f64 / self.image_height as f64;
+            let d =
+                self.projection
+                    .panoramic_direction(position, aspect_ratio, self.fisheye_fov)?;
+            Ray::new(
+                self.center,
+                self.u * d.x() + self.v * d.y() + self.w * d.z(),
+            )
+        } else {
+            let pixel_center = self.pixel00_loc
+                + (self.pixel_delta_u * i as f64)
+                + (self.pixel_delta_v * j as f64);
+            let origin = match self.projection {
+                Projection::Orthographic => pixel_center + self.w * self.focus_dist,
+                _ => self.center,
+            };
+            Ray::new(origin, pixel_center - origin)
+        };
+        let r = r.with_time(self.time);
+
+        let mut rec = HitRecord::default();
+        if !world.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            return None;
+        }
+        // Panoramas focus on spheres around the camera, planar views on planes facing it
+        let distance = if self.projection.is_panoramic() {
+            (rec.p - self.center).length()
+        } else {
+            Vec3::dot(&(rec.p - self.center), &-self.w)
+        };
+        Some(distance)
     }
 
     /// Initializes camera parameters based on aspect ratio and field of view.
@@ -107,9 +364,20 @@ impl Camera {
 
         self.center = self.lookfrom;
 
+        self.ray_weight = 1.0;
+        if let Some(physical) = &self.physical {
+            let aspect_ratio = self.image_width as f64 / self.image_height as f64;
+            self.vfov = physical.vfov(aspect_ratio);
+            self.defocus_angle = physical.defocus_angle(self.focus_dist);
+            self.ray_weight = physical.exposure_scale();
+        }
+
         let theta: f64 = degrees_to_radians(self.vfov);
         let h: f64 = (theta / 2.0).tan();
-        let viewport_height: f64 = 2.0 * h * self.focus_dist;
+        let viewport_height: f64 = match (self.projection, self.view_height) {
+            (Projection::Orthographic, Some(view_height)) => view_height,
+            _ => 2.0 * h * self.focus_dist,
+        };
         let viewport_width: f64 =
             viewport_height * (self.image_width as f64 / self.image_height as f64);
 
@@ -130,100 +398,1229 @@ impl Camera {
         let defocus_radius = self.focus_dist * (degrees_to_radians(self.defocus_angle / 2.0)).tan();
         self.defocus_disk_u = self.u * defocus_radius;
         self.defocus_disk_v = self.v * defocus_radius;
+
+        if let Some(lens) = &mut self.lens {
+            // Scene units are metres and lens units millimetres
+            let image_distance = lens.focus(1000.0 * self.focus_dist).unwrap_or_else(|| {
+                eprintln!(
+                    "warning: the lens system cannot focus at {} m",
+                    self.focus_dist
+                );
+                lens.rear_z()
+            });
+            let film_height = 2.0 * h * image_distance;
+            let aspect_ratio = self.image_width as f64 / self.image_height as f64;
+            self.lens_film = (film_height * aspect_ratio, film_height);
+
+            // Brighten the image by the share of rays blocked in its center
+            let (mut inside, mut passed) = (0, 0);
+            let n = 64;
+            for y in 0..n {
+                for x in 0..n {
+                    let px = 2.0 * (x as f64 + 0.5) / n as f64 - 1.0;
+                    let py = 2.0 * (y as f64 + 0.5) / n as f64 - 1.0;
+                    if px * px + py * py > 1.0 {
+                        continue;
+                    }
+                    inside += 1;
+                    let r = lens.rear_radius();
+                    let rear = Point3::new(px * r, py * r, lens.rear_z());
+                    if lens
+                        .trace_from_film(Point3::zero(), rear, &self.aperture)
+                        .is_some()
+                    {
+                        passed += 1;
+                    }
+                }
+            }
+            if passed > 0 {
+                self.ray_weight *= inside as f64 / passed as f64;
+            }
+        }
     }
 
-    /// Generates a ray corresponding to a pixel in the image.
-    fn get_ray(&mut self, i: i32, j: i32) -> Ray {
+    /// Generates a ray through a pixel in the image, at an offset in pixels from its center,
+    /// or `None` where the projection does not cover the image.
+    fn get_ray(&self, i: i32, j: i32, (px, py): (f64, f64), rng: &mut Rng) -> Option<Ray> {
+        // Pixel within its view, and how far the view's eye sits along u
+        let (i, j, eye_offset) = match &self.stereo {
+            Some(stereo) => {
+                let size = (self.image_width, self.image_height);
+                let eye = stereo.eye_at((i, j), size);
+                let view = stereo.view_area(eye, size);
+                let offset = match eye {
+                    Eye::Left => -0.5 * stereo.interocular,
+                    Eye::Right => 0.5 * stereo.interocular,
+                };
+                (i - view.x, j - view.y, offset)
+            }
+            None => (i, j, 0.0),
+        };
+
+        // Position in the view, from 0 to 1 across and down
+        let position = (
+            (i as f64 + 0.5 + px) / self.image_width as f64,
+            (j as f64 + 0.5 + py) / self.image_height as f64,
+        );
+        if self.projection.is_panoramic() {
+            let aspect_ratio = self.image_width as f64 / self.image_height as f64;
+            let d =
+                self.projection
+                    .panoramic_direction(position, aspect_ratio, self.fisheye_fov)?;
+            let direction = self.u * d.x() + self.v * d.y() + self.w * d.z();
+            if eye_offset == 0.0 {
+                return Some(Ray::new(self.center, direction));
+            }
+            // Omni-directional stereo: the eyes sit on a circle, beside the camera position
+            // as seen from every direction, and shrink towards the camera position looking
+            // straight up or down
+            let horizontal = direction - self.v * Vec3::dot(&direction, &self.v);
+            let origin = self.center + Vec3::cross(&horizontal, &self.v) * eye_offset;
+            let target = self.center + direction * self.convergence();
+            return Some(Ray::new(origin, target - origin));
+        }
+
+        if let (Some(lens), Projection::Perspective) = (&self.lens, self.projection) {
+            return self.lens_ray(lens, position, eye_offset, rng);
+        }
+
         // Compute pixel location in world space
         let pixel_center =
             self.pixel00_loc + (self.pixel_delta_u * i as f64) + (self.pixel_delta_v * j as f64);
-        let pixel_sample = pixel_center + Camera::pixel_sample_square(&self);
+        let pixel_sample = pixel_center + (self.pixel_delta_u * px) + (self.pixel_delta_v * py);
 
-        // Compute ray origin and direction
+        // Compute ray origin and direction; orthographic rays start from the point of the
+        // camera plane behind the pixel instead of the camera position
+        let lens_center = match self.projection {
+            Projection::Orthographic => pixel_sample + self.w * self.focus_dist,
+            _ => self.center,
+        };
+        let (lens_center, pixel_sample) = if eye_offset == 0.0 {
+            (lens_center, pixel_sample)
+        } else {
+            self.eye_view(eye_offset, lens_center, pixel_sample)
+        };
         let ray_origin = if self.defocus_angle <= 0.0 {
-            self.center
+            lens_center
         } else {
-            Camera::defocus_disk_sample(self)
+            self.defocus_disk_sample(lens_center, position, rng)?
         };
         let ray_direction = pixel_sample - ray_origin;
 
-        Ray::new(ray_origin, ray_direction)
+        Some(Ray::new(ray_origin, ray_direction))
+    }
+
+    /// Moves a ray from `lens_center` through `pixel_sample` on the focus plane to the eye
+    /// `eye_offset` along u, returning the eye's lens center and point on the focus plane.
+    fn eye_view(
+        &self,
+        eye_offset: f64,
+        lens_center: Point3,
+        pixel_sample: Point3,
+    ) -> (Point3, Point3) {
+        let eye = lens_center + self.u * eye_offset;
+        let convergence = self.convergence();
+        let direction = match self.stereo.map(|stereo| stereo.mode) {
+            Some(StereoMode::ToeIn) => {
+                // Turn the ray about v, as the eye turns to the convergence point
+                let angle = (eye_offset / convergence).atan();
+                let d = pixel_sample - lens_center;
+                let v_cross_d = Vec3::cross(&self.v, &d);
+                let along_v = self.v * Vec3::dot(&self.v, &d);
+                d * angle.cos() + v_cross_d * angle.sin() + along_v * (1.0 - angle.cos())
+            }
+            _ => {
+                // Aim through the mono ray's point at the convergence distance, then stop at
+                // the focus distance
+                let target =
+                    lens_center + (pixel_sample - lens_center) * (convergence / self.focus_dist);
+                (target - eye) * (self.focus_dist / convergence)
+            }
+        };
+        (eye, eye + direction)
+    }
+
+    /// Returns the distance at which the stereo views line up.
+    fn convergence(&self) -> f64 {
+        self.stereo
+            .and_then(|stereo| stereo.convergence)
+            .unwrap_or(self.focus_dist)
+    }
+
+    /// Returns the whole image: one view, or both views of a stereo pair.
+    fn frame(&self) -> Tile {
+        let size = (self.image_width, self.image_height);
+        let (width, height) = match &self.stereo {
+            Some(stereo) => stereo.frame_size(size),
+            None => size,
+        };
+        Tile::new(0, 0, width, height)
+    }
+
+    /// Returns the view holding pixel (`i`, `j`), which samples taken in the pixel stay in.
+    fn view_of(&self, i: i32, j: i32) -> Tile {
+        match &self.stereo {
+            Some(stereo) => {
+                let size = (self.image_width, self.image_height);
+                stereo.view_area(stereo.eye_at((i, j), size), size)
+            }
+            None => self.frame(),
+        }
+    }
+
+    /// Computes a sample point on the defocus disk around `lens_center`, for a ray through
+    /// `position` in the view, or `None` where cat-eye vignetting blocks it.
+    fn defocus_disk_sample(
+        &self,
+        lens_center: Point3,
+        (x, y): (f64, f64),
+        rng: &mut Rng,
+    ) -> Option<Point3> {
+        let (px, py) = self.aperture.sample(rng);
+        if self.cat_eye > 0.0 {
+            // Offset from the image center, one at the corners
+            let (width, height) = (self.image_width as f64, self.image_height as f64);
+            let half_diagonal = 0.5 * (width * width + height * height).sqrt();
+            let fx = (x - 0.5) * width / half_diagonal;
+            let fy = (0.5 - y) * height / half_diagonal;
+            let (dx, dy) = (px - self.cat_eye * fx, py - self.cat_eye * fy);
+            if dx * dx + dy * dy > 1.0 {
+                return None;
+            }
+        }
+        Some(lens_center + (self.defocus_disk_u * px) + (self.defocus_disk_v * py))
     }
 
-    /// Computes a sample point on the defocus disk.
-    fn defocus_disk_sample(&self) -> Point3 {
-        let p = Vec3::random_in_unit_disk();
-        self.center + (self.defocus_disk_u * p.e[0]) + (self.defocus_disk_v * p.e[1])
+    /// Generates a ray through the lens system from `position` in the view, from 0 to 1
+    /// across and down, or `None` when the lens blocks it.
+    fn lens_ray(
+        &self,
+        lens: &LensSystem,
+        (x, y): (f64, f64),
+        eye_offset: f64,
+        rng: &mut Rng,
+    ) -> Option<Ray> {
+        // The lens turns the image upside down, so the film is too
+        let (width, height) = self.lens_film;
+        let film = Point3::new(-(x - 0.5) * width, (y - 0.5) * height, 0.0);
+        let p = Vec3::random_in_unit_disk(rng);
+        let r = lens.rear_radius();
+        let rear = Point3::new(p.x() * r, p.y() * r, lens.rear_z());
+        let (origin, direction) = lens.trace_from_film(film, rear - film, &self.aperture)?;
+
+        // Lens space along u, v and -w, from millimetres to metres, with the film at the
+        // camera position
+        let origin = self.center
+            + self.u * (eye_offset + 0.001 * origin.x())
+            + self.v * (0.001 * origin.y())
+            - self.w * (0.001 * origin.z());
+        let direction = self.u * direction.x() + self.v * direction.y() - self.w * direction.z();
+        Some(Ray::new(origin, direction))
     }
 
-    /// Computes a sample vector within the pixel's area.
-    fn pixel_sample_square(&self) -> Vec3 {
-        let px = -0.5 + random_double();
-        let py = -0.5 + random_double();
-        (self.pixel_delta_u * px) + (self.pixel_delta_v * py)
+    /// Computes a sample offset in pixels from the pixel's center, within the pixel's area.
+    fn pixel_sample_square(&self, rng: &mut Rng) -> (f64, f64) {
+        let px = -0.5 + random_double(rng);
+        let py = -0.5 + random_double(rng);
+        (px, py)
     }
 
     /// Computes the color of a ray after intersection with the scene.
-    fn ray_color(r: &Ray, depth: i32, world: &dyn Hittable) -> Color {
+    ///
+    /// The path's bounces and first hit are recorded in `path`, for the output variables.
+    fn ray_color(
+        &self,
+        r: &Ray,
+        depth: i32,
+        world: &dyn Hittable,
+        rng: &mut Rng,
+        path: &mut PathRecord,
+    ) -> Color {
         // Base case: maximum recursion depth reached
         if depth <= 0 {
             return Color::new(0.0, 0.0, 0.0);
         }
+        RAYS_TRACED.increment();
+        // This bounce's block of sampler dimensions
+        let bounce = BOUNCE_DIMENSION + (self.max_depth - depth) as usize * DIMENSIONS_PER_BOUNCE;
 
         // Check for intersection with scene
         let mut rec = HitRecord::default();
-        if world.hit(r, Interval::new(0.001, INFINITY), &mut rec) {
+        if world.hit(r, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            path.bounces += 1;
+            // Only the output variables need the first hit
+            if path.first_hit.is_none() && self.wants_aovs() {
+                path.first_hit = Some(FirstHit {
+                    albedo: Color::new(0.9, 0.9, 0.9),
+                    normal: rec.normal,
+                    position: rec.p,
+                    depth: rec.t * r.direction().length(),
+                    object_id: rec.object_id,
+                    material: rec.mat.clone(),
+                    specular: false,
+                });
+            }
+
+            // Rays leaving a closed object through its back face travelled through its interior
+            let mut transmittance = Color::new(1.0, 1.0, 1.0);
+            let interior = rec.mat.as_ref().and_then(|m| m.interior());
+            if let Some(medium) = interior.filter(|_| !rec.front_face) {
+                rng.start_dimensions(bounce, 2);
+                match medium.sample(r, rec.t, rng) {
+                    FreeFlight::Scatter { t, weight } => {
+                        // Isotropic scattering inside the medium
+                        rng.start_dimensions(bounce + 2, 2);
+                        let scattered = Ray::new(r.at(t), Vec3::random_unit_vector(rng))
+                            .with_wavelength(r.wavelength())
+                            .with_time(r.time());
+                        let weight = self.working_color(weight, r.wavelength());
+                        return weight * self.ray_color(&scattered, depth - 1, world, rng, path);
+                    }
+                    FreeFlight::Escape { weight } => {
+                        transmittance = self.working_color(weight, r.wavelength())
+                    }
+                }
+            }
+
             if let Some(material) = &rec.mat {
                 let mut scattered = Ray::default();
                 let mut attenuation = Color::zero();
                 // Scatter ray with material properties
-                if material.scatter(r, &rec, &mut attenuation, &mut scattered) {
-                    return attenuation * Camera::ray_color(&scattered, depth - 1, world);
+                rng.start_dimensions(bounce + 2, 2);
+                if material.scatter(r, &rec, &mut attenuation, &mut scattered, rng) {
+                    attenuation = self.working_color(attenuation, r.wavelength());
+                    // Diffuse bounces may aim at the environment instead, weighting the result
+                    // by the mixture of both sampling densities
+                    let scattering_pdf = material.scattering_pdf(r, &rec, &scattered);
+                    if path.bounces == 1 {
+                        if let Some(first) = &mut path.first_hit {
+                            first.albedo = attenuation;
+                            first.specular = scattering_pdf <= 0.0;
+                        }
+                    }
+                    if scattering_pdf > 0.0 && self.background.is_sampled() {
+                        rng.start_dimensions(bounce + 4, 1);
+                        if random_double(rng) < 0.5 {
+                            rng.start_dimensions(bounce + 5, 2);
+                            scattered = Ray::new(rec.p, self.background.sample_direction(rng));
+                        }
+                        let scattering_pdf = material.scattering_pdf(r, &rec, &scattered);
+                        let light_pdf = self.background.pdf_value(&scattered.direction());
+                        let mixture_pdf = 0.5 * scattering_pdf + 0.5 * light_pdf;
+                        attenuation = attenuation * (scattering_pdf / mixture_pdf);
+                    }
+
+                    // Spectral rays keep their wavelength and see the albedo as a spectrum, and
+                    // all rays keep their time
+                    let scattered = scattered
+                        .with_wavelength(r.wavelength())
+                        .with_time(r.time());
+                    let attenuation = spectral_sample(attenuation, r.wavelength());
+                    return transmittance
+                        * attenuation
+                        * self.ray_color(&scattered, depth - 1, world, rng, path);
                 }
             }
 
             // Compute scattered ray direction
-            let direction = rec.normal + Vec3::random_unit_vector();
+            rng.start_dimensions(bounce + 2, 2);
+            let direction = rec.normal + Vec3::random_unit_vector(rng);
+            let scattered = Ray::new(rec.p, direction)
+                .with_wavelength(r.wavelength())
+                .with_time(r.time());
             // Recursive ray tracing
-            return Camera::ray_color(&Ray::new(rec.p, direction), depth - 1, world) * 0.9;
+            return self.ray_color(&scattered, depth - 1, world, rng, path) * 0.9;
         }
 
         // Background color for rays that miss the scene
-        let unit_direction = Vec3::unit_vector(r.direction());
-        let a = 0.5 * (unit_direction.y() + 1.0);
-        Color::new(1.0, 1.0, 1.0) * (1.0 - a) + (Color::new(0.5, 0.7, 1.0) * a)
+        let background = self.background.radiance(&r.direction());
+        self.working_color(spectral_sample(background, r.wavelength()), r.wavelength())
+    }
+
+    /// Computes the linear RGB contribution of one camera ray sample, and the record of its
+    /// path.
+    fn sample_color(&self, r: &Ray, world: &dyn Hittable, rng: &mut Rng) -> (Color, PathRecord) {
+        let mut path = PathRecord::new();
+        if !self.spectral {
+            let color = self.ray_color(r, self.max_depth, world, rng, &mut path);
+            return (color, path);
+        }
+
+        // Trace a single wavelength and project it back to RGB
+        rng.start_dimensions(WAVELENGTH_DIMENSION, 1);
+        let lambda = sample_wavelength(rng);
+        let r = r.with_wavelength(Some(lambda));
+        let radiance = self.ray_color(&r, self.max_depth, world, rng, &mut path);
+        let rgb = wavelength_to_rgb(radiance.0.x(), lambda);
+        (self.display.working_space.convert_from_srgb(rgb), path)
+    }
+
+    /// Converts a scene color, given in linear sRGB, to the working color space. Colors on
+    /// spectral rays hold the value at one wavelength and are left alone.
+    fn working_color(&self, color: Color, wavelength: Option<f64>) -> Color {
+        match wavelength {
+            Some(_) => color,
+            None => self.display.working_space.convert_from_srgb(color),
+        }
+    }
+
+    /// Takes `count` more samples of a pixel, continuing its sample sequence, and adds them to
+    /// the output variables too when given.
+    ///
+    /// With a reconstruction filter wider than the pixel, the samples are also splatted into
+    /// the neighbouring pixels of the film's filtered image.
+    fn sample_pixel(
+        &self,
+        film: &mut Film,
+        mut aov_film: Option<&mut AovFilm>,
+        sampler: &dyn Sampler,
+        world: &dyn Hittable,
+        (i, j): (i32, i32),
+        count: u32,
+    ) {
+        // Each sample has its own random sequence so the result does not depend on
+        // rendering order
+        let pixel = (j * self.frame().width + i) as u64;
+        let first = film.pixel(i, j).count;
+        for s in first..first + count {
+            let mut rng = Rng::for_sample(self.seed, pixel, s as u64)
+                .with_samples(sampler.pixel_sample(i, j, s));
+            rng.start_dimensions(PIXEL_DIMENSION, 2);
+            let offset = self.pixel_sample_square(&mut rng);
+            rng.start_dimensions(LENS_DIMENSION, 2);
+            // Samples outside the projection are black but still count
+            let (color, path) = match self.get_ray(i, j, offset, &mut rng) {
+                Some(r) => {
+                    // Moments the shutter is open, for motion blur
+                    rng.start_dimensions(TIME_DIMENSION, 1);
+                    let time = match self.shutter {
+                        Some(shutter) => self.time + shutter * random_double(&mut rng),
+                        None => self.time,
+                    };
+                    let r = r.with_time(time);
+                    CAMERA_RAYS.increment();
+                    let (color, path) = self.sample_color(&r, world, &mut rng);
+                    (color * self.ray_weight, path)
+                }
+                None => (Color::zero(), PathRecord::new()),
+            };
+            film.pixel_mut(i, j).add(color);
+            if let Some(splats) = &mut film.splats {
+                let position = (i as f64 + offset.0, j as f64 + offset.1);
+                splats.add_sample(&self.filter, position, color, &self.view_of(i, j));
+            }
+            if let Some(aov_film) = aov_film.as_deref_mut() {
+                aov_film.pixel_mut(i, j).add(&path, color);
+            }
+        }
+    }
+
+    /// Returns the key identifying this scene and camera, which workers must share with their
+    /// coordinator.
+    ///
+    /// The sample counts and image quality settings are left out, so a render can be resumed
+    /// with more samples or a different time budget.
+    fn camera_key(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        self.scene_hash.hash(&mut hasher);
+        self.seed.hash(&mut hasher);
+        self.sampler.hash(&mut hasher);
+        self.display.working_space.hash(&mut hasher);
+        self.filter.hash(&mut hasher);
+        self.projection.hash(&mut hasher);
+        self.view_height.map(f64::to_bits).hash(&mut hasher);
+        self.fisheye_fov.to_bits().hash(&mut hasher);
+        self.stereo.hash(&mut hasher);
+        self.aperture.hash(&mut hasher);
+        self.lens.hash(&mut hasher);
+        self.physical.hash(&mut hasher);
+        self.cat_eye.to_bits().hash(&mut hasher);
+        self.time.to_bits().hash(&mut hasher);
+        self.shutter.map(f64::to_bits).hash(&mut hasher);
+        self.spectral.hash(&mut hasher);
+        self.max_depth.hash(&mut hasher);
+        let vectors = [self.lookfrom, self.lookat, self.vup];
+        let scalars = [
+            self.aspect_ratio,
+            self.vfov,
+            self.defocus_angle,
+            self.focus_dist,
+        ];
+        for value in vectors.iter().flat_map(|v| v.e).chain(scalars) {
+            value.to_bits().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Returns the key identifying checkpoints of this scene and camera, rendered with
+    /// `base_samples` per pixel before adaptive sampling.
+    ///
+    /// The stratified sampler lays out its strata for the base samples, so with it they count
+    /// too; other samplers can resume with more samples.
+    fn checkpoint_key(&self, base_samples: u32) -> u64 {
+        let mut hasher = StableHasher::new();
+        self.camera_key().hash(&mut hasher);
+        if self.sampler == SamplerKind::Stratified {
+            base_samples.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Returns the number of samples a pixel takes in the next pass, or zero once it is done.
+    ///
+    /// Pixels take one sample per pass until `samples_per_pixel`, so the whole image improves
+    /// evenly. Adaptive sampling then doubles the samples of pixels that have not converged.
+    fn pass_samples(&self, stats: &PixelStats, base_samples: u32, max_samples: u32) -> u32 {
+        if stats.count < base_samples {
+            1
+        } else if stats.count >= max_samples || stats.relative_error() <= self.adaptive_threshold {
+            0
+        } else {
+            stats.count.min(max_samples - stats.count)
+        }
+    }
+
+    /// Estimates the fraction of the render done from the samples taken, the time spent and
+    /// the noise reached, whichever is furthest along.
+    fn fraction_done(
+        &self,
+        control: &RenderControl,
+        start: Instant,
+        samples: f64,
+        noise: f64,
+    ) -> f64 {
+        let mut progress = samples;
+        if let Some(deadline) = control.deadline() {
+            let total = deadline.saturating_duration_since(start).as_secs_f64();
+            progress = progress.max(start.elapsed().as_secs_f64() / total.max(1e-9));
+        }
+        if let Some(target) = control.noise_target() {
+            // The error falls with the square root of the samples taken
+            progress = progress.max((target / noise).powi(2));
+        }
+        progress
+    }
+
+    /// Returns the part of the frame written to images: the region when cropping, otherwise
+    /// the whole frame.
+    fn output_frame(&self, film: &Film) -> Tile {
+        if self.crop {
+            film.area
+        } else {
+            self.frame()
+        }
+    }
+
+    /// Writes the current image, denoised and with effects when enabled, and the checkpoint
+    /// when enabled.
+    fn write_progress(
+        &self,
+        film: &Film,
+        aov_film: Option<&AovFilm>,
+        key: u64,
+    ) -> std::io::Result<()> {
+        // Radiance of the film's pixels, denoised when enabled, then through the lens and
+        // film effects and the display transform. The views of a stereo frame are separate
+        // pictures, which must not blur into each other.
+        let area = film.area;
+        let views = match &self.stereo {
+            Some(stereo) => {
+                let size = (self.image_width, self.image_height);
+                vec![
+                    stereo.view_area(Eye::Left, size),
+                    stereo.view_area(Eye::Right, size),
+                ]
+            }
+            None => vec![self.frame()],
+        };
+        let mut colors = vec![Color::zero(); area.pixels().count()];
+        for view in views {
+            let Some(part) = area.intersect(&view) else {
+                continue;
+            };
+            let part_colors = self.view_colors(film, aov_film, part, view);
+            for ((i, j), color) in part.pixels().zip(part_colors) {
+                colors[((j - area.y) * area.width + (i - area.x)) as usize] = color;
+            }
+        }
+        let color = |i: i32, j: i32| {
+            self.display
+                .apply(colors[((j - area.y) * area.width + (i - area.x)) as usize])
+        };
+        match &self.stereo {
+            Some(stereo) if stereo.layout == StereoLayout::Separate => {
+                let size = (self.image_width, self.image_height);
+                // The eye goes before the extension
+                let (stem, extension) = split_extension(&self.output);
+                for (eye, name) in [(Eye::Left, "left"), (Eye::Right, "right")] {
+                    let view = stereo.view_area(eye, size);
+                    if let Some(frame) = self.output_frame(film).intersect(&view) {
+                        let path = format!("{}_{}{}", stem, name, extension);
+                        write_frame_image(&path, &frame, &area, color)?;
+                    }
+                }
+            }
+            _ => write_frame_image(&self.output, &self.output_frame(film), &area, color)?,
+        }
+        if let Some(path) = &self.checkpoint {
+            film.write_checkpoint(path, key)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the radiance of the pixels in `part` of the picture `view`, in row-major order,
+    /// denoised when enabled and through the lens and film effects.
+    fn view_colors(
+        &self,
+        film: &Film,
+        aov_film: Option<&AovFilm>,
+        part: Tile,
+        view: Tile,
+    ) -> Vec<Color> {
+        let mut colors: Vec<Color> = match (&self.denoiser, aov_film) {
+            (Some(denoiser), Some(aov_film)) => {
+                let start = Instant::now();
+                let colors = denoiser.denoise(film, aov_film, part);
+                record_phase("Denoising", start.elapsed());
+                colors
+            }
+            _ => part.pixels().map(|(i, j)| film.color(i, j)).collect(),
+        };
+        if self.effects.is_active() {
+            // Effects place themselves relative to the view
+            let start = Instant::now();
+            let within = Tile::new(part.x - view.x, part.y - view.y, part.width, part.height);
+            let size = (view.width, view.height);
+            self.effects.apply(&mut colors, within, size, self.seed);
+            record_phase("Post effects", start.elapsed());
+        }
+        colors
+    }
+
+    /// Returns the samples every pixel takes and the upper limit for adaptive sampling.
+    ///
+    /// With a noise target the passes continue until the target is met, so only an explicit
+    /// maximum limits the samples.
+    fn sample_counts(&self, noise_target: Option<f64>) -> (u32, u32) {
+        let base_samples = match noise_target {
+            Some(_) if self.max_samples_per_pixel > 0 => self.max_samples_per_pixel as u32,
+            Some(_) => u32::MAX,
+            None => self.samples_per_pixel.max(1) as u32,
+        };
+        let max_samples = (self.max_samples_per_pixel.max(0) as u32).max(base_samples);
+        (base_samples, max_samples)
+    }
+
+    /// Returns the part of the frame to render: the region of interest or the whole frame.
+    fn render_area(&self) -> std::io::Result<Tile> {
+        let frame = self.frame();
+        match self.region {
+            Some(region) => region.intersect(&frame).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "region lies outside the image",
+                )
+            }),
+            None => Ok(frame),
+        }
+    }
+
+    /// Returns the film to accumulate into: the checkpoint when resuming, otherwise empty.
+    fn start_film(&self, key: u64, area: Tile) -> std::io::Result<Film> {
+        match (&self.checkpoint, self.resume) {
+            (Some(path), true) => Film::read_checkpoint(path, key, area),
+            _ => Ok(self.empty_film(area, area)),
+        }
+    }
+
+    /// Returns an empty film for sampling `area`, splatting into `splat_area` when the filter
+    /// reaches beyond the pixels.
+    fn empty_film(&self, area: Tile, splat_area: Tile) -> Film {
+        let film = Film::new(area);
+        if self.filter.splats() {
+            film.with_splats(splat_area)
+        } else {
+            film
+        }
     }
 
     /// Renders the scene from the camera's perspective.
-    pub fn render(mut self, world: &dyn Hittable) -> std::io::Result<()> {
+    ///
+    /// Samples accumulate in passes over the image. The current image and checkpoint are
+    /// written every `checkpoint_interval`. Rendering stops early when `control` is cancelled
+    /// or its deadline passes, and with a noise target it runs until the target is met.
+    pub fn render(&mut self, world: &dyn Hittable, control: &RenderControl) -> std::io::Result<()> {
         // Initialize camera parameters
+        reset_counters();
+        let setup_start = Instant::now();
+        Camera::initialize(self);
+        let noise_target = control.noise_target();
+        let (base_samples, max_samples) = self.sample_counts(noise_target);
+        let sampler = self.sampler.create(base_samples, self.seed);
+        let key = self.checkpoint_key(base_samples);
+        let area = self.render_area()?;
+        let tiles = self.tile_order.tiles(area, self.tile_size);
+        let mut film = self.start_film(key, area)?;
+        let mut aov_film = self.wants_aovs().then(|| AovFilm::new(area));
+        record_phase("Setup", setup_start.elapsed());
+
+        let start = Instant::now();
+        let rays_at_start = RAYS_TRACED.get();
+        let mut writing = Duration::ZERO;
+        let mut last_write = start;
+        let planned = film.pixels.len() as f64 * base_samples as f64;
+        let mut done: f64 = film
+            .pixels
+            .iter()
+            .map(|p| p.count.min(base_samples) as f64)
+            .sum();
+        let mut noise = film.mean_relative_error();
+        let mut pass = 0;
+        let mut stop_reason = None;
+        'passes: loop {
+            pass += 1;
+            let mut active = 0;
+            for tile in &tiles {
+                for (i, j) in tile.pixels() {
+                    let stats = film.pixel(i, j);
+                    let count = self.pass_samples(stats, base_samples, max_samples);
+                    if count > 0 {
+                        done += count.min(base_samples.saturating_sub(stats.count)) as f64;
+                        self.sample_pixel(
+                            &mut film,
+                            aov_film.as_mut(),
+                            sampler.as_ref(),
+                            world,
+                            (i, j),
+                            count,
+                        );
+                        active += 1;
+                    }
+                }
+                let fraction = self.fraction_done(control, start, done / planned, noise);
+                control.report(Progress {
+                    fraction,
+                    pass,
+                    elapsed: start.elapsed(),
+                    rays_per_second: (RAYS_TRACED.get() - rays_at_start) as f64
+                        / start.elapsed().as_secs_f64(),
+                    finished: false,
+                });
+
+                // Every pixel holds a valid estimate between tiles, so the image can be saved
+                // or finished after any tile
+                if control.should_stop() {
+                    stop_reason = if control.is_cancelled() {
+                        Some("Cancelled")
+                    } else {
+                        Some("Time budget reached")
+                    };
+                    break 'passes;
+                }
+                if last_write.elapsed() >= self.checkpoint_interval {
+                    let write_start = Instant::now();
+                    self.write_progress(&film, aov_film.as_ref(), key)?;
+                    writing += write_start.elapsed();
+                    last_write = Instant::now();
+                }
+            }
+            if active == 0 {
+                // Nothing was left to sample in this pass
+                pass -= 1;
+                break;
+            }
+
+            // The noise target is checked once every pixel has had the same number of passes
+            if let Some(target) = noise_target {
+                noise = film.mean_relative_error();
+                if noise <= target {
+                    stop_reason = Some("Noise target reached");
+                    break;
+                }
+            }
+        }
+        let elapsed = start.elapsed();
+        control.report(Progress {
+            fraction: 1.0,
+            pass,
+            elapsed,
+            rays_per_second: (RAYS_TRACED.get() - rays_at_start) as f64 / elapsed.as_secs_f64(),
+            finished: true,
+        });
+        record_phase("Sampling", elapsed - writing);
+        if let Some(reason) = stop_reason {
+            println!("{}", reason);
+        }
+
+        // Write the image
+        let write_start = Instant::now();
+        self.write_progress(&film, aov_film.as_ref(), key)?;
+        if let Some(path) = &self.heatmap {
+            film.write_heatmap(path, &self.output_frame(&film))?;
+        }
+        if let Some(aov_film) = &aov_film {
+            self.write_aovs(&film, aov_film)?;
+        }
+        record_phase("Writing images", writing + write_start.elapsed());
+        println!("Done");
+        print_statistics();
+        Ok(())
+    }
+
+    /// Returns whether any output variables are needed, to be written or to guide the
+    /// denoiser.
+    fn wants_aovs(&self) -> bool {
+        !self.aovs.is_empty() || self.aov_exr.is_some() || self.denoiser.is_some()
+    }
+
+    /// Writes the output variables as separate images, or as layers of the EXR file with the
+    /// beauty image when one is given.
+    fn write_aovs(&self, film: &Film, aov_film: &AovFilm) -> std::io::Result<()> {
+        let frame = self.output_frame(film);
+        match &self.aov_exr {
+            Some(path) => {
+                let space = self.display.working_space;
+                aov_film.write_exr(path, film, &self.aovs, &frame, space)
+            }
+            None => {
+                let (stem, _) = split_extension(&self.output);
+                for &aov in &self.aovs {
+                    let path = format!("{}_{}.ppm", stem, aov.name());
+                    aov_film.write_ppm(aov, &path, &frame, &self.display)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Renders a tile in passes until every pixel is done, as a worker does for one job.
+    fn render_tile(
+        &self,
+        world: &dyn Hittable,
+        tile: Tile,
+        (base_samples, max_samples): (u32, u32),
+    ) -> Film {
+        let sampler = self.sampler.create(base_samples, self.seed);
+        // Samples near the edge also count in the pixels of the neighbouring tiles
+        let frame = self.frame();
+        let reach = tile.expand(self.filter.margin()).intersect(&frame);
+        let mut film = self.empty_film(tile, reach.unwrap_or(tile));
+        loop {
+            let mut active = false;
+            for (i, j) in tile.pixels() {
+                let count = self.pass_samples(film.pixel(i, j), base_samples, max_samples);
+                if count > 0 {
+                    self.sample_pixel(&mut film, None, sampler.as_ref(), world, (i, j), count);
+                    active = true;
+                }
+            }
+            if !active {
+                return film;
+            }
+        }
+    }
+
+    /// Renders tiles for the coordinator at `address` until it has no work left.
+    ///
+    /// The worker must build the same scene and camera as the coordinator; the sampling
+    /// settings come with every job.
+    pub fn render_worker(mut self, world: &dyn Hittable, address: &str) -> std::io::Result<()> {
         Camera::initialize(&mut self);
+        let mut connection = Connection::connect(address, Duration::from_secs(30))?;
+        write_hello(&mut connection, self.camera_key())?;
+        println!("Connected to {}", address);
 
-        // Open file for output
-        let mut output: std::fs::File = std::fs::File::create("output.ppm")?;
-        // Write PPM header
-        write!(
-            output,
-            "P3\n{} {}\n255\n",
-            self.image_width, self.image_height
-        )?;
-
-        // Loop over each pixel in the image
-        for j in 0..self.image_height {
-            println!("\rScanlines remaining: {}", self.image_height - j);
-            for i in 0..self.image_width {
-                // Initialize pixel color
-                let mut pixel_color: Color = Color::new(0.0, 0.0, 0.0);
-                // Perform multiple samples per pixel for antialiasing
-                for _ in 0..self.samples_per_pixel {
-                    let r: Ray = Camera::get_ray(&mut self, i, j);
-                    pixel_color += Camera::ray_color(&r, self.max_depth, world);
-                }
-                // Write color to output file
-                Color::write_color(&output, pixel_color, self.samples_per_pixel);
+        let mut tiles = 0;
+        loop {
+            let job = match read_job(&mut connection, &self.frame()) {
+                Ok(job) => job,
+                // The coordinator exits once the image is complete or cancelled
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            };
+            match job {
+                Job::Render { tile, settings } => {
+                    let counters_before = (
+                        CAMERA_RAYS.get(),
+                        RAYS_TRACED.get(),
+                        INTERSECTION_TESTS.get(),
+                        NODES_VISITED.get(),
+                    );
+                    self.adaptive_threshold = settings.adaptive_threshold;
+                    let film = self.render_tile(
+                        world,
+                        tile,
+                        (settings.base_samples, settings.max_samples),
+                    );
+                    let counters = WorkCounters {
+                        camera_rays: CAMERA_RAYS.get() - counters_before.0,
+                        rays_traced: RAYS_TRACED.get() - counters_before.1,
+                        intersection_tests: INTERSECTION_TESTS.get() - counters_before.2,
+                        nodes_visited: NODES_VISITED.get() - counters_before.3,
+                    };
+                    write_result(&mut connection, &film, &counters)?;
+                    tiles += 1;
+                }
+                Job::Finish => break,
+                Job::Reject => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "the coordinator renders a different scene",
+                    ))
+                }
             }
         }
+        println!("Rendered {} tiles", tiles);
+        Ok(())
+    }
+
+    /// Renders the scene by handing out tiles to worker processes connecting to `address`,
+    /// merging their results.
+    ///
+    /// Tiles are given out one at a time, so faster workers take more of them, and a tile
+    /// whose worker fails goes to another worker. Rendering stops early when `control` is
+    /// cancelled or its deadline passes, leaving missing tiles black.
+    pub fn render_distributed(
+        mut self,
+        address: &str,
+        control: &RenderControl,
+    ) -> std::io::Result<()> {
+        reset_counters();
+        let setup_start = Instant::now();
+        Camera::initialize(&mut self);
+        let (base_samples, max_samples) = self.sample_counts(None);
+        let settings = JobSettings {
+            base_samples,
+            max_samples,
+            adaptive_threshold: self.adaptive_threshold,
+        };
+        let key = self.checkpoint_key(base_samples);
+        let area = self.render_area()?;
+        let mut film = self.start_film(key, area)?;
+
+        // Tiles finished in a resumed checkpoint are not rendered again
+        let tiles: VecDeque<Tile> = self
+            .tile_order
+            .tiles(area, self.tile_size)
+            .into_iter()
+            .filter(|tile| {
+                tile.pixels()
+                    .any(|(i, j)| film.pixel(i, j).count < base_samples)
+            })
+            .collect();
+        let total = tiles.len();
+        // The splats of a partly sampled tile cannot be taken out to render it again
+        let partly_sampled = |tile: &Tile| tile.pixels().any(|(i, j)| film.pixel(i, j).count > 0);
+        if film.splats.is_some() && tiles.iter().any(partly_sampled) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a filtered checkpoint with partly sampled tiles cannot be resumed distributed",
+            ));
+        }
+
+        // The Unix socket file, if any, is removed when the render returns
+        let (listener, _socket_file) = Listener::bind(address)?;
+        let queue = Arc::new(Mutex::new(JobQueue::new(tiles)));
+        let (sender, results) = mpsc::channel();
+        let serve = ServeSettings {
+            key: self.camera_key(),
+            job: settings,
+            timeout: self.worker_timeout,
+            frame: self.frame(),
+        };
+        std::thread::spawn(move || accept_workers(listener, serve, queue, sender));
+        println!("Waiting for workers on {}", address);
+        if self.wants_aovs() {
+            eprintln!(
+                "warning: distributed renders are neither denoised nor write output variables"
+            );
+        }
+        record_phase("Setup", setup_start.elapsed());
+
+        let start = Instant::now();
+        let mut writing = Duration::ZERO;
+        let mut last_write = start;
+        let mut finished = 0;
+        let mut stop_reason = None;
+        while finished < total {
+            if control.should_stop() {
+                stop_reason = if control.is_cancelled() {
+                    Some("Cancelled")
+                } else {
+                    Some("Time budget reached")
+                };
+                break;
+            }
+            match results.recv_timeout(Duration::from_millis(100)) {
+                Ok((tile_film, counters)) => {
+                    film.insert(&tile_film);
+                    CAMERA_RAYS.add(counters.camera_rays);
+                    RAYS_TRACED.add(counters.rays_traced);
+                    INTERSECTION_TESTS.add(counters.intersection_tests);
+                    NODES_VISITED.add(counters.nodes_visited);
+                    finished += 1;
+                }
+                Err(_) => continue,
+            }
+            control.report(Progress {
+                fraction: finished as f64 / total as f64,
+                pass: 1,
+                elapsed: start.elapsed(),
+                rays_per_second: RAYS_TRACED.get() as f64 / start.elapsed().as_secs_f64(),
+                finished: false,
+            });
+            if last_write.elapsed() >= self.checkpoint_interval {
+                let write_start = Instant::now();
+                self.write_progress(&film, None, key)?;
+                writing += write_start.elapsed();
+                last_write = Instant::now();
+            }
+        }
+        let elapsed = start.elapsed();
+        control.report(Progress {
+            fraction: 1.0,
+            pass: 1,
+            elapsed,
+            rays_per_second: RAYS_TRACED.get() as f64 / elapsed.as_secs_f64(),
+            finished: true,
+        });
+        record_phase("Sampling", elapsed - writing);
+        if let Some(reason) = stop_reason {
+            println!("{}", reason);
+        }
+
+        // Write the image
+        let write_start = Instant::now();
+        self.write_progress(&film, None, key)?;
+        if let Some(path) = &self.heatmap {
+            film.write_heatmap(path, &self.output_frame(&film))?;
+        }
+        record_phase("Writing images", writing + write_start.elapsed());
         println!("Done");
+        print_statistics();
         Ok(())
     }
 }
+
+/// Splits a path into the part before the extension and the extension with its dot, which is
+/// empty when the file name has none.
+pub fn split_extension(path: &str) -> (&str, &str) {
+    let name_start = path.rfind('/').map_or(0, |slash| slash + 1);
+    match path[name_start..].rfind('.') {
+        Some(dot) => path.split_at(name_start + dot),
+        None => (path, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable_list::*, material::*, sphere::*};
+
+    // Returns a small camera looking at a diffuse sphere on a diffuse ground.
+    fn small_scene() -> (Camera, HittableList) {
+        let mut world = HittableList::new();
+        let ground = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let ball = Rc::new(Lambertian::new(Color::new(0.8, 0.3, 0.1)));
+        world.add(Rc::new(Sphere::new(
+            Point3::new(0.0, -100.5, -1.0),
+            100.0,
+            Some(ground),
+        )));
+        world.add(Rc::new(Sphere::new(
+            Point3::new(0.0, 0.0, -1.0),
+            0.5,
+            Some(ball),
+        )));
+
+        let mut cam = Camera::default();
+        cam.image_width = 24;
+        cam.samples_per_pixel = 4;
+        cam.tile_size = 8;
+        cam.lookfrom = Point3::new(0.0, 0.0, 1.0);
+        cam.lookat = Point3::new(0.0, 0.0, -1.0);
+        cam.vfov = 60.0;
+        (cam, world)
+    }
+
+    // Renders the scene and returns the contents of its checkpoint, which holds the film's
+    // accumulated samples.
+    fn render_film(mut cam: Camera, world: &HittableList, name: &str) -> Vec<u8> {
+        let stem = std::env::temp_dir().join(format!("camera_{}_{}", std::process::id(), name));
+        let stem = stem.to_string_lossy();
+        cam.output = format!("{}.ppm", stem);
+        cam.checkpoint = Some(format!("{}.ckpt", stem));
+        cam.render(world, &RenderControl::new()).unwrap();
+        let film = std::fs::read(format!("{}.ckpt", stem)).unwrap();
+        std::fs::remove_file(format!("{}.ppm", stem)).unwrap();
+        std::fs::remove_file(format!("{}.ckpt", stem)).unwrap();
+        film
+    }
+
+    #[test]
+    fn equal_seeds_render_identical_films_in_any_tile_order() {
+        let films: Vec<Vec<u8>> = [TileOrder::Scanline, TileOrder::Hilbert, TileOrder::Scanline]
+            .into_iter()
+            .enumerate()
+            .map(|(k, order)| {
+                let (mut cam, world) = small_scene();
+                cam.seed = 42;
+                cam.tile_order = order;
+                render_film(cam, &world, &format!("order_{}", k))
+            })
+            .collect();
+        assert!(films[0] == films[1]);
+        assert!(films[1] == films[2]);
+    }
+
+    #[test]
+    fn progress_is_reported_until_the_render_finishes() {
+        let (mut cam, world) = small_scene();
+        let stem = std::env::temp_dir().join(format!("camera_{}_progress", std::process::id()));
+        cam.output = format!("{}.ppm", stem.to_string_lossy());
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reports);
+        let control = RenderControl::new()
+            .with_progress_callback(move |progress| sink.lock().unwrap().push(*progress));
+        cam.render(&world, &control).unwrap();
+        std::fs::remove_file(&cam.output).unwrap();
+
+        let reports = reports.lock().unwrap();
+        // Reports follow every tile of the 24 by 24 image in every pass, with one sample per
+        // pixel and pass
+        assert!(reports.len() > 4 * 9);
+        assert!(reports
+            .windows(2)
+            .all(|pair| pair[0].fraction <= pair[1].fraction));
+        let last = reports.last().unwrap();
+        assert!(last.finished && last.fraction == 1.0 && last.pass == 4);
+        let (_, running) = reports.split_last().unwrap();
+        assert!(running.iter().all(|report| !report.finished));
+        assert_eq!(control.progress(), 1.0);
+    }
+
+    // Returns an orthographic camera seeing 4 units high, looking down at an angle.
+    fn orthographic() -> Camera {
+        let mut cam = Camera::default();
+        cam.image_width = 40;
+        cam.aspect_ratio = 2.0;
+        cam.projection = Projection::Orthographic;
+        cam.view_height = Some(4.0);
+        cam.lookfrom = Point3::new(1.0, 2.0, 3.0);
+        cam.lookat = Point3::new(0.0, 0.0, 0.0);
+        cam.focus_dist = 3.0;
+        Camera::initialize(&mut cam);
+        cam
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel_across_the_view_height() {
+        let cam = orthographic();
+        let mut rng = Rng::new(0);
+        let mut ray =
+            |i: i32, j: i32, offset: (f64, f64)| cam.get_ray(i, j, offset, &mut rng).unwrap();
+        for (i, j) in [(0, 0), (39, 0), (20, 10), (0, 19), (39, 19)] {
+            let direction = Vec3::unit_vector(ray(i, j, (0.0, 0.0)).direction());
+            assert!((Vec3::dot(&direction, &cam.w) + 1.0).abs() < 1e-12);
+        }
+
+        // The top edge of the top row and the bottom edge of the bottom row are the view
+        // height apart, and the origins lie in the plane through the camera position
+        let top = ray(20, 0, (0.0, -0.5)).origin();
+        let bottom = ray(20, 19, (0.0, 0.5)).origin();
+        assert!((Vec3::dot(&(top - bottom), &cam.v) - 4.0).abs() < 1e-12);
+        assert!(Vec3::dot(&(top - cam.center), &cam.w).abs() < 1e-12);
+        let left = ray(0, 10, (-0.5, 0.0)).origin();
+        let right = ray(39, 10, (0.5, 0.0)).origin();
+        assert!((Vec3::dot(&(right - left), &cam.u) - 8.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn orthographic_defocus_converges_at_the_focus_distance() {
+        let mut cam = orthographic();
+        cam.defocus_angle = 10.0;
+        Camera::initialize(&mut cam);
+        let rays: Vec<Ray> = (0..16)
+            .map(|seed| cam.get_ray(7, 3, (0.2, -0.1), &mut Rng::new(seed)).unwrap())
+            .collect();
+        for r in &rays {
+            // The rays start on the camera plane and meet on the focus plane
+            assert!(Vec3::dot(&(cam.center - r.origin()), &cam.w).abs() < 1e-12);
+            assert!((Vec3::dot(&(cam.center - r.at(1.0)), &cam.w) - 3.0).abs() < 1e-12);
+            assert!((r.at(1.0) - rays[0].at(1.0)).length() < 1e-12);
+        }
+        let spread = rays
+            .iter()
+            .map(|r| (r.origin() - rays[0].origin()).length());
+        assert!(spread.fold(0.0, f64::max) > 0.01);
+    }
+
+    // Returns where a ray reaches the plane `distance` in front of the camera.
+    fn at_distance(cam: &Camera, r: &Ray, distance: f64) -> Point3 {
+        let start = Vec3::dot(&(cam.center - r.origin()), &cam.w);
+        let t = (distance - start) / Vec3::dot(&-r.direction(), &cam.w);
+        r.at(t)
+    }
+
+    #[test]
+    fn stereo_rays_converge_at_the_convergence_distance() {
+        for mode in [StereoMode::OffAxis, StereoMode::ToeIn] {
+            let mut cam = Camera::default();
+            cam.image_width = 20;
+            cam.aspect_ratio = 2.0;
+            cam.focus_dist = 3.0;
+            cam.stereo = Some(Stereo {
+                interocular: 0.5,
+                convergence: Some(5.0),
+                mode,
+                layout: StereoLayout::SideBySide,
+            });
+            Camera::initialize(&mut cam);
+            let mut rng = Rng::new(0);
+            // Toe-in only lines up the views in the middle of the picture
+            let pixels = match mode {
+                StereoMode::OffAxis => vec![(0, 0), (10, 5), (19, 9)],
+                StereoMode::ToeIn => vec![(10, 5)],
+            };
+            for (i, j) in pixels {
+                let offset = match mode {
+                    StereoMode::OffAxis => (0.3, -0.2),
+                    StereoMode::ToeIn => (-0.5, -0.5),
+                };
+                let left = cam.get_ray(i, j, offset, &mut rng).unwrap();
+                let right = cam.get_ray(i + 20, j, offset, &mut rng).unwrap();
+                let (l, r) = (
+                    at_distance(&cam, &left, 5.0),
+                    at_distance(&cam, &right, 5.0),
+                );
+                assert!((l - r).length() < 1e-9, "{:?} at ({}, {})", mode, i, j);
+                assert!((left.origin() - right.origin()).length() > 0.49);
+
+                // Nearer and further, the views are apart, and off-axis only sideways
+                for distance in [2.0, 10.0] {
+                    let (l, r) = (
+                        at_distance(&cam, &left, distance),
+                        at_distance(&cam, &right, distance),
+                    );
+                    assert!((l - r).length() > 0.1);
+                    if mode == StereoMode::OffAxis {
+                        assert!(Vec3::dot(&(l - r), &cam.v).abs() < 1e-9);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn different_seeds_render_different_films() {
+        let films: Vec<Vec<u8>> = [1, 2]
+            .into_iter()
+            .map(|seed| {
+                let (mut cam, world) = small_scene();
+                cam.seed = seed;
+                render_film(cam, &world, &format!("seed_{}", seed))
+            })
+            .collect();
+        assert!(films[0] != films[1]);
+    }
+}
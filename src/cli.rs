@@ -0,0 +1,914 @@
+use crate::{
+    aov::*, display::*, filter::*, physical::*, post::*, projection::*, rtweekend::*, sampler::*,
+    stereo::*, tile::*,
+};
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Command-line options for the renderer.
+pub struct Options {
+    /// Seed for the random numbers building the scene and sampling the image.
+    pub seed: u64,
+
+    /// Number of samples per pixel, or the base pass of adaptive sampling.
+    pub samples_per_pixel: i32,
+
+    /// Upper limit of samples per pixel for adaptive sampling.
+    pub max_samples_per_pixel: i32,
+
+    /// Relative error below which adaptive sampling stops sampling a pixel.
+    pub adaptive_threshold: f64,
+
+    /// Path of the rendered image; without it `output.ppm`, or `frame_NNNN.png` for the
+    /// frames of an animation.
+    pub output: Option<String>,
+
+    /// Path of an image showing the samples taken per pixel.
+    pub heatmap: Option<String>,
+
+    /// Path of the checkpoint file for progressive rendering.
+    pub checkpoint: Option<String>,
+
+    /// Time between writes of the current image and checkpoint.
+    pub checkpoint_interval: Duration,
+
+    /// Continues the render stored in the checkpoint file.
+    pub resume: bool,
+
+    /// Rendering time after which the image is written as it is.
+    pub time_budget: Option<Duration>,
+
+    /// Mean relative pixel error at which rendering stops, instead of a fixed sample count.
+    pub noise_target: Option<f64>,
+
+    /// Width and height in pixels of the tiles the image is rendered in.
+    pub tile_size: i32,
+
+    /// Order in which the tiles are rendered.
+    pub tile_order: TileOrder,
+
+    /// Part of the image to render.
+    pub region: Option<Tile>,
+
+    /// Writes only the region instead of the whole frame.
+    pub crop: bool,
+
+    /// Address on which to hand out tiles to worker processes, instead of rendering locally.
+    pub coordinator: Option<String>,
+
+    /// Address of a coordinator to render tiles for.
+    pub worker: Option<String>,
+
+    /// Longest the coordinator waits for a worker's answer before giving its tile to another
+    /// worker.
+    pub worker_timeout: Duration,
+
+    /// Hides the progress bar.
+    pub quiet: bool,
+
+    /// Output variables written besides the beauty image.
+    pub aovs: Vec<Aov>,
+
+    /// Path of a multi-layer OpenEXR file holding the beauty image and output variables.
+    pub aov_exr: Option<String>,
+
+    /// Pixel reconstruction filter.
+    pub filter: Filter,
+
+    /// Mapping from the image to rays into the scene.
+    pub projection: Projection,
+
+    /// Height of the orthographic view in scene units.
+    pub view_height: Option<f64>,
+
+    /// Full field of view of the fisheye projections in degrees.
+    pub fisheye_fov: f64,
+
+    /// Real camera settings deriving the field of view, depth of field and exposure.
+    pub physical: Option<PhysicalCamera>,
+
+    /// Path of a lens system to trace instead of the thin lens.
+    pub lens: Option<String>,
+
+    /// Number of diaphragm blades shaping the aperture, instead of a round one.
+    pub aperture_blades: Option<u32>,
+
+    /// Angle of the first blade corner in degrees.
+    pub aperture_rotation: f64,
+
+    /// Path of an image of the aperture opening.
+    pub aperture_image: Option<String>,
+
+    /// Cat-eye vignetting of the thin lens.
+    pub cat_eye: f64,
+
+    /// Focuses on what the focus pixel sees instead of at the fixed focus distance.
+    pub autofocus: bool,
+
+    /// Pixel of the view that autofocus focuses through, instead of the center.
+    pub focus_pixel: Option<(i32, i32)>,
+
+    /// Name of the scene object to focus on.
+    pub focus_object: Option<String>,
+
+    /// Path of the keyframes animating the camera and scene objects.
+    pub animation: Option<String>,
+
+    /// First and last frame of the animation to render as numbered images.
+    pub frames: Option<(u32, u32)>,
+
+    /// Frames per second of the animation.
+    pub fps: f64,
+
+    /// Blurs animated objects over the time the shutter is open.
+    pub motion_blur: bool,
+
+    /// Left and right eye views to render instead of a single view.
+    pub stereo: Option<Stereo>,
+
+    /// Lens and film effects applied before the display transform.
+    pub effects: PostEffects,
+
+    /// Exposure adjustment of the written images, in stops.
+    pub exposure: f64,
+
+    /// Curve compressing radiance into the range of the written images.
+    pub tone_mapper: ToneMapper,
+
+    /// Color space rendering multiplies colors in.
+    pub working_space: ColorSpace,
+
+    /// Denoises the written image.
+    pub denoise: bool,
+
+    /// Luminance difference the denoiser blurs, in standard deviations of the noise.
+    pub denoise_strength: f64,
+
+    /// Generator of the leading sample dimensions of every pixel sample.
+    pub sampler: SamplerKind,
+
+    /// Renders with the spectral integrator instead of RGB.
+    pub spectral: bool,
+
+    /// Mixes spheres of the coated and translucent materials into the scene's random spheres.
+    pub material_showcase: bool,
+
+    /// Equirectangular `.hdr` or `.exr` image lighting the scene, replacing the sky gradient.
+    pub environment: Option<String>,
+
+    /// Rotation of the environment map about the vertical axis, in degrees.
+    pub environment_rotation: f64,
+
+    /// Scale applied to the environment map's radiance.
+    pub environment_intensity: f64,
+
+    /// Lights the scene with the physical sky and sun model instead of the sky gradient.
+    pub sky: bool,
+
+    /// Atmospheric turbidity of the physical sky.
+    pub turbidity: f64,
+
+    /// Gray albedo of the ground below the physical sky's horizon, from 0 to 1.
+    pub ground_albedo: f64,
+
+    /// Scale applied to the physical sky's radiance, per kcd/m².
+    pub sky_intensity: f64,
+
+    /// Sun elevation above the horizon in degrees.
+    pub sun_elevation: f64,
+
+    /// Sun azimuth clockwise from north (-z) in degrees.
+    pub sun_azimuth: f64,
+
+    /// Latitude and longitude in degrees placing the sun, together with `datetime`.
+    pub location: Option<(f64, f64)>,
+
+    /// UTC date and time placing the sun, as year, month, day and fractional hour.
+    pub datetime: Option<(i32, u32, u32, f64)>,
+}
+
+impl Options {
+    /// Creates `Options` with default settings.
+    pub fn default() -> Self {
+        Options {
+            seed: 0,
+            samples_per_pixel: 1,
+            max_samples_per_pixel: 0,
+            adaptive_threshold: 0.05,
+            output: None,
+            heatmap: None,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+            resume: false,
+            time_budget: None,
+            noise_target: None,
+            tile_size: 32,
+            tile_order: TileOrder::Scanline,
+            region: None,
+            crop: false,
+            coordinator: None,
+            worker: None,
+            worker_timeout: Duration::from_secs(300),
+            quiet: false,
+            aovs: Vec::new(),
+            aov_exr: None,
+            filter: Filter::default(),
+            projection: Projection::Perspective,
+            view_height: None,
+            fisheye_fov: 180.0,
+            physical: None,
+            lens: None,
+            aperture_blades: None,
+            aperture_rotation: 0.0,
+            aperture_image: None,
+            cat_eye: 0.0,
+            autofocus: false,
+            focus_pixel: None,
+            focus_object: None,
+            animation: None,
+            frames: None,
+            fps: 24.0,
+            motion_blur: false,
+            stereo: None,
+            effects: PostEffects::default(),
+            exposure: 0.0,
+            tone_mapper: ToneMapper::Clamp,
+            working_space: ColorSpace::LinearSrgb,
+            denoise: false,
+            denoise_strength: 4.0,
+            sampler: SamplerKind::Independent,
+            spectral: false,
+            material_showcase: false,
+            environment: None,
+            environment_rotation: 0.0,
+            environment_intensity: 1.0,
+            sky: false,
+            turbidity: 3.0,
+            ground_albedo: 0.3,
+            sky_intensity: 0.1,
+            sun_elevation: 45.0,
+            sun_azimuth: 135.0,
+            location: None,
+            datetime: None,
+        }
+    }
+
+    /// Parses options from the given arguments, excluding the program name.
+    ///
+    /// `--scene FILE` reads settings from a scene file, one per line as a flag name without
+    /// its dashes followed by its values, such as `sampler sobol`. Lines starting with `#` are
+    /// comments. Flags after `--scene` override the file's settings.
+    pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut args = expand_scene_files(args)?.into_iter();
+        let mut options = Options::default();
+        // The radius may come before the filter, which otherwise sets its usual radius
+        let mut filter_radius = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => options.seed = value(&mut args, &arg)?,
+                "--spp" => options.samples_per_pixel = value(&mut args, &arg)?,
+                "--max-spp" => options.max_samples_per_pixel = value(&mut args, &arg)?,
+                "--adaptive-threshold" => options.adaptive_threshold = value(&mut args, &arg)?,
+                "--output" | "-o" => options.output = Some(value(&mut args, &arg)?),
+                "--heatmap" => options.heatmap = Some(value(&mut args, &arg)?),
+                "--checkpoint" => options.checkpoint = Some(value(&mut args, &arg)?),
+                "--checkpoint-interval" => {
+                    let text: String = value(&mut args, &arg)?;
+                    options.checkpoint_interval = parse_duration(&text)?;
+                }
+                "--resume" => options.resume = true,
+                "--time-budget" => {
+                    let text: String = value(&mut args, &arg)?;
+                    options.time_budget = Some(parse_duration(&text)?);
+                }
+                "--noise-target" => options.noise_target = Some(value(&mut args, &arg)?),
+                "--tile-size" => options.tile_size = value(&mut args, &arg)?,
+                "--tile-order" => {
+                    let name: String = value(&mut args, &arg)?;
+                    options.tile_order = TileOrder::parse(&name)
+                        .ok_or_else(|| format!("unknown tile order: {}", name))?;
+                }
+                "--region" => {
+                    let text: String = value(&mut args, &arg)?;
+                    options.region = Some(parse_region(&text)?);
+                }
+                "--crop" => options.crop = true,
+                "--coordinator" => options.coordinator = Some(value(&mut args, &arg)?),
+                "--worker" => options.worker = Some(value(&mut args, &arg)?),
+                "--worker-timeout" => {
+                    let text: String = value(&mut args, &arg)?;
+                    options.worker_timeout = parse_duration(&text)?;
+                    if options.worker_timeout.is_zero() {
+                        return Err(format!("invalid value for {}: {}", arg, text));
+                    }
+                }
+                "--quiet" => options.quiet = true,
+                "--aov" => {
+                    let text: String = value(&mut args, &arg)?;
+                    options.aovs = parse_aovs(&text)?;
+                }
+                "--aov-exr" => options.aov_exr = Some(value(&mut args, &arg)?),
+                "--filter" => {
+                    let name: String = value(&mut args, &arg)?;
+                    let kind = FilterKind::parse(&name)
+                        .ok_or_else(|| format!("unknown filter: {}", name))?;
+                    let radius = filter_radius.unwrap_or(kind.default_radius());
+                    options.filter = Filter::new(kind, radius);
+                }
+                "--filter-radius" => {
+                    let radius: f64 = value(&mut args, &arg)?;
+                    if radius <= 0.0 {
+                        return Err(format!("invalid value for {}: {}", arg, radius));
+                    }
+                    filter_radius = Some(radius);
+                    options.filter = Filter::new(options.filter.kind, radius);
+                }
+                "--projection" => {
+                    let name: String = value(&mut args, &arg)?;
+                    options.projection = Projection::parse(&name)
+                        .ok_or_else(|| format!("unknown projection: {}", name))?;
+                }
+                "--view-height" => {
+                    let height: f64 = value(&mut args, &arg)?;
+                    if height <= 0.0 {
+                        return Err(format!("invalid value for {}: {}", arg, height));
+                    }
+                    options.view_height = Some(height);
+                }
+                "--fisheye-fov" => {
+                    let fov: f64 = value(&mut args, &arg)?;
+                    if fov <= 0.0 || fov > 360.0 {
+                        return Err(format!("invalid value for {}: {}", arg, fov));
+                    }
+                    options.fisheye_fov = fov;
+                }
+                "--focal-length" => {
+                    let length = positive(&mut args, &arg)?;
+                    options
+                        .physical
+                        .get_or_insert_with(PhysicalCamera::default)
+                        .focal_length = length;
+                }
+                "--sensor" => {
+                    let text: String = value(&mut args, &arg)?;
+                    let (width, height) = parse_sensor(&text)?;
+                    let physical = options.physical.get_or_insert_with(PhysicalCamera::default);
+                    physical.sensor_width = width;
+                    physical.sensor_height = height;
+                }
+                "--f-stop" => {
+                    let f_number = positive(&mut args, &arg)?;
+                    options
+                        .physical
+                        .get_or_insert_with(PhysicalCamera::default)
+                        .f_number = f_number;
+                }
+                "--shutter" => {
+                    let text: String = value(&mut args, &arg)?;
+                    let shutter = parse_shutter(&text)?;
+                    options
+                        .physical
+                        .get_or_insert_with(PhysicalCamera::default)
+                        .shutter = shutter;
+                }
+                "--iso" => {
+                    let iso = positive(&mut args, &arg)?;
+                    options
+                        .physical
+                        .get_or_insert_with(PhysicalCamera::default)
+                        .iso = iso;
+                }
+                "--lens" => options.lens = Some(value(&mut args, &arg)?),
+                "--aperture-blades" => {
+                    let blades: u32 = value(&mut args, &arg)?;
+                    if blades < 3 {
+                        return Err(format!("invalid value for {}: {}", arg, blades));
+                    }
+                    options.aperture_blades = Some(blades);
+                }
+                "--aperture-rotation" => options.aperture_rotation = value(&mut args, &arg)?,
+                "--aperture-image" => options.aperture_image = Some(value(&mut args, &arg)?),
+                "--cat-eye" => options.cat_eye = value(&mut args, &arg)?,
+                "--autofocus" => options.autofocus = true,
+                "--focus-pixel" => {
+                    let text: String = value(&mut args, &arg)?;
+                    options.autofocus = true;
+                    options.focus_pixel = Some(parse_pixel(&text)?);
+                }
+                "--focus-object" => options.focus_object = Some(value(&mut args, &arg)?),
+                "--animation" => options.animation = Some(value(&mut args, &arg)?),
+                "--frames" => {
+                    let text: String = value(&mut args, &arg)?;
+                    options.frames = Some(parse_frames(&text)?);
+                }
+                "--fps" => options.fps = positive(&mut args, &arg)?,
+                "--motion-blur" => options.motion_blur = true,
+                "--stereo" => {
+                    let name: String = value(&mut args, &arg)?;
+                    let layout = StereoLayout::parse(&name)
+                        .ok_or_else(|| format!("unknown stereo layout: {}", name))?;
+                    options.stereo.get_or_insert_with(Stereo::default).layout = layout;
+                }
+                "--stereo-mode" => {
+                    let name: String = value(&mut args, &arg)?;
+                    let mode = StereoMode::parse(&name)
+                        .ok_or_else(|| format!("unknown stereo mode: {}", name))?;
+                    options.stereo.get_or_insert_with(Stereo::default).mode = mode;
+                }
+                "--interocular" => {
+                    let distance: f64 = value(&mut args, &arg)?;
+                    if distance < 0.0 {
+                        return Err(format!("invalid value for {}: {}", arg, distance));
+                    }
+                    options
+                        .stereo
+                        .get_or_insert_with(Stereo::default)
+                        .interocular = distance;
+                }
+                "--convergence" => {
+                    let distance: f64 = value(&mut args, &arg)?;
+                    if distance <= 0.0 {
+                        return Err(format!("invalid value for {}: {}", arg, distance));
+                    }
+                    options
+                        .stereo
+                        .get_or_insert_with(Stereo::default)
+                        .convergence = Some(distance);
+                }
+                "--bloom" => options.effects.bloom = non_negative(&mut args, &arg)?,
+                "--bloom-radius" => options.effects.bloom_radius = positive(&mut args, &arg)?,
+                "--bloom-threshold" => options.effects.threshold = non_negative(&mut args, &arg)?,
+                "--glare" => options.effects.glare = non_negative(&mut args, &arg)?,
+                "--glare-streaks" => options.effects.glare_streaks = value(&mut args, &arg)?,
+                "--glare-length" => options.effects.glare_length = positive(&mut args, &arg)?,
+                "--vignette" => options.effects.vignette = non_negative(&mut args, &arg)?,
+                "--chromatic-aberration" => {
+                    options.effects.chromatic_aberration = value(&mut args, &arg)?
+                }
+                "--grain" => options.effects.grain = non_negative(&mut args, &arg)?,
+                "--exposure" => options.exposure = value(&mut args, &arg)?,
+                "--tonemap" => {
+                    let name: String = value(&mut args, &arg)?;
+                    options.tone_mapper = ToneMapper::parse(&name)
+                        .ok_or_else(|| format!("unknown tone mapper: {}", name))?;
+                }
+                "--working-space" => {
+                    let name: String = value(&mut args, &arg)?;
+                    options.working_space = ColorSpace::parse(&name)
+                        .ok_or_else(|| format!("unknown color space: {}", name))?;
+                }
+                "--denoise" => options.denoise = true,
+                "--denoise-strength" => {
+                    options.denoise = true;
+                    options.denoise_strength = value(&mut args, &arg)?;
+                }
+                "--sampler" => {
+                    let name: String = value(&mut args, &arg)?;
+                    options.sampler = SamplerKind::parse(&name)
+                        .ok_or_else(|| format!("unknown sampler: {}", name))?;
+                }
+                "--spectral" => options.spectral = true,
+                "--material-showcase" => options.material_showcase = true,
+                "--environment" => options.environment = Some(value(&mut args, &arg)?),
+                "--environment-rotation" => options.environment_rotation = value(&mut args, &arg)?,
+                "--environment-intensity" => {
+                    options.environment_intensity = value(&mut args, &arg)?
+                }
+                "--sky" => options.sky = true,
+                "--turbidity" => options.turbidity = value(&mut args, &arg)?,
+                "--ground-albedo" => options.ground_albedo = value(&mut args, &arg)?,
+                "--sky-intensity" => options.sky_intensity = value(&mut args, &arg)?,
+                "--sun-elevation" => options.sun_elevation = value(&mut args, &arg)?,
+                "--sun-azimuth" => options.sun_azimuth = value(&mut args, &arg)?,
+                "--location" => {
+                    let text: String = value(&mut args, &arg)?;
+                    options.location = Some(parse_location(&text)?);
+                }
+                "--datetime" => {
+                    let text: String = value(&mut args, &arg)?;
+                    options.datetime = Some(parse_datetime(&text)?);
+                }
+                _ => return Err(format!("unknown argument: {}", arg)),
+            }
+        }
+        if options.resume && options.checkpoint.is_none() {
+            return Err("--resume requires --checkpoint".to_string());
+        }
+        if options.resume
+            && (options.denoise || !options.aovs.is_empty() || options.aov_exr.is_some())
+        {
+            // Checkpoints hold the beauty image only, not the output variables
+            return Err(
+                "--resume cannot be combined with --denoise, --aov or --aov-exr".to_string(),
+            );
+        }
+        if options.environment.is_some() && options.sky {
+            return Err("--environment and --sky cannot be combined".to_string());
+        }
+        if options.aperture_blades.is_some() && options.aperture_image.is_some() {
+            return Err("--aperture-blades and --aperture-image cannot be combined".to_string());
+        }
+        if options.autofocus && options.focus_object.is_some() {
+            return Err("--autofocus and --focus-object cannot be combined".to_string());
+        }
+        if options.frames.is_some() {
+            if options.animation.is_none() {
+                return Err("--frames requires --animation".to_string());
+            }
+            if options.coordinator.is_some() || options.worker.is_some() {
+                return Err(
+                    "--frames cannot be combined with --coordinator or --worker".to_string()
+                );
+            }
+            if options.resume {
+                return Err("--frames and --resume cannot be combined".to_string());
+            }
+        }
+        if options.coordinator.is_some() && options.worker.is_some() {
+            return Err("--coordinator and --worker cannot be combined".to_string());
+        }
+        if options.coordinator.is_some() && options.noise_target.is_some() {
+            // Workers sample their tiles on their own, without the image's noise
+            return Err("--noise-target and --coordinator cannot be combined".to_string());
+        }
+        // A noise target of zero or NaN is never reached, so the render would not end
+        let mut positives = vec![
+            ("--adaptive-threshold", options.adaptive_threshold),
+            ("--turbidity", options.turbidity),
+            ("--denoise-strength", options.denoise_strength),
+        ];
+        if let Some(target) = options.noise_target {
+            positives.push(("--noise-target", target));
+        }
+        for (flag, number) in positives {
+            if !(number.is_finite() && number > 0.0) {
+                return Err(format!("invalid value for {}: {}", flag, number));
+            }
+        }
+        if !(0.0..=1.0).contains(&options.ground_albedo) {
+            return Err(format!(
+                "invalid value for --ground-albedo: {}",
+                options.ground_albedo
+            ));
+        }
+        Ok(options)
+    }
+
+    /// Returns a hash of the options that change the scene, identifying it in checkpoints.
+    ///
+    /// The environment map and the animation count by their contents, so editing them in place
+    /// also tells checkpoints apart.
+    pub fn scene_hash(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        self.seed.hash(&mut hasher);
+        for path in [&self.environment, &self.animation] {
+            // Unreadable files stop the render when they are loaded
+            path.as_ref()
+                .map(|path| std::fs::read(path).ok())
+                .hash(&mut hasher);
+        }
+        self.sky.hash(&mut hasher);
+        self.material_showcase.hash(&mut hasher);
+        self.location
+            .map(|(lat, lon)| (lat.to_bits(), lon.to_bits()))
+            .hash(&mut hasher);
+        self.datetime
+            .map(|(year, month, day, hour)| (year, month, day, hour.to_bits()))
+            .hash(&mut hasher);
+        let scalars = [
+            self.environment_rotation,
+            self.environment_intensity,
+            self.turbidity,
+            self.ground_albedo,
+            self.sky_intensity,
+            self.sun_elevation,
+            self.sun_azimuth,
+        ];
+        for value in scalars {
+            value.to_bits().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+}
+
+// Replaces each `--scene FILE` in the arguments by the flags and values of the file's settings.
+fn expand_scene_files<I: Iterator<Item = String>>(mut args: I) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg != "--scene" {
+            expanded.push(arg);
+            continue;
+        }
+        let path: String = value(&mut args, &arg)?;
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("cannot read scene file {}: {}", path, e))?;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let name = fields.next().unwrap_or_default();
+            if name == "scene" {
+                return Err(format!(
+                    "{}: scene files cannot include other scene files",
+                    path
+                ));
+            }
+            expanded.push(format!("--{}", name));
+            expanded.extend(fields.map(str::to_string));
+        }
+    }
+    Ok(expanded)
+}
+
+// Parses the value following a flag.
+fn value<T: FromStr, I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<T, String> {
+    let text = args
+        .next()
+        .ok_or_else(|| format!("missing value for {}", flag))?;
+    text.parse()
+        .map_err(|_| format!("invalid value for {}: {}", flag, text))
+}
+
+// Parses the value following a flag, which must be a positive number.
+fn positive<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<f64, String> {
+    let number: f64 = value(args, flag)?;
+    if number > 0.0 {
+        Ok(number)
+    } else {
+        Err(format!("invalid value for {}: {}", flag, number))
+    }
+}
+
+// Parses the value following a flag, which must be zero or a positive number.
+fn non_negative<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<f64, String> {
+    let number: f64 = value(args, flag)?;
+    if number >= 0.0 {
+        Ok(number)
+    } else {
+        Err(format!("invalid value for {}: {}", flag, number))
+    }
+}
+
+// Parses a duration given in seconds, or with an `s`, `m` or `h` suffix.
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "invalid duration, expected e.g. 90, 90s, 10m or 2h: {}",
+            text
+        )
+    };
+    let (number, unit) = match text.char_indices().last() {
+        Some((index, 's')) => (&text[..index], 1.0),
+        Some((index, 'm')) => (&text[..index], 60.0),
+        Some((index, 'h')) => (&text[..index], 3600.0),
+        _ => (text, 1.0),
+    };
+    let seconds: f64 = number.parse().map_err(|_| invalid())?;
+    Duration::try_from_secs_f64(seconds * unit).map_err(|_| invalid())
+}
+
+// Parses a region given as `X,Y,W,H` in pixels from the top left.
+fn parse_region(text: &str) -> Result<Tile, String> {
+    let invalid = || format!("invalid region, expected X,Y,W,H: {}", text);
+    let values = text
+        .split(',')
+        .map(|value| value.trim().parse())
+        .collect::<Result<Vec<i32>, _>>()
+        .map_err(|_| invalid())?;
+    match values[..] {
+        [x, y, width, height] if x >= 0 && y >= 0 && width > 0 && height > 0 => {
+            Ok(Tile::new(x, y, width, height))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+// Parses a frame range given as `FIRST-LAST`, or a single frame.
+fn parse_frames(text: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid frames, expected FIRST-LAST or a frame: {}", text);
+    let (first, last) = text.split_once('-').unwrap_or((text, text));
+    let first: u32 = first.trim().parse().map_err(|_| invalid())?;
+    let last: u32 = last.trim().parse().map_err(|_| invalid())?;
+    if last < first {
+        return Err(invalid());
+    }
+    Ok((first, last))
+}
+
+// Parses a pixel given as `X,Y` from the top left.
+fn parse_pixel(text: &str) -> Result<(i32, i32), String> {
+    let invalid = || format!("invalid pixel, expected X,Y: {}", text);
+    let (x, y) = text.split_once(',').ok_or_else(invalid)?;
+    let x: i32 = x.trim().parse().map_err(|_| invalid())?;
+    let y: i32 = y.trim().parse().map_err(|_| invalid())?;
+    if x < 0 || y < 0 {
+        return Err(invalid());
+    }
+    Ok((x, y))
+}
+
+// Parses a comma-separated list of output variable names, or `all`.
+fn parse_aovs(text: &str) -> Result<Vec<Aov>, String> {
+    if text == "all" {
+        return Ok(Aov::ALL.to_vec());
+    }
+    text.split(',')
+        .map(|name| {
+            let name = name.trim();
+            Aov::parse(name).ok_or_else(|| format!("unknown output variable: {}", name))
+        })
+        .collect()
+}
+
+// Parses a location given as `LAT,LON` in degrees.
+fn parse_location(text: &str) -> Result<(f64, f64), String> {
+    let invalid = || format!("invalid location, expected LAT,LON: {}", text);
+    let (lat, lon) = text.split_once(',').ok_or_else(invalid)?;
+    let lat = lat.trim().parse().map_err(|_| invalid())?;
+    let lon = lon.trim().parse().map_err(|_| invalid())?;
+    Ok((lat, lon))
+}
+
+// Parses a UTC date and time given as `YYYY-MM-DDTHH:MM`.
+fn parse_datetime(text: &str) -> Result<(i32, u32, u32, f64), String> {
+    let invalid = || format!("invalid date and time, expected YYYY-MM-DDTHH:MM: {}", text);
+    let (date, time) = text.split_once('T').ok_or_else(invalid)?;
+    let date: Vec<&str> = date.split('-').collect();
+    let time: Vec<&str> = time.split(':').collect();
+    if date.len() != 3 || time.len() != 2 {
+        return Err(invalid());
+    }
+    let year = date[0].parse().map_err(|_| invalid())?;
+    let month: u32 = date[1].parse().map_err(|_| invalid())?;
+    let day: u32 = date[2].parse().map_err(|_| invalid())?;
+    let hours: f64 = time[0].parse().map_err(|_| invalid())?;
+    let minutes: f64 = time[1].parse().map_err(|_| invalid())?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    Ok((year, month, day, hours + minutes / 60.0))
+}
+
+// Parses a sensor size in millimetres given as `WIDTHxHEIGHT`, like `36x24`.
+fn parse_sensor(text: &str) -> Result<(f64, f64), String> {
+    let invalid = || format!("invalid sensor size, expected WIDTHxHEIGHT: {}", text);
+    let (width, height) = text.split_once('x').ok_or_else(invalid)?;
+    let width: f64 = width.trim().parse().map_err(|_| invalid())?;
+    let height: f64 = height.trim().parse().map_err(|_| invalid())?;
+    if width <= 0.0 || height <= 0.0 {
+        return Err(invalid());
+    }
+    Ok((width, height))
+}
+
+// Parses a shutter speed in seconds, either as a number or a fraction like `1/125`.
+fn parse_shutter(text: &str) -> Result<f64, String> {
+    let invalid = || {
+        format!(
+            "invalid shutter speed, expected e.g. 0.01 or 1/125: {}",
+            text
+        )
+    };
+    let seconds = match text.split_once('/') {
+        Some((numerator, denominator)) => {
+            let numerator: f64 = numerator.trim().parse().map_err(|_| invalid())?;
+            let denominator: f64 = denominator.trim().parse().map_err(|_| invalid())?;
+            numerator / denominator
+        }
+        None => text.trim().parse().map_err(|_| invalid())?,
+    };
+    if seconds > 0.0 && seconds.is_finite() {
+        Ok(seconds)
+    } else {
+        Err(invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parses options from arguments given as one string.
+    fn parse(args: &str) -> Result<Options, String> {
+        Options::parse(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn scene_files_set_options_that_later_flags_override() {
+        let path = std::env::temp_dir().join(format!("scene-{}.txt", std::process::id()));
+        std::fs::write(&path, "# Sampling\nsampler sobol\nspp 8\ncrop\n").unwrap();
+        let options = parse(&format!("--scene {} --spp 4", path.display())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(options.sampler, SamplerKind::Sobol);
+        assert_eq!(options.samples_per_pixel, 4);
+        assert!(options.crop);
+        assert!(parse("--scene /nonexistent/scene.txt").is_err());
+    }
+
+    #[test]
+    fn output_paths_default_to_the_render_mode() {
+        assert_eq!(parse("").unwrap().output, None);
+        assert_eq!(parse("-o a.png").unwrap().output.as_deref(), Some("a.png"));
+        assert_eq!(
+            parse("--output b.exr").unwrap().output.as_deref(),
+            Some("b.exr")
+        );
+        assert!(parse("--output").is_err());
+    }
+
+    #[test]
+    fn resumed_renders_cannot_write_output_variables() {
+        assert!(parse("--checkpoint c.bin --resume").is_ok());
+        for flags in ["--denoise", "--aov depth", "--aov-exr out.exr"] {
+            assert!(parse(&format!("--checkpoint c.bin --resume {}", flags)).is_err());
+        }
+    }
+
+    #[test]
+    fn effect_strengths_cannot_be_negative() {
+        assert!(parse("--bloom 1 --bloom-threshold 0 --vignette 0.5 --grain 0.1").is_ok());
+        for flags in [
+            "--bloom-threshold -1",
+            "--bloom-threshold NaN",
+            "--bloom-radius 0",
+            "--glare-length -0.1",
+            "--vignette -1",
+            "--grain -0.5",
+        ] {
+            assert!(parse(flags).is_err(), "{}", flags);
+        }
+    }
+
+    #[test]
+    fn noise_and_sky_settings_must_be_finite_and_positive() {
+        assert!(parse("--noise-target 0.01 --adaptive-threshold 0.1 --denoise-strength 2").is_ok());
+        assert!(parse("--sky --turbidity 2 --ground-albedo 0").is_ok());
+        for flag in [
+            "--noise-target",
+            "--adaptive-threshold",
+            "--turbidity",
+            "--denoise-strength",
+        ] {
+            for number in ["0", "-0.5", "NaN", "inf"] {
+                let flags = format!("{} {}", flag, number);
+                assert!(parse(&flags).is_err(), "{}", flags);
+            }
+        }
+        for number in ["-0.1", "1.5", "NaN"] {
+            assert!(
+                parse(&format!("--ground-albedo {}", number)).is_err(),
+                "{}",
+                number
+            );
+        }
+    }
+
+    #[test]
+    fn scene_hashes_follow_the_animation_contents() {
+        let temporary = |name: &str| {
+            let file = format!("{}-{}.txt", name, std::process::id());
+            std::env::temp_dir().join(file)
+        };
+        let hash = |path: &std::path::Path| {
+            let options = parse(&format!("--animation {}", path.display())).unwrap();
+            options.scene_hash()
+        };
+        let (path, copy) = (temporary("animation"), temporary("animation-copy"));
+        std::fs::write(&path, "0 camera vfov 20\n").unwrap();
+        std::fs::copy(&path, &copy).unwrap();
+        let (before, copied) = (hash(&path), hash(&copy));
+        std::fs::write(&path, "0 camera vfov 30\n").unwrap();
+        let after = hash(&path);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&copy).unwrap();
+        assert_eq!(before, copied);
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn durations_take_seconds_minutes_or_hours() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        for invalid in ["", "m", "ten", "-5s", "3d"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn regions_need_a_position_and_a_positive_size() {
+        assert_eq!(parse_region("10, 20,30,40"), Ok(Tile::new(10, 20, 30, 40)));
+        for invalid in [
+            "10,20,30",
+            "10,20,0,40",
+            "-1,20,30,40",
+            "a,b,c,d",
+            "1,2,3,4,5",
+        ] {
+            assert!(parse_region(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
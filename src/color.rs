@@ -1,6 +1,7 @@
 use crate::{interval::*, vec3::*};
+use std::hash::{Hash, Hasher};
 use std::io::Write;
-use std::ops::{Add, AddAssign, Mul};
+use std::ops::{Add, AddAssign, Mul, Sub};
 
 /// Represents a color in RGB space.
 #[derive(Debug, Copy, Clone)]
@@ -17,36 +18,39 @@ impl Color {
         Color(Vec3::new(r, g, b))
     }
 
-    /// Converts a linear color component to gamma space.
-    pub fn linear_to_gamma(linear_component: f64) -> f64 {
-        f64::sqrt(linear_component)
+    /// Returns the average of the RGB components.
+    pub fn average(&self) -> f64 {
+        (self.0.x() + self.0.y() + self.0.z()) / 3.0
     }
 
-    /// Writes a color to a stream in PPM format.
-    pub fn write_color<W: Write>(mut out: W, pixel_color: Color, samples_per_pixel: i32) {
-        let mut r: f64 = pixel_color.0.x();
-        let mut g: f64 = pixel_color.0.y();
-        let mut b: f64 = pixel_color.0.z();
-
-        let scale = 1.0 / samples_per_pixel as f64;
-        r *= scale;
-        g *= scale;
-        b *= scale;
+    /// Returns the relative luminance of a linear sRGB color.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.0.x() + 0.7152 * self.0.y() + 0.0722 * self.0.z()
+    }
 
-        r = Color::linear_to_gamma(r);
-        g = Color::linear_to_gamma(g);
-        b = Color::linear_to_gamma(b);
+    /// Writes a color, already encoded for display, to a stream in PPM format.
+    pub fn write_color<W: Write>(mut out: W, pixel_color: Color) {
+        let [r, g, b] = pixel_color.to_bytes();
+        writeln!(out, "{} {} {} ", r, g, b).unwrap();
+    }
 
+    /// Returns the 8-bit values of a color already encoded for display.
+    pub fn to_bytes(self) -> [u8; 3] {
         let intensity: Interval = Interval::new(0.000, 0.999);
-        // Write the translated [0,255] value of each color component.
-        write!(
-            out,
-            "{} {} {} \n",
-            (255.999 * intensity.clamp(r)) as i32,
-            (255.999 * intensity.clamp(g)) as i32,
-            (255.999 * intensity.clamp(b)) as i32,
-        )
-        .unwrap();
+        // The translated [0,255] value of each color component.
+        [
+            (255.999 * intensity.clamp(self.0.x())) as u8,
+            (255.999 * intensity.clamp(self.0.y())) as u8,
+            (255.999 * intensity.clamp(self.0.z())) as u8,
+        ]
+    }
+}
+
+impl Hash for Color {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for value in self.0.e {
+            value.to_bits().hash(state);
+        }
     }
 }
 
@@ -87,6 +91,19 @@ impl Add<Color> for Color {
     }
 }
 
+impl Sub<Color> for Color {
+    type Output = Color;
+
+    /// Subtracts two colors component-wise.
+    fn sub(self, other: Color) -> Color {
+        Color::new(
+            self.0.e[0] - other.0.e[0],
+            self.0.e[1] - other.0.e[1],
+            self.0.e[2] - other.0.e[2],
+        )
+    }
+}
+
 impl AddAssign for Color {
     /// Adds another color to this color.
     fn add_assign(&mut self, other: Self) {
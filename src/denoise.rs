@@ -0,0 +1,389 @@
+use crate::{aov::*, color::*, film::*, tile::*, vec3::*};
+
+/// Weights of the 5-tap B3 spline the à-trous filter is built from.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Samples below which a pixel's own variance is too unreliable, so the variance of its
+/// neighbours is used instead.
+const MIN_VARIANCE_SAMPLES: u32 = 4;
+
+/// Edge-avoiding à-trous wavelet filter removing Monte Carlo noise from a rendered image, like
+/// the spatial part of spatiotemporal variance-guided filtering (SVGF).
+///
+/// Passes of a sparse 5×5 kernel with doubling spacing blur the image, while the normal, depth
+/// and albedo of the first hit stop the blur at the edges of surfaces, and the luminance
+/// variance keeps detail that is already converged. Lighting is filtered with the albedo
+/// divided out, so textures stay sharp.
+#[derive(Debug, Copy, Clone)]
+pub struct Denoiser {
+    /// Number of filter passes; every pass doubles the reach of the filter.
+    pub iterations: u32,
+
+    /// Luminance difference still blurred between pixels, in standard deviations of their
+    /// noise.
+    pub strength: f64,
+
+    /// Exponent of the cosine between normals; higher values keep creases sharper.
+    pub normal_power: f64,
+
+    /// Depth difference still blurred between neighbouring pixels, relative to their depth.
+    pub depth_tolerance: f64,
+
+    /// Albedo difference still blurred between pixels.
+    pub albedo_tolerance: f64,
+}
+
+// What the filter knows about the surface seen in a pixel.
+struct Guide {
+    // At least 0.01 in every channel, so the albedo can be divided out.
+    albedo: Color,
+    normal: Vec3,
+    // Infinite when the pixel sees the background.
+    depth: f64,
+}
+
+impl Denoiser {
+    /// Creates a denoiser of the given strength.
+    pub fn new(strength: f64) -> Self {
+        Denoiser {
+            iterations: 5,
+            strength,
+            normal_power: 128.0,
+            depth_tolerance: 0.05,
+            albedo_tolerance: 0.1,
+        }
+    }
+
+    /// Returns the denoised colors of the pixels in `area`, in row-major order.
+    ///
+    /// The film and the output variables must cover the area, and only its pixels are blurred
+    /// together, so separate pictures in one frame stay apart.
+    pub fn denoise(&self, film: &Film, aovs: &AovFilm, area: Tile) -> Vec<Color> {
+        let guides: Vec<Guide> = area
+            .pixels()
+            .map(|(i, j)| {
+                let pixel = aovs.pixel(i, j);
+                let depth = pixel.value(Aov::Depth).x();
+                Guide {
+                    albedo: if depth.is_finite() {
+                        let albedo = pixel.value(Aov::Albedo);
+                        Color::new(
+                            albedo.x().max(0.01),
+                            albedo.y().max(0.01),
+                            albedo.z().max(0.01),
+                        )
+                    } else {
+                        Color::new(1.0, 1.0, 1.0)
+                    },
+                    normal: pixel.value(Aov::Normal),
+                    depth,
+                }
+            })
+            .collect();
+
+        // Filter the light arriving at the surfaces, without their albedo
+        let mut colors: Vec<Color> = area
+            .pixels()
+            .zip(&guides)
+            .map(|((i, j), guide)| demodulate(film.color(i, j), guide.albedo))
+            .collect();
+        let mut variances = self.initial_variances(film, area, &colors, &guides);
+
+        let (width, height) = (area.width as usize, area.height as usize);
+        for iteration in 0..self.iterations {
+            let step = 1usize << iteration;
+            let blurred = blur_variances(&variances, width, height);
+            let mut next_colors = colors.clone();
+            let mut next_variances = variances.clone();
+            for y in 0..height {
+                for x in 0..width {
+                    let p = y * width + x;
+                    let luminance = colors[p].luminance();
+                    let sigma = self.strength * blurred[p].sqrt() + 1e-6;
+                    let mut color_sum = Color::zero();
+                    let mut variance_sum = 0.0;
+                    let mut weight_sum = 0.0;
+                    for (ky, wy) in KERNEL.iter().enumerate() {
+                        let Some(qy) = offset(y, ky, step, height) else {
+                            continue;
+                        };
+                        for (kx, wx) in KERNEL.iter().enumerate() {
+                            let Some(qx) = offset(x, kx, step, width) else {
+                                continue;
+                            };
+                            let q = qy * width + qx;
+                            // The pixel itself always has weight, also where its guides are
+                            // degenerate, so the sum is positive
+                            let edges = if q == p {
+                                1.0
+                            } else {
+                                self.edge_weight(&guides[p], &guides[q], step)
+                            };
+                            let similarity =
+                                (-(luminance - colors[q].luminance()).abs() / sigma).exp();
+                            let weight = wx * wy * edges * similarity;
+                            color_sum += colors[q] * weight;
+                            variance_sum += weight * weight * variances[q];
+                            weight_sum += weight;
+                        }
+                    }
+                    next_colors[p] = color_sum * (1.0 / weight_sum);
+                    next_variances[p] = variance_sum / (weight_sum * weight_sum);
+                }
+            }
+            colors = next_colors;
+            variances = next_variances;
+        }
+
+        colors
+            .into_iter()
+            .zip(&guides)
+            .map(|(color, guide)| color * guide.albedo)
+            .collect()
+    }
+
+    /// Returns the variance of every pixel's demodulated luminance.
+    ///
+    /// Pixels with few samples take the variance between their neighbours seeing the same kind
+    /// of thing, surface or background, which assumes the light varies little over a few
+    /// pixels.
+    fn initial_variances(
+        &self,
+        film: &Film,
+        area: Tile,
+        colors: &[Color],
+        guides: &[Guide],
+    ) -> Vec<f64> {
+        let (width, height) = (area.width as usize, area.height as usize);
+        let mut variances = Vec::with_capacity(colors.len());
+        for y in 0..height {
+            for x in 0..width {
+                let p = y * width + x;
+                let stats = film.pixel(area.x + x as i32, area.y + y as i32);
+                if stats.count >= MIN_VARIANCE_SAMPLES {
+                    let albedo = guides[p].albedo.luminance();
+                    variances.push(stats.mean_variance() / (albedo * albedo));
+                    continue;
+                }
+
+                let hit = guides[p].depth.is_finite();
+                let (mut sum, mut sum_sq, mut n) = (0.0, 0.0, 0.0);
+                for qy in y.saturating_sub(3)..(y + 4).min(height) {
+                    for qx in x.saturating_sub(3)..(x + 4).min(width) {
+                        let q = qy * width + qx;
+                        if guides[q].depth.is_finite() == hit {
+                            let luminance = colors[q].luminance();
+                            sum += luminance;
+                            sum_sq += luminance * luminance;
+                            n += 1.0;
+                        }
+                    }
+                }
+                let mean = sum / n;
+                variances.push((sum_sq / n - mean * mean).max(0.0));
+            }
+        }
+        variances
+    }
+
+    /// Returns how much the surfaces seen in two pixels `step` pixels apart look alike, from 0
+    /// for different surfaces to 1.
+    fn edge_weight(&self, p: &Guide, q: &Guide, step: usize) -> f64 {
+        match (p.depth.is_finite(), q.depth.is_finite()) {
+            (false, false) => return 1.0,
+            (true, true) => {}
+            _ => return 0.0,
+        }
+        let normal = Vec3::dot(&p.normal, &q.normal)
+            .max(0.0)
+            .powf(self.normal_power);
+        let depth = (-(p.depth - q.depth).abs()
+            / (self.depth_tolerance * p.depth * step as f64 + 1e-9))
+            .exp();
+        let difference = (p.albedo - q.albedo).0;
+        let albedo =
+            (-difference.length_squared() / (self.albedo_tolerance * self.albedo_tolerance)).exp();
+        normal * depth * albedo
+    }
+}
+
+// Divides the albedo out of a color, leaving the light arriving at the surface.
+fn demodulate(color: Color, albedo: Color) -> Color {
+    Color::new(
+        color.0.x() / albedo.0.x(),
+        color.0.y() / albedo.0.y(),
+        color.0.z() / albedo.0.z(),
+    )
+}
+
+// Returns the coordinate of kernel tap `k` around `x` with taps `step` apart, if it lies inside
+// `0..size`.
+fn offset(x: usize, k: usize, step: usize, size: usize) -> Option<usize> {
+    let coordinate = x as i64 + (k as i64 - 2) * step as i64;
+    (0..size as i64)
+        .contains(&coordinate)
+        .then_some(coordinate as usize)
+}
+
+// Blurs the variances with a 3×3 Gaussian, which steadies the luminance weights.
+fn blur_variances(variances: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let weights = [0.25, 0.5, 0.25];
+    let mut blurred = vec![0.0; variances.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (mut sum, mut weight_sum) = (0.0, 0.0);
+            for (dy, wy) in weights.iter().enumerate() {
+                for (dx, wx) in weights.iter().enumerate() {
+                    let (qx, qy) = ((x + dx).wrapping_sub(1), (y + dy).wrapping_sub(1));
+                    if qx < width && qy < height {
+                        sum += wx * wy * variances[qy * width + qx];
+                        weight_sum += wx * wy;
+                    }
+                }
+            }
+            blurred[y * width + x] = sum / weight_sum;
+        }
+    }
+    blurred
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtweekend::*;
+
+    // Adds `samples` noisy samples around `color` to a pixel seeing a surface with the given
+    // albedo and normal at depth 1.
+    fn expose(
+        (film, aovs): (&mut Film, &mut AovFilm),
+        (i, j): (i32, i32),
+        color: f64,
+        (albedo, normal): (f64, Vec3),
+        rng: &mut Rng,
+    ) {
+        let path = PathRecord {
+            bounces: 1,
+            first_hit: Some(FirstHit {
+                albedo: Color::new(albedo, albedo, albedo),
+                normal,
+                position: Point3::zero(),
+                depth: 1.0,
+                object_id: 0,
+                material: None,
+                specular: false,
+            }),
+        };
+        for _ in 0..8 {
+            let sample = color * random_double_range(rng, 0.5, 1.5);
+            let sample = Color::new(sample, sample, sample);
+            film.pixel_mut(i, j).add(sample);
+            aovs.pixel_mut(i, j).add(&path, sample);
+        }
+    }
+
+    // Returns a 16 by 16 film whose left half sees a surface facing the camera in light 0.5
+    // and whose right half sees one facing sideways in light 0.2.
+    fn two_walls(seed: u64) -> (Film, AovFilm) {
+        let area = Tile::new(0, 0, 16, 16);
+        let (mut film, mut aovs) = (Film::new(area), AovFilm::new(area));
+        let mut rng = Rng::new(seed);
+        for (i, j) in area.pixels() {
+            let (color, normal) = if i < 8 {
+                (0.5, Vec3::new(0.0, 0.0, 1.0))
+            } else {
+                (0.2, Vec3::new(1.0, 0.0, 0.0))
+            };
+            expose(
+                (&mut film, &mut aovs),
+                (i, j),
+                color,
+                (1.0, normal),
+                &mut rng,
+            );
+        }
+        (film, aovs)
+    }
+
+    // Returns the mean and variance of the luminance of the colors in columns `x0..x1` of a
+    // 16 pixels wide image.
+    fn column_stats(colors: &[Color], x0: usize, x1: usize) -> (f64, f64) {
+        let values: Vec<f64> = colors
+            .iter()
+            .enumerate()
+            .filter(|(p, _)| (x0..x1).contains(&(p % 16)))
+            .map(|(_, color)| color.luminance())
+            .collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance =
+            values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64;
+        (mean, variance)
+    }
+
+    #[test]
+    fn noise_drops_on_flat_surfaces_while_edges_stay_sharp() {
+        let (film, aovs) = two_walls(3);
+        let area = film.area;
+        let noisy: Vec<Color> = area.pixels().map(|(i, j)| film.color(i, j)).collect();
+        // Strong enough that only the guides keep the walls apart
+        let denoised = Denoiser::new(100.0).denoise(&film, &aovs, area);
+
+        let (_, noisy_variance) = column_stats(&noisy, 0, 6);
+        let (mean, variance) = column_stats(&denoised, 0, 6);
+        assert!(
+            variance < 0.1 * noisy_variance,
+            "{} vs {}",
+            variance,
+            noisy_variance
+        );
+        assert!((mean - 0.5).abs() < 0.02, "{}", mean);
+
+        let (left, _) = column_stats(&denoised, 7, 8);
+        let (right, _) = column_stats(&denoised, 8, 9);
+        assert!((left - 0.5).abs() < 0.03, "{}", left);
+        assert!((right - 0.2).abs() < 0.03, "{}", right);
+    }
+
+    #[test]
+    fn pixels_outside_the_area_are_left_out() {
+        let (film, aovs) = two_walls(5);
+        let left = Tile::new(0, 0, 8, 16);
+        let denoised = Denoiser::new(4.0).denoise(&film, &aovs, left);
+        assert_eq!(denoised.len(), 8 * 16);
+
+        // Brighter light on the right wall does not bleed into the left one
+        let (mut brighter, mut brighter_aovs) = two_walls(5);
+        let mut rng = Rng::new(9);
+        for (i, j) in Tile::new(8, 0, 8, 16).pixels() {
+            let surface = (1.0, Vec3::new(0.0, 0.0, 1.0));
+            expose(
+                (&mut brighter, &mut brighter_aovs),
+                (i, j),
+                50.0,
+                surface,
+                &mut rng,
+            );
+        }
+        let again = Denoiser::new(4.0).denoise(&brighter, &brighter_aovs, left);
+        for (a, b) in denoised.iter().zip(&again) {
+            assert_eq!(a.0.e, b.0.e);
+        }
+    }
+
+    #[test]
+    fn pixels_without_a_normal_stay_finite() {
+        // Samples of opposite normals cancel out, leaving a hit without a normal
+        let area = Tile::new(0, 0, 3, 3);
+        let (mut film, mut aovs) = (Film::new(area), AovFilm::new(area));
+        let mut rng = Rng::new(1);
+        for (i, j) in area.pixels() {
+            for z in [1.0, -1.0] {
+                let surface = (0.5, Vec3::new(0.0, 0.0, z));
+                expose((&mut film, &mut aovs), (i, j), 0.3, surface, &mut rng);
+            }
+        }
+        assert_eq!(aovs.pixel(1, 1).value(Aov::Normal).e, [0.0; 3]);
+        for color in Denoiser::new(4.0).denoise(&film, &aovs, area) {
+            assert!(color.0.e.iter().all(|c| c.is_finite()), "{:?}", color);
+        }
+    }
+}
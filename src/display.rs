@@ -0,0 +1,326 @@
+use crate::color::*;
+
+/// Row-major 3×3 matrix converting between RGB color spaces.
+type Matrix = [[f64; 3]; 3];
+
+/// Linear sRGB to ACEScg, adapting the white point from D65 to D60 with the Bradford
+/// transform.
+const SRGB_TO_ACESCG: Matrix = [
+    [0.6130974, 0.3395231, 0.0473795],
+    [0.0701937, 0.9163539, 0.0134524],
+    [0.0206156, 0.1095698, 0.8698146],
+];
+
+/// ACEScg to linear sRGB, the inverse of `SRGB_TO_ACESCG`.
+const ACESCG_TO_SRGB: Matrix = [
+    [1.7050510, -0.6217921, -0.0832589],
+    [-0.1302564, 1.1408047, -0.0105483],
+    [-0.0240034, -0.1289690, 1.1529723],
+];
+
+/// Linear sRGB to the color space of the fitted ACES transform, with its exposure.
+const ACES_INPUT: Matrix = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+
+/// Fitted ACES color space back to linear sRGB.
+const ACES_OUTPUT: Matrix = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+/// Linear sRGB to the inset primaries AgX compresses in.
+const AGX_INSET: Matrix = [
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104],
+];
+
+/// AgX primaries back to linear sRGB, the inverse of `AGX_INSET`.
+const AGX_OUTSET: Matrix = [
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+];
+
+// Multiplies a color by a matrix.
+fn transform(m: &Matrix, c: Color) -> Color {
+    let [r, g, b] = c.0.e;
+    Color::new(
+        m[0][0] * r + m[0][1] * g + m[0][2] * b,
+        m[1][0] * r + m[1][1] * g + m[1][2] * b,
+        m[2][0] * r + m[2][1] * g + m[2][2] * b,
+    )
+}
+
+/// RGB color space in which rendering multiplies colors.
+#[derive(Debug, Copy, Clone, PartialEq, Hash)]
+pub enum ColorSpace {
+    /// Linear values with the sRGB (Rec. 709) primaries and D65 white.
+    LinearSrgb,
+    /// Linear values with the wider ACES AP1 primaries and D60 white.
+    AcesCg,
+}
+
+impl ColorSpace {
+    /// Parses a color space name as used on the command line.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "srgb" | "linear-srgb" => Some(ColorSpace::LinearSrgb),
+            "acescg" => Some(ColorSpace::AcesCg),
+            _ => None,
+        }
+    }
+
+    /// Converts a linear sRGB color, as scene colors are given, to this space.
+    pub fn convert_from_srgb(self, color: Color) -> Color {
+        match self {
+            ColorSpace::LinearSrgb => color,
+            ColorSpace::AcesCg => transform(&SRGB_TO_ACESCG, color),
+        }
+    }
+
+    /// Converts a color in this space to linear sRGB.
+    pub fn convert_to_srgb(self, color: Color) -> Color {
+        match self {
+            ColorSpace::LinearSrgb => color,
+            ColorSpace::AcesCg => transform(&ACESCG_TO_SRGB, color),
+        }
+    }
+
+    /// Returns the CIE xy chromaticities of the red, green and blue primaries and the white
+    /// point.
+    pub fn chromaticities(self) -> [(f64, f64); 4] {
+        match self {
+            ColorSpace::LinearSrgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), (0.3127, 0.3290)],
+            ColorSpace::AcesCg => [
+                (0.713, 0.293),
+                (0.165, 0.830),
+                (0.128, 0.044),
+                (0.32168, 0.33767),
+            ],
+        }
+    }
+}
+
+/// Curve compressing scene radiance into the range a display shows.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMapper {
+    /// Clips values above one.
+    Clamp,
+    /// Reinhard's operator on luminance, keeping hues.
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms.
+    Aces,
+    /// Troy Sobotka's AgX, which desaturates bright colors towards white.
+    Agx,
+    /// John Hable's filmic curve from Uncharted 2.
+    Uncharted2,
+}
+
+impl ToneMapper {
+    /// Parses a tone mapper name as used on the command line.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "clamp" | "none" => Some(ToneMapper::Clamp),
+            "reinhard" => Some(ToneMapper::Reinhard),
+            "aces" => Some(ToneMapper::Aces),
+            "agx" => Some(ToneMapper::Agx),
+            "uncharted2" | "hable" => Some(ToneMapper::Uncharted2),
+            _ => None,
+        }
+    }
+
+    /// Maps a linear sRGB color to linear display values between zero and one.
+    ///
+    /// The fitted curves overshoot one a little for bright or saturated colors, and Reinhard's
+    /// operator much more for saturated colors, so the result is clipped to the display range.
+    pub fn apply(self, color: Color) -> Color {
+        let mapped = match self {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => color * (1.0 / (1.0 + color.luminance().max(0.0))),
+            ToneMapper::Aces => {
+                let v = transform(&ACES_INPUT, color);
+                let fit = |v: f64| {
+                    (v * (v + 0.0245786) - 0.000090537)
+                        / (v * (0.983729 * v + 0.4329510) + 0.238081)
+                };
+                transform(&ACES_OUTPUT, map(v, fit))
+            }
+            ToneMapper::Agx => {
+                // Compress the log-encoded inset color with AgX's sigmoid, then undo the
+                // sigmoid's built-in 2.2 display gamma
+                const MIN_EV: f64 = -12.47393;
+                const MAX_EV: f64 = 4.026069;
+                let v = transform(&AGX_INSET, color);
+                let sigmoid = |v: f64| {
+                    let x =
+                        (v.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+                    let x2 = x * x;
+                    let x4 = x2 * x2;
+                    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x
+                        + 0.4298 * x2
+                        + 0.1191 * x
+                        - 0.00232
+                };
+                let v = transform(&AGX_OUTSET, map(v, sigmoid));
+                map(v, |v| v.max(0.0).powf(2.2))
+            }
+            ToneMapper::Uncharted2 => {
+                let curve = |x: f64| {
+                    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+                    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+                };
+                // Linear white point, and the exposure bias the curve was made for
+                let white_scale = 1.0 / curve(11.2);
+                map(color, |v| curve(2.0 * v.max(0.0)) * white_scale)
+            }
+        };
+        map(mapped, |v| v.clamp(0.0, 1.0))
+    }
+}
+
+// Applies a function to every channel of a color.
+fn map<F: Fn(f64) -> f64>(color: Color, f: F) -> Color {
+    Color::new(f(color.0.x()), f(color.0.y()), f(color.0.z()))
+}
+
+/// Encodes a linear value between zero and one with the sRGB transfer function.
+pub fn srgb_oetf(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Turns rendered radiance into the values written to 8-bit images.
+#[derive(Debug, Copy, Clone)]
+pub struct DisplayTransform {
+    /// Exposure adjustment in stops, scaling radiance by a power of two.
+    pub exposure: f64,
+
+    /// Curve compressing radiance into the display range.
+    pub tone_mapper: ToneMapper,
+
+    /// Color space the rendered radiance is in.
+    pub working_space: ColorSpace,
+}
+
+impl DisplayTransform {
+    /// Creates a transform clipping linear sRGB, without exposure adjustment.
+    pub fn default() -> Self {
+        DisplayTransform {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::Clamp,
+            working_space: ColorSpace::LinearSrgb,
+        }
+    }
+
+    /// Maps rendered radiance in the working space to sRGB encoded display values between zero
+    /// and one: exposure, then the tone mapper, then the sRGB transfer function.
+    pub fn apply(&self, radiance: Color) -> Color {
+        let exposed = radiance * 2f64.powf(self.exposure);
+        let mapped = self
+            .tone_mapper
+            .apply(self.working_space.convert_to_srgb(exposed));
+        map(mapped, |v| srgb_oetf(v.clamp(0.0, 1.0)))
+    }
+
+    /// Encodes a working space color that needs no tone mapping, such as an albedo, for
+    /// display.
+    pub fn encode(&self, color: Color) -> Color {
+        map(self.working_space.convert_to_srgb(color), |v| {
+            srgb_oetf(v.clamp(0.0, 1.0))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the product of two matrices.
+    fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+        let mut product = [[0.0; 3]; 3];
+        for (i, row) in product.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+            }
+        }
+        product
+    }
+
+    #[test]
+    fn inverse_matrices_give_the_identity() {
+        for (m, inverse) in [(SRGB_TO_ACESCG, ACESCG_TO_SRGB), (AGX_INSET, AGX_OUTSET)] {
+            for product in [multiply(&m, &inverse), multiply(&inverse, &m)] {
+                for (i, row) in product.iter().enumerate() {
+                    for (j, value) in row.iter().enumerate() {
+                        let identity = if i == j { 1.0 } else { 0.0 };
+                        assert!((value - identity).abs() < 1e-5, "{:?}", product);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_oetf_is_continuous_and_keeps_white() {
+        let knee = 0.0031308;
+        let below = srgb_oetf(knee);
+        let above = srgb_oetf(knee + 1e-12);
+        assert!((below - above).abs() < 1e-6, "{} vs {}", below, above);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-12);
+        assert_eq!(srgb_oetf(0.0), 0.0);
+    }
+
+    #[test]
+    fn tone_mappers_are_monotonic_within_the_display_range() {
+        // Exposures from far below black to far above white
+        let exposures: Vec<f64> = (-80..=80)
+            .map(|stop| 2f64.powf(stop as f64 / 4.0))
+            .collect();
+        for tone_mapper in [
+            ToneMapper::Clamp,
+            ToneMapper::Reinhard,
+            ToneMapper::Aces,
+            ToneMapper::Agx,
+            ToneMapper::Uncharted2,
+        ] {
+            let mut previous = tone_mapper.apply(Color::zero());
+            for &exposure in &exposures {
+                let gray = tone_mapper.apply(Color::new(exposure, exposure, exposure));
+                assert!(
+                    gray.0.x() >= previous.0.x(),
+                    "{:?} at {}",
+                    tone_mapper,
+                    exposure
+                );
+                previous = gray;
+            }
+            assert!(previous.0.x() > 0.99, "{:?}", tone_mapper);
+
+            // Saturated colors too stay displayable
+            for tint in [
+                Color::new(1.0, 0.0, 0.0),
+                Color::new(0.0, 1.0, 0.0),
+                Color::new(0.0, 0.0, 1.0),
+                Color::new(1.0, 0.5, 0.1),
+            ] {
+                for &exposure in &exposures {
+                    let mapped = tone_mapper.apply(tint * exposure);
+                    assert!(
+                        mapped.0.e.iter().all(|v| (0.0..=1.0).contains(v)),
+                        "{:?} at {}",
+                        tone_mapper,
+                        exposure
+                    );
+                }
+            }
+        }
+    }
+}
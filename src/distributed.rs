@@ -0,0 +1,517 @@
+use crate::{film::*, tile::*};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Identifies the renderer's protocol at the start of every connection.
+const PROTOCOL_MAGIC: &[u8; 8] = b"RTDIST02";
+
+/// Stream between the coordinator and a worker, over TCP or, on Unix, a Unix socket.
+///
+/// Addresses starting with `unix:` name a Unix socket path; any other address is a TCP
+/// `host:port`.
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    /// Connects to a coordinator, retrying for up to `timeout` while it is not listening yet.
+    pub fn connect(address: &str, timeout: Duration) -> std::io::Result<Self> {
+        let start = Instant::now();
+        loop {
+            let result = match address.strip_prefix("unix:") {
+                #[cfg(unix)]
+                Some(path) => UnixStream::connect(path).map(Connection::Unix),
+                #[cfg(not(unix))]
+                Some(_) => return Err(unix_sockets_unsupported()),
+                None => TcpStream::connect(address).map(Connection::Tcp),
+            };
+            match result {
+                Err(_) if start.elapsed() < timeout => {
+                    std::thread::sleep(Duration::from_millis(200));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Makes reads and writes fail with `WouldBlock` or `TimedOut` once they wait longer than
+    /// `timeout`.
+    pub fn set_timeout(&self, timeout: Duration) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+// Error for `unix:` addresses on platforms without Unix sockets.
+#[cfg(not(unix))]
+fn unix_sockets_unsupported() -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        "Unix sockets are not supported on this platform; use a TCP address",
+    )
+}
+
+/// Socket on which the coordinator accepts workers.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Listens on a TCP `host:port` or a `unix:` socket path, returning the listener and,
+    /// for a Unix socket, the guard removing its file when dropped.
+    ///
+    /// A stale Unix socket left by an earlier coordinator is replaced.
+    pub fn bind(address: &str) -> std::io::Result<(Self, Option<SocketFile>)> {
+        match address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => {
+                let stale = std::fs::symlink_metadata(path)
+                    .is_ok_and(|metadata| metadata.file_type().is_socket());
+                if stale {
+                    std::fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                Ok((Listener::Unix(listener), Some(SocketFile(path.into()))))
+            }
+            #[cfg(not(unix))]
+            Some(_) => Err(unix_sockets_unsupported()),
+            None => Ok((Listener::Tcp(TcpListener::bind(address)?), None)),
+        }
+    }
+
+    /// Waits for the next worker to connect.
+    pub fn accept(&self) -> std::io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Ok(Connection::Tcp(stream))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => Ok(Connection::Unix(listener.accept()?.0)),
+        }
+    }
+}
+
+/// File of a Unix socket the coordinator listens on, removed when the guard is dropped.
+pub struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Sampling settings sent with every job, so all workers sample alike.
+#[derive(Debug, Copy, Clone)]
+pub struct JobSettings {
+    /// Samples every pixel takes.
+    pub base_samples: u32,
+
+    /// Upper limit of samples per pixel for adaptive sampling.
+    pub max_samples: u32,
+
+    /// Relative error below which adaptive sampling stops.
+    pub adaptive_threshold: f64,
+}
+
+/// Message from the coordinator to a worker.
+#[derive(Debug, Copy, Clone)]
+pub enum Job {
+    /// Render a tile and send back its film.
+    Render { tile: Tile, settings: JobSettings },
+    /// No work is left; the worker can exit.
+    Finish,
+    /// The worker's scene differs from the coordinator's.
+    Reject,
+}
+
+/// Work counters a worker sends back with every tile.
+#[derive(Debug, Copy, Clone)]
+pub struct WorkCounters {
+    pub camera_rays: u64,
+    pub rays_traced: u64,
+    pub intersection_tests: u64,
+    pub nodes_visited: u64,
+}
+
+/// Sends the greeting opening a worker's connection, identifying its scene by `key`.
+pub fn write_hello<W: Write>(output: &mut W, key: u64) -> std::io::Result<()> {
+    output.write_all(PROTOCOL_MAGIC)?;
+    output.write_all(&key.to_le_bytes())?;
+    output.flush()
+}
+
+/// Reads a worker's greeting and returns its scene key.
+pub fn read_hello<R: Read>(input: &mut R) -> std::io::Result<u64> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != PROTOCOL_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a renderer worker"));
+    }
+    read_u64(input)
+}
+
+/// Sends a job to a worker.
+pub fn write_job<W: Write>(output: &mut W, job: &Job) -> std::io::Result<()> {
+    // Messages are assembled first so each goes out in one write
+    let mut message = Vec::new();
+    match job {
+        Job::Render { tile, settings } => {
+            message.push(1);
+            for value in [tile.x, tile.y, tile.width, tile.height] {
+                message.extend_from_slice(&value.to_le_bytes());
+            }
+            message.extend_from_slice(&settings.base_samples.to_le_bytes());
+            message.extend_from_slice(&settings.max_samples.to_le_bytes());
+            message.extend_from_slice(&settings.adaptive_threshold.to_le_bytes());
+        }
+        Job::Finish => message.push(0),
+        Job::Reject => message.push(2),
+    }
+    output.write_all(&message)?;
+    output.flush()
+}
+
+/// Reads the next job from the coordinator, for a tile within `frame`.
+pub fn read_job<R: Read>(input: &mut R, frame: &Tile) -> std::io::Result<Job> {
+    let mut tag = [0; 1];
+    input.read_exact(&mut tag)?;
+    match tag[0] {
+        0 => Ok(Job::Finish),
+        1 => {
+            let mut values = [0; 4];
+            for value in &mut values {
+                *value = read_u32(input)? as i32;
+            }
+            let [x, y, width, height] = values;
+            let tile = checked_area(Tile::new(x, y, width, height), frame)?;
+            let settings = JobSettings {
+                base_samples: read_u32(input)?,
+                max_samples: read_u32(input)?,
+                adaptive_threshold: f64::from_bits(read_u64(input)?),
+            };
+            Ok(Job::Render { tile, settings })
+        }
+        2 => Ok(Job::Reject),
+        _ => Err(Error::new(ErrorKind::InvalidData, "unknown job")),
+    }
+}
+
+/// Sends a rendered tile and the work it took back to the coordinator.
+pub fn write_result<W: Write>(
+    output: &mut W,
+    film: &Film,
+    counters: &WorkCounters,
+) -> std::io::Result<()> {
+    let mut message = Vec::new();
+    film.write_pixels(&mut message)?;
+    message.extend_from_slice(&counters.camera_rays.to_le_bytes());
+    message.extend_from_slice(&counters.rays_traced.to_le_bytes());
+    message.extend_from_slice(&counters.intersection_tests.to_le_bytes());
+    message.extend_from_slice(&counters.nodes_visited.to_le_bytes());
+    output.write_all(&message)?;
+    output.flush()
+}
+
+/// Reads the result of a job for `tile` from a worker, whose splats must lie within `frame`.
+pub fn read_result<R: Read>(
+    input: &mut R,
+    tile: Tile,
+    frame: &Tile,
+) -> std::io::Result<(Film, WorkCounters)> {
+    // Read the message in a few large parts rather than value by value from the socket: the
+    // pixels and the splat marker, the area and values of the splats if any, and the counters
+    let mut film = Film::new(tile);
+    let mut message = vec![0; film.pixels.len() * PixelStats::ENCODED_SIZE + 1];
+    input.read_exact(&mut message)?;
+    if message.last() == Some(&1) {
+        let mut area = [0; 16];
+        input.read_exact(&mut area)?;
+        let value = |k: usize| i32::from_le_bytes([area[k], area[k + 1], area[k + 2], area[k + 3]]);
+        let splat_area = Tile::new(value(0), value(4), value(8), value(12));
+        let splat_area = checked_area(splat_area, frame)?;
+        message.extend_from_slice(&area);
+        let pixels = splat_area.width as usize * splat_area.height as usize;
+        read_more(input, &mut message, pixels * Splat::ENCODED_SIZE)?;
+    }
+    read_more(input, &mut message, 32)?;
+    let mut message = message.as_slice();
+    film.read_pixels(&mut message)?;
+    let counters = WorkCounters {
+        camera_rays: read_u64(&mut message)?,
+        rays_traced: read_u64(&mut message)?,
+        intersection_tests: read_u64(&mut message)?,
+        nodes_visited: read_u64(&mut message)?,
+    };
+    Ok((film, counters))
+}
+
+// Returns `area` if it is a non-empty part of `frame`, so the pixels it holds can be allocated,
+// and an `InvalidData` error otherwise.
+fn checked_area(area: Tile, frame: &Tile) -> std::io::Result<Tile> {
+    if area.width > 0 && area.height > 0 && area.intersect(frame) == Some(area) {
+        Ok(area)
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("area {:?} lies outside the frame", area),
+        ))
+    }
+}
+
+// Reads `count` more bytes onto the end of `message`.
+fn read_more<R: Read>(input: &mut R, message: &mut Vec<u8>, count: usize) -> std::io::Result<()> {
+    let start = message.len();
+    message.resize(start + count, 0);
+    input.read_exact(&mut message[start..])
+}
+
+/// Tiles waiting to be rendered and the number being rendered by workers.
+pub struct JobQueue {
+    tiles: VecDeque<Tile>,
+    in_flight: usize,
+}
+
+impl JobQueue {
+    /// Creates a queue of tiles to render.
+    pub fn new(tiles: VecDeque<Tile>) -> Self {
+        JobQueue {
+            tiles,
+            in_flight: 0,
+        }
+    }
+}
+
+/// Result of a job: the rendered tile and the work it took.
+pub type JobResult = (Film, WorkCounters);
+
+/// How the coordinator serves workers.
+#[derive(Debug, Copy, Clone)]
+pub struct ServeSettings {
+    /// Identifies the coordinator's scene; workers with another key are rejected.
+    pub key: u64,
+
+    /// Sampling settings sent with every job.
+    pub job: JobSettings,
+
+    /// Longest a worker may take to answer, after which its tile goes to another worker.
+    pub timeout: Duration,
+
+    /// Part of the image the tiles and their splats lie in.
+    pub frame: Tile,
+}
+
+/// Accepts workers forever, serving each from its own thread.
+pub fn accept_workers(
+    listener: Listener,
+    settings: ServeSettings,
+    queue: Arc<Mutex<JobQueue>>,
+    results: Sender<JobResult>,
+) {
+    // Failing accepts, such as when out of file descriptors, are retried less and less often
+    let mut backoff = Duration::from_millis(10);
+    loop {
+        let connection = match listener.accept() {
+            Ok(connection) => connection,
+            Err(err) => {
+                eprintln!("warning: cannot accept worker: {}", err);
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(Duration::from_secs(1));
+                continue;
+            }
+        };
+        backoff = Duration::from_millis(10);
+        let queue = queue.clone();
+        let results = results.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = serve_worker(connection, settings, &queue, &results) {
+                eprintln!("warning: lost worker: {}", err);
+            }
+        });
+    }
+}
+
+// Hands out tiles to one worker until none are left, sending the results to the coordinator.
+// A tile whose worker fails or stalls is put back in the queue for another worker.
+fn serve_worker(
+    mut connection: Connection,
+    settings: ServeSettings,
+    queue: &Mutex<JobQueue>,
+    results: &Sender<JobResult>,
+) -> std::io::Result<()> {
+    connection.set_timeout(settings.timeout)?;
+    if read_hello(&mut connection)? != settings.key {
+        write_job(&mut connection, &Job::Reject)?;
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "worker renders a different scene",
+        ));
+    }
+
+    loop {
+        // Wait while other workers may still fail and return their tiles
+        let tile = loop {
+            let mut jobs = queue.lock().unwrap();
+            if let Some(tile) = jobs.tiles.pop_front() {
+                jobs.in_flight += 1;
+                break Some(tile);
+            }
+            if jobs.in_flight == 0 {
+                break None;
+            }
+            drop(jobs);
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        let Some(tile) = tile else {
+            return write_job(&mut connection, &Job::Finish);
+        };
+
+        let job = Job::Render {
+            tile,
+            settings: settings.job,
+        };
+        let result = write_job(&mut connection, &job)
+            .and_then(|_| read_result(&mut connection, tile, &settings.frame));
+        let mut jobs = queue.lock().unwrap();
+        jobs.in_flight -= 1;
+        match result {
+            Ok(result) => {
+                // The coordinator may have stopped listening after a cancel
+                let _ = results.send(result);
+            }
+            Err(err) => {
+                jobs.tiles.push_back(tile);
+                return Err(err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encodes a render job for a tile given by its position and size.
+    fn job_message(tile: [i32; 4]) -> Vec<u8> {
+        let job = Job::Render {
+            tile: Tile::new(tile[0], tile[1], tile[2], tile[3]),
+            settings: JobSettings {
+                base_samples: 4,
+                max_samples: 16,
+                adaptive_threshold: 0.05,
+            },
+        };
+        let mut message = Vec::new();
+        write_job(&mut message, &job).unwrap();
+        message
+    }
+
+    #[test]
+    fn jobs_round_trip() {
+        let frame = Tile::new(0, 0, 100, 50);
+        let message = job_message([10, 20, 8, 8]);
+        let Job::Render { tile, settings } = read_job(&mut message.as_slice(), &frame).unwrap()
+        else {
+            panic!("expected a render job");
+        };
+        assert_eq!(tile, Tile::new(10, 20, 8, 8));
+        assert_eq!((settings.base_samples, settings.max_samples), (4, 16));
+        assert_eq!(settings.adaptive_threshold, 0.05);
+    }
+
+    #[test]
+    fn jobs_outside_the_frame_are_invalid() {
+        let frame = Tile::new(0, 0, 100, 50);
+        for tile in [
+            [0, 0, 0, 8],
+            [0, 0, 8, -8],
+            [96, 0, 8, 8],
+            [0, 0, i32::MAX, i32::MAX],
+        ] {
+            let error = read_job(&mut job_message(tile).as_slice(), &frame).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{:?}", tile);
+        }
+    }
+
+    #[test]
+    fn results_with_splats_outside_the_frame_are_invalid() {
+        let frame = Tile::new(0, 0, 100, 50);
+        let tile = Tile::new(0, 0, 2, 2);
+        let counters = WorkCounters {
+            camera_rays: 1,
+            rays_traced: 2,
+            intersection_tests: 3,
+            nodes_visited: 4,
+        };
+        let film = Film::new(tile).with_splats(Tile::new(0, 0, 3, 3));
+        let mut message = Vec::new();
+        write_result(&mut message, &film, &counters).unwrap();
+        let (read, read_counters) = read_result(&mut message.as_slice(), tile, &frame).unwrap();
+        assert_eq!(read.splats.unwrap().area, Tile::new(0, 0, 3, 3));
+        assert_eq!(read_counters.intersection_tests, 3);
+        assert_eq!(read_counters.nodes_visited, 4);
+
+        // A huge splat area is refused before anything is allocated for it
+        let huge = Film::new(tile).with_splats(Tile::new(0, 0, 1, 1));
+        let mut message = Vec::new();
+        write_result(&mut message, &huge, &counters).unwrap();
+        let area_start = tile.width as usize * tile.height as usize * PixelStats::ENCODED_SIZE + 1;
+        for (k, value) in [0i32, 0, 1 << 20, 1 << 20].into_iter().enumerate() {
+            let at = area_start + 4 * k;
+            message[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        let result = read_result(&mut message.as_slice(), tile, &frame);
+        assert!(result.is_err_and(|error| error.kind() == ErrorKind::InvalidData));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{color::*, hdr_image::*, rtweekend::*, vec3::*};
+use std::f64::consts::PI;
+
+/// Light arriving from infinitely far away, seen by rays that miss the scene.
+pub trait Environment {
+    /// Returns the radiance arriving from the given direction.
+    fn radiance(&self, direction: &Vec3) -> Color;
+
+    /// Returns true if the environment can importance sample its light.
+    fn is_sampled(&self) -> bool {
+        false
+    }
+
+    /// Samples a unit direction roughly proportionally to the light arriving from it.
+    fn sample_direction(&self, rng: &mut Rng) -> Vec3 {
+        Vec3::random_unit_vector(rng)
+    }
+
+    /// Returns the solid-angle density with which `sample_direction` picks a direction.
+    fn pdf_value(&self, _direction: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+/// Vertical blend between two colors, the classic sky background.
+pub struct Gradient {
+    // Color seen looking straight down.
+    bottom: Color,
+    // Color seen looking straight up.
+    top: Color,
+}
+
+impl Gradient {
+    /// Creates a new gradient from the bottom and top colors.
+    pub fn new(bottom: Color, top: Color) -> Self {
+        Gradient { bottom, top }
+    }
+}
+
+impl Environment for Gradient {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let unit_direction = Vec3::unit_vector(*direction);
+        let a = 0.5 * (unit_direction.y() + 1.0);
+        self.bottom * (1.0 - a) + self.top * a
+    }
+}
+
+/// Equirectangular (latitude-longitude) HDR image surrounding the scene.
+///
+/// Directions are importance sampled by pixel luminance, so small bright features like the sun
+/// are found by diffuse bounces instead of by chance.
+pub struct EnvironmentMap {
+    // Radiance values, with +y at the top row and -z at the center column.
+    image: HdrImage,
+    // Rotation about the vertical axis in radians.
+    rotation: f64,
+    // Scale applied to every radiance value.
+    intensity: f64,
+    // Cumulative distribution over rows, with `height + 1` entries.
+    marginal_cdf: Vec<f64>,
+    // Cumulative distribution over columns for each row, with `width + 1` entries per row.
+    conditional_cdf: Vec<f64>,
+    // Sum of all sampling weights.
+    total_weight: f64,
+}
+
+impl EnvironmentMap {
+    /// Creates a new environment map from an equirectangular image.
+    pub fn new(image: HdrImage) -> Self {
+        let (width, height) = (image.width, image.height);
+        let mut conditional_cdf = Vec::with_capacity(height * (width + 1));
+        let mut marginal_cdf = Vec::with_capacity(height + 1);
+        marginal_cdf.push(0.0);
+
+        // Weight each pixel by its luminance and the solid angle it covers
+        for y in 0..height {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            let mut sum = 0.0;
+            conditional_cdf.push(0.0);
+            for x in 0..width {
+                sum += image.get(x, y).luminance().max(0.0) * sin_theta;
+                conditional_cdf.push(sum);
+            }
+            let row_total = *marginal_cdf.last().unwrap() + sum;
+            marginal_cdf.push(row_total);
+        }
+        let total_weight = *marginal_cdf.last().unwrap();
+
+        EnvironmentMap {
+            image,
+            rotation: 0.0,
+            intensity: 1.0,
+            marginal_cdf,
+            conditional_cdf,
+            total_weight,
+        }
+    }
+
+    /// Loads an environment map from a `.hdr` or `.exr` file.
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        Ok(EnvironmentMap::new(HdrImage::load(path)?))
+    }
+
+    /// Returns a copy of the map rotated about the vertical axis by the given angle in degrees.
+    pub fn with_rotation(mut self, degrees: f64) -> Self {
+        self.rotation = degrees_to_radians(degrees);
+        self
+    }
+
+    /// Returns a copy of the map with its radiance scaled by the given factor.
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    // Maps a world direction to image coordinates in [0, 1)².
+    fn direction_to_uv(&self, direction: &Vec3) -> (f64, f64) {
+        let d = Vec3::unit_vector(*direction);
+        let phi = d.x().atan2(-d.z()) - self.rotation;
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        (u, theta / PI)
+    }
+
+    // Maps image coordinates in [0, 1)² back to a world direction.
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let phi = 2.0 * PI * u + self.rotation;
+        let theta = PI * v;
+        Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+
+    // Returns the pixel containing the image coordinates.
+    fn pixel(&self, u: f64, v: f64) -> (usize, usize) {
+        let x = ((u * self.image.width as f64) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as f64) as usize).min(self.image.height - 1);
+        (x, y)
+    }
+}
+
+// Finds the interval of a cumulative distribution containing `value`.
+fn sample_cdf(cdf: &[f64], value: f64) -> usize {
+    let index = cdf.partition_point(|&c| c <= value);
+    index.clamp(1, cdf.len() - 1) - 1
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let (u, v) = self.direction_to_uv(direction);
+        let (x, y) = self.pixel(u, v);
+        self.image.get(x, y) * self.intensity
+    }
+
+    fn is_sampled(&self) -> bool {
+        self.total_weight > 0.0
+    }
+
+    fn sample_direction(&self, rng: &mut Rng) -> Vec3 {
+        let width = self.image.width;
+        let y = sample_cdf(&self.marginal_cdf, random_double(rng) * self.total_weight);
+        let row = &self.conditional_cdf[y * (width + 1)..(y + 1) * (width + 1)];
+        let x = sample_cdf(row, random_double(rng) * row[width]);
+
+        // Pick a uniformly random point inside the chosen pixel
+        let u = (x as f64 + random_double(rng)) / width as f64;
+        let v = (y as f64 + random_double(rng)) / self.image.height as f64;
+        self.uv_to_direction(u, v)
+    }
+
+    fn pdf_value(&self, direction: &Vec3) -> f64 {
+        let (u, v) = self.direction_to_uv(direction);
+        let (x, y) = self.pixel(u, v);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        // Density over the unit square, converted to solid angle
+        let width = self.image.width;
+        let row = &self.conditional_cdf[y * (width + 1)..(y + 1) * (width + 1)];
+        let weight = row[x + 1] - row[x];
+        let pixel_count = (width * self.image.height) as f64;
+        let pdf_uv = weight / self.total_weight * pixel_count;
+        pdf_uv / (2.0 * PI * PI * sin_theta)
+    }
+}
@@ -0,0 +1,552 @@
+use crate::{color::*, filter::*, tile::*};
+use std::io::{Read, Write};
+
+/// Running statistics of the samples taken in one pixel.
+#[derive(Debug, Copy, Clone)]
+pub struct PixelStats {
+    /// Sum of the sample colors.
+    pub sum: Color,
+
+    /// Sum of the squared sample luminances.
+    pub luminance_sq: f64,
+
+    /// Number of samples taken.
+    pub count: u32,
+}
+
+impl PixelStats {
+    /// Size in bytes of the statistics in the encoding of `Film::write_pixels`.
+    pub const ENCODED_SIZE: usize = 36;
+
+    /// Creates statistics for a pixel with no samples.
+    pub fn new() -> Self {
+        PixelStats {
+            sum: Color::zero(),
+            luminance_sq: 0.0,
+            count: 0,
+        }
+    }
+
+    /// Adds one sample.
+    pub fn add(&mut self, sample: Color) {
+        let luminance = sample.luminance();
+        self.sum += sample;
+        self.luminance_sq += luminance * luminance;
+        self.count += 1;
+    }
+
+    /// Returns the mean of the samples, or black without samples.
+    pub fn mean(&self) -> Color {
+        if self.count == 0 {
+            return Color::zero();
+        }
+        self.sum * (1.0 / self.count as f64)
+    }
+
+    /// Returns the variance of the mean luminance, which is infinite with fewer than two
+    /// samples.
+    pub fn mean_variance(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.count as f64;
+        let mean = self.sum.luminance() / n;
+        let variance = ((self.luminance_sq / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        variance / n
+    }
+
+    /// Returns the standard error of the mean luminance relative to the mean luminance.
+    ///
+    /// Dark pixels are compared against a floor of 0.01 so that noise too faint to see does
+    /// not keep them sampling. Pixels with fewer than two samples have an infinite error.
+    pub fn relative_error(&self) -> f64 {
+        let mean = self.sum.luminance() / self.count as f64;
+        self.mean_variance().sqrt() / mean.max(0.01)
+    }
+}
+
+/// Samples splatted into one pixel, weighted by the reconstruction filter.
+#[derive(Debug, Copy, Clone)]
+pub struct Splat {
+    /// Sum of the weighted sample colors.
+    pub sum: Color,
+
+    /// Sum of the weights.
+    pub weight: f64,
+}
+
+impl Splat {
+    /// Size in bytes of a splat in the encoding of `Film::write_pixels`.
+    pub const ENCODED_SIZE: usize = 32;
+
+    /// Creates a pixel no sample has reached.
+    pub fn new() -> Self {
+        Splat {
+            sum: Color::zero(),
+            weight: 0.0,
+        }
+    }
+
+    /// Returns the filtered color of a pixel where `samples` were taken, or their `mean` when
+    /// the weight is under a tenth of the samples.
+    ///
+    /// The negative lobes of sharpening filters can cancel most of the weight, and dividing
+    /// by what is left would blow up the noise.
+    pub fn color(&self, samples: u32, mean: Color) -> Color {
+        if self.weight < 0.1 * samples as f64 || self.weight <= 0.0 {
+            return mean;
+        }
+        self.sum * (1.0 / self.weight)
+    }
+}
+
+/// Image made of samples splatted with filter weights into every pixel they reach.
+///
+/// Its area may reach beyond the pixels sampled, since samples near the edge of a tile also
+/// count in the pixels next to it.
+pub struct SplatFilm {
+    /// Pixels of the frame covered.
+    pub area: Tile,
+
+    /// Splats of every pixel in row-major order, starting at the top left.
+    pub pixels: Vec<Splat>,
+}
+
+impl SplatFilm {
+    /// Creates an empty image covering an area of the frame.
+    pub fn new(area: Tile) -> Self {
+        SplatFilm {
+            area,
+            pixels: vec![Splat::new(); (area.width * area.height) as usize],
+        }
+    }
+
+    /// Returns the splat of a pixel, given in frame coordinates.
+    pub fn pixel(&self, i: i32, j: i32) -> &Splat {
+        &self.pixels[((j - self.area.y) * self.area.width + (i - self.area.x)) as usize]
+    }
+
+    /// Adds a sample at position (x, y) in the frame to every pixel the filter reaches,
+    /// with pixel centers at whole coordinates.
+    ///
+    /// Samples only reach pixels within `bounds`, such as the stereo view they were taken in.
+    pub fn add_sample(&mut self, filter: &Filter, (x, y): (f64, f64), color: Color, bounds: &Tile) {
+        let Some(reach) = self.area.intersect(bounds) else {
+            return;
+        };
+        let r = filter.radius;
+        let i0 = ((x - r).ceil() as i32).max(reach.x);
+        let i1 = ((x + r).floor() as i32).min(reach.x + reach.width - 1);
+        let j0 = ((y - r).ceil() as i32).max(reach.y);
+        let j1 = ((y + r).floor() as i32).min(reach.y + reach.height - 1);
+        for j in j0..=j1 {
+            for i in i0..=i1 {
+                let weight = filter.evaluate(i as f64 - x, j as f64 - y);
+                if weight != 0.0 {
+                    let index = ((j - self.area.y) * self.area.width + (i - self.area.x)) as usize;
+                    let splat = &mut self.pixels[index];
+                    splat.sum += color * weight;
+                    splat.weight += weight;
+                }
+            }
+        }
+    }
+
+    /// Adds the splats of another image where they overlap.
+    pub fn add(&mut self, other: &SplatFilm) {
+        if let Some(overlap) = self.area.intersect(&other.area) {
+            for (i, j) in overlap.pixels() {
+                let theirs = *other.pixel(i, j);
+                let index = ((j - self.area.y) * self.area.width + (i - self.area.x)) as usize;
+                let splat = &mut self.pixels[index];
+                splat.sum += theirs.sum;
+                splat.weight += theirs.weight;
+            }
+        }
+    }
+}
+
+/// Image being accumulated from pixel samples, covering all or part of the frame.
+pub struct Film {
+    /// Pixels of the frame covered by the film.
+    pub area: Tile,
+
+    /// Statistics of every pixel in row-major order, starting at the top left.
+    pub pixels: Vec<PixelStats>,
+
+    /// Filtered image, when samples are splatted with a reconstruction filter rather than
+    /// averaged in their own pixel.
+    pub splats: Option<SplatFilm>,
+}
+
+impl Film {
+    /// Creates an empty film covering an area of the frame.
+    pub fn new(area: Tile) -> Self {
+        Film {
+            area,
+            pixels: vec![PixelStats::new(); (area.width * area.height) as usize],
+            splats: None,
+        }
+    }
+
+    /// Returns the film splatting samples into an image covering `area`.
+    pub fn with_splats(mut self, area: Tile) -> Self {
+        self.splats = Some(SplatFilm::new(area));
+        self
+    }
+
+    /// Returns the color of a pixel, given in frame coordinates: filtered when splatting,
+    /// otherwise the mean of its samples.
+    pub fn color(&self, i: i32, j: i32) -> Color {
+        match &self.splats {
+            Some(splats) => {
+                let stats = self.pixel(i, j);
+                splats.pixel(i, j).color(stats.count, stats.mean())
+            }
+            None => self.pixel(i, j).mean(),
+        }
+    }
+
+    // Returns the index in `pixels` of a pixel given in frame coordinates.
+    fn index(&self, i: i32, j: i32) -> usize {
+        ((j - self.area.y) * self.area.width + (i - self.area.x)) as usize
+    }
+
+    /// Returns the statistics of a pixel, given in frame coordinates.
+    pub fn pixel(&self, i: i32, j: i32) -> &PixelStats {
+        &self.pixels[self.index(i, j)]
+    }
+
+    /// Returns the statistics of a pixel, given in frame coordinates, for updating.
+    pub fn pixel_mut(&mut self, i: i32, j: i32) -> &mut PixelStats {
+        let index = self.index(i, j);
+        &mut self.pixels[index]
+    }
+
+    /// Returns the mean relative error of the pixels, see `PixelStats::relative_error`.
+    pub fn mean_relative_error(&self) -> f64 {
+        let total: f64 = self.pixels.iter().map(|p| p.relative_error()).sum();
+        total / self.pixels.len() as f64
+    }
+
+    /// Identifies checkpoint files written by `write_checkpoint`.
+    const CHECKPOINT_MAGIC: &'static [u8; 8] = b"RTCKPT03";
+
+    /// Writes the accumulated samples to a checkpoint file.
+    ///
+    /// Every pixel sample draws its random numbers from a generator derived from the seed, the
+    /// pixel and the sample index, so the sample counts are all the random state needed to
+    /// continue the render. `key` identifies the scene and camera settings.
+    pub fn write_checkpoint(&self, path: &str, key: u64) -> std::io::Result<()> {
+        // Write to a temporary file first so an interrupted write keeps the old checkpoint
+        let temporary = format!("{}.tmp", path);
+        let mut output = std::io::BufWriter::new(std::fs::File::create(&temporary)?);
+        output.write_all(Film::CHECKPOINT_MAGIC)?;
+        output.write_all(&key.to_le_bytes())?;
+        for value in [self.area.x, self.area.y, self.area.width, self.area.height] {
+            output.write_all(&value.to_le_bytes())?;
+        }
+        self.write_pixels(&mut output)?;
+        output.into_inner()?.sync_all()?;
+        std::fs::rename(&temporary, path)
+    }
+
+    /// Reads a film from a checkpoint file written with the same `key` and area.
+    pub fn read_checkpoint(path: &str, key: u64, area: Tile) -> std::io::Result<Self> {
+        let mut input = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != Film::CHECKPOINT_MAGIC {
+            return Err(invalid_data("not a checkpoint file"));
+        }
+        if read_u64(&mut input)? != key {
+            return Err(invalid_data("checkpoint was written for a different scene"));
+        }
+        let mut stored = [0; 4];
+        for value in &mut stored {
+            *value = read_u32(&mut input)? as i32;
+        }
+        if stored != [area.x, area.y, area.width, area.height] {
+            return Err(invalid_data(
+                "checkpoint covers a different part of the image",
+            ));
+        }
+
+        let mut film = Film::new(area);
+        film.read_pixels(&mut input)?;
+        Ok(film)
+    }
+
+    /// Writes the statistics of every pixel in a binary little-endian encoding, followed by a
+    /// byte telling whether splats follow, and then their area and values.
+    pub fn write_pixels<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        for stats in &self.pixels {
+            for value in stats.sum.0.e {
+                output.write_all(&value.to_le_bytes())?;
+            }
+            output.write_all(&stats.luminance_sq.to_le_bytes())?;
+            output.write_all(&stats.count.to_le_bytes())?;
+        }
+        let Some(splats) = &self.splats else {
+            return output.write_all(&[0]);
+        };
+        output.write_all(&[1])?;
+        let area = splats.area;
+        for value in [area.x, area.y, area.width, area.height] {
+            output.write_all(&value.to_le_bytes())?;
+        }
+        for splat in &splats.pixels {
+            for value in splat.sum.0.e {
+                output.write_all(&value.to_le_bytes())?;
+            }
+            output.write_all(&splat.weight.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reads the statistics of every pixel, and the splats if any, as written by
+    /// `write_pixels`.
+    pub fn read_pixels<R: Read>(&mut self, input: &mut R) -> std::io::Result<()> {
+        for stats in &mut self.pixels {
+            let r = f64::from_bits(read_u64(input)?);
+            let g = f64::from_bits(read_u64(input)?);
+            let b = f64::from_bits(read_u64(input)?);
+            stats.sum = Color::new(r, g, b);
+            stats.luminance_sq = f64::from_bits(read_u64(input)?);
+            stats.count = read_u32(input)?;
+        }
+        let mut has_splats = [0; 1];
+        input.read_exact(&mut has_splats)?;
+        self.splats = match has_splats[0] {
+            0 => None,
+            1 => {
+                let mut values = [0; 4];
+                for value in &mut values {
+                    *value = read_u32(input)? as i32;
+                }
+                let [x, y, width, height] = values;
+                let pixels = match (width, height) {
+                    (0.., 0..) => width.checked_mul(height),
+                    _ => None,
+                };
+                let Some(pixels) = pixels else {
+                    return Err(invalid_data("invalid splat area"));
+                };
+                // The splats hold the colors of every pixel of the film
+                let area = Tile::new(x, y, width, height);
+                let fits = x.checked_add(width).is_some() && y.checked_add(height).is_some();
+                if !fits || area.intersect(&self.area) != Some(self.area) {
+                    return Err(invalid_data("splat area does not cover the film"));
+                }
+                // Grow the splats as they are read rather than trusting the area with memory
+                let mut splats = Vec::new();
+                for _ in 0..pixels {
+                    let r = f64::from_bits(read_u64(input)?);
+                    let g = f64::from_bits(read_u64(input)?);
+                    let b = f64::from_bits(read_u64(input)?);
+                    let weight = f64::from_bits(read_u64(input)?);
+                    splats.push(Splat {
+                        sum: Color::new(r, g, b),
+                        weight,
+                    });
+                }
+                Some(SplatFilm {
+                    area,
+                    pixels: splats,
+                })
+            }
+            _ => return Err(invalid_data("invalid splat marker")),
+        };
+        Ok(())
+    }
+
+    /// Replaces the pixels covered by another film with its statistics, and adds its splats,
+    /// which also reach into neighbouring pixels.
+    pub fn insert(&mut self, other: &Film) {
+        if let Some(overlap) = self.area.intersect(&other.area) {
+            for (i, j) in overlap.pixels() {
+                *self.pixel_mut(i, j) = *other.pixel(i, j);
+            }
+        }
+        if let (Some(splats), Some(theirs)) = (&mut self.splats, &other.splats) {
+            splats.add(theirs);
+        }
+    }
+
+    /// Writes the number of samples taken in every pixel in `frame` as a false-color PPM image.
+    ///
+    /// Colors run from black through red and yellow to white at the largest count.
+    pub fn write_heatmap(&self, path: &str, frame: &Tile) -> std::io::Result<()> {
+        let max_count = self
+            .pixels
+            .iter()
+            .map(|p| p.count)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        write_frame_ppm(path, frame, &self.area, |i, j| {
+            let stats = self.pixel(i, j);
+            let t = 3.0 * stats.count as f64 / max_count as f64;
+            Color::new(t.min(1.0), (t - 1.0).clamp(0.0, 1.0), (t - 2.0).max(0.0))
+        })
+    }
+}
+
+/// Writes the part of the frame in `frame` as a PPM image, with the color of every pixel in
+/// `area` given by `color`, already encoded for display, and black elsewhere.
+pub fn write_frame_ppm<F: Fn(i32, i32) -> Color>(
+    path: &str,
+    frame: &Tile,
+    area: &Tile,
+    color: F,
+) -> std::io::Result<()> {
+    let mut output = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write!(output, "P3\n{} {}\n255\n", frame.width, frame.height)?;
+    for (i, j) in frame.pixels() {
+        let pixel_color = if area.contains(i, j) {
+            color(i, j)
+        } else {
+            Color::zero()
+        };
+        Color::write_color(&mut output, pixel_color);
+    }
+    Ok(())
+}
+
+/// Writes the part of the frame in `frame` like `write_frame_ppm`, as a PNG image when the path
+/// ends in `.png` and as a PPM image otherwise.
+pub fn write_frame_image<F: Fn(i32, i32) -> Color>(
+    path: &str,
+    frame: &Tile,
+    area: &Tile,
+    color: F,
+) -> std::io::Result<()> {
+    if !path.ends_with(".png") {
+        return write_frame_ppm(path, frame, area, color);
+    }
+    let output = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(output, frame.width as u32, frame.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    // The pixels are encoded with the sRGB transfer curve
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    let mut data = Vec::with_capacity(3 * frame.pixels().count());
+    for (i, j) in frame.pixels() {
+        let pixel_color = if area.contains(i, j) {
+            color(i, j)
+        } else {
+            Color::zero()
+        };
+        data.extend(pixel_color.to_bytes());
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Reads a little-endian 64-bit value.
+pub fn read_u64<R: Read>(input: &mut R) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Reads a little-endian 32-bit value.
+pub fn read_u32<R: Read>(input: &mut R) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// Creates an error for a malformed checkpoint file.
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns a temporary path unique to this test process.
+    fn temporary_path(name: &str) -> String {
+        let file = format!("{}-{}", std::process::id(), name);
+        std::env::temp_dir()
+            .join(file)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn checkpoints_round_trip() {
+        let area = Tile::new(2, 3, 4, 2);
+        let mut film = Film::new(area).with_splats(Tile::new(1, 2, 6, 4));
+        film.pixel_mut(3, 4).add(Color::new(0.25, 0.5, 1.0));
+        film.pixel_mut(3, 4).add(Color::new(1.0, 0.0, 0.5));
+        film.pixel_mut(5, 3).add(Color::new(2.0, 2.0, 2.0));
+        let filter = Filter::new(FilterKind::Tent, 1.5);
+        let bounds = Tile::new(0, 0, 10, 10);
+        film.splats.as_mut().unwrap().add_sample(
+            &filter,
+            (3.2, 4.1),
+            Color::new(1.0, 1.0, 1.0),
+            &bounds,
+        );
+
+        let path = temporary_path("checkpoint");
+        film.write_checkpoint(&path, 42).unwrap();
+        let read = Film::read_checkpoint(&path, 42, area).unwrap();
+        let wrong_key = Film::read_checkpoint(&path, 43, area);
+        let wrong_area = Film::read_checkpoint(&path, 42, Tile::new(2, 3, 4, 3));
+        // Splats that leave out some pixels of the film
+        Film::new(area)
+            .with_splats(Tile::new(3, 3, 4, 2))
+            .write_checkpoint(&path, 42)
+            .unwrap();
+        let short_splats = Film::read_checkpoint(&path, 42, area);
+        std::fs::remove_file(&path).unwrap();
+
+        for (a, b) in film.pixels.iter().zip(&read.pixels) {
+            assert_eq!(a.sum.0.e, b.sum.0.e);
+            assert_eq!(a.luminance_sq, b.luminance_sq);
+            assert_eq!(a.count, b.count);
+        }
+        let (splats, read_splats) = (film.splats.unwrap(), read.splats.unwrap());
+        assert_eq!(splats.area, read_splats.area);
+        for (a, b) in splats.pixels.iter().zip(&read_splats.pixels) {
+            assert_eq!(a.sum.0.e, b.sum.0.e);
+            assert_eq!(a.weight, b.weight);
+        }
+        assert!(wrong_key.is_err());
+        assert!(wrong_area.is_err());
+        assert!(short_splats.is_err());
+    }
+
+    #[test]
+    fn splats_fall_back_to_the_mean_where_little_weight_is_left() {
+        let mean = Color::new(0.5, 0.5, 0.5);
+        let splat = |weight: f64| Splat {
+            sum: Color::new(2.0, 2.0, 2.0) * weight,
+            weight,
+        };
+        assert_eq!(splat(4.0).color(4, mean).0.e, [2.0; 3]);
+        assert_eq!(splat(0.01).color(4, mean).0.e, [0.5; 3]);
+        assert_eq!(splat(-1.0).color(4, mean).0.e, [0.5; 3]);
+        assert_eq!(splat(0.0).color(0, Color::zero()).0.e, [0.0; 3]);
+    }
+
+    #[test]
+    fn oversized_splat_areas_are_rejected() {
+        let mut encoded = Vec::new();
+        Film::new(Tile::new(0, 0, 1, 1))
+            .write_pixels(&mut encoded)
+            .unwrap();
+        encoded.pop();
+        encoded.push(1);
+        for value in [0i32, 0, 1 << 16, 1 << 16] {
+            encoded.extend(value.to_le_bytes());
+        }
+        let mut film = Film::new(Tile::new(0, 0, 1, 1));
+        assert!(film.read_pixels(&mut encoded.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use std::f64::consts::PI;
+use std::hash::{Hash, Hasher};
+
+/// Shape of a pixel reconstruction filter.
+#[derive(Debug, Copy, Clone, PartialEq, Hash)]
+pub enum FilterKind {
+    /// Equal weights within the radius.
+    Box,
+    /// Weights falling linearly to zero at the radius.
+    Tent,
+    /// Gaussian with a standard deviation of a third of the radius, shifted to reach zero at
+    /// the radius.
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3, which sharpens with small negative lobes.
+    Mitchell,
+    /// Sinc windowed by a wider sinc, the sharpest filter with the strongest ringing.
+    Lanczos,
+}
+
+/// Filter weighting the samples that make up each pixel by their distance from the pixel's
+/// center.
+///
+/// Filters are separable: the weight is the product of a one-dimensional filter along x and
+/// along y, with distances in pixels. Weights are scaled to integrate to one over the plane,
+/// so a pixel collects about as much weight as samples were taken around it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Filter {
+    /// Shape of the filter.
+    pub kind: FilterKind,
+
+    /// Distance in pixels beyond which samples have no weight.
+    pub radius: f64,
+
+    // Inverse of the integral of the one-dimensional filter.
+    scale: f64,
+}
+
+impl FilterKind {
+    /// Parses a filter name as used on the command line.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(FilterKind::Box),
+            "tent" | "triangle" => Some(FilterKind::Tent),
+            "gaussian" => Some(FilterKind::Gaussian),
+            "mitchell" => Some(FilterKind::Mitchell),
+            "lanczos" => Some(FilterKind::Lanczos),
+            _ => None,
+        }
+    }
+
+    /// Returns the radius the filter is usually used with.
+    pub fn default_radius(self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+impl Filter {
+    /// Creates the box filter covering exactly one pixel, which averages the samples taken
+    /// in each pixel.
+    pub fn default() -> Self {
+        Filter::new(FilterKind::Box, 0.5)
+    }
+
+    /// Creates a filter.
+    pub fn new(kind: FilterKind, radius: f64) -> Self {
+        let mut filter = Filter {
+            kind,
+            radius,
+            scale: 1.0,
+        };
+        // Integrate with the midpoint rule, exact for the box and close for the others
+        let steps = 1024;
+        let dx = 2.0 * radius / steps as f64;
+        let integral: f64 = (0..steps)
+            .map(|k| filter.evaluate_1d(-radius + (k as f64 + 0.5) * dx) * dx)
+            .sum();
+        filter.scale = 1.0 / integral;
+        filter
+    }
+
+    /// Returns whether samples reach other pixels than their own, which the box filter of
+    /// one pixel does not.
+    pub fn splats(&self) -> bool {
+        self.kind != FilterKind::Box || self.radius != 0.5
+    }
+
+    /// Returns how many pixels beyond their own the samples of a pixel can reach.
+    pub fn margin(&self) -> i32 {
+        ((self.radius + 0.5).ceil() as i32 - 1).max(0)
+    }
+
+    /// Returns the weight of a sample at offset (dx, dy) in pixels from a pixel's center.
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy) * self.scale * self.scale
+    }
+
+    // Returns the one-dimensional weight at distance x.
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let r = self.radius;
+        let x = x.abs();
+        if x >= r {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - x,
+            FilterKind::Gaussian => {
+                let sigma = r / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(r)
+            }
+            FilterKind::Mitchell => {
+                // The cubic spans two units, stretched over the radius
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let x = 2.0 * x / r;
+                let x2 = x * x;
+                let x3 = x2 * x;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x3
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x2
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x3
+                        + (6.0 * b + 30.0 * c) * x2
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                }
+            }
+            FilterKind::Lanczos => sinc(x) * sinc(x / r),
+        }
+    }
+}
+
+impl Hash for Filter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+        self.radius.to_bits().hash(state);
+    }
+}
+
+// Returns sin(πx) / (πx).
+fn sinc(x: f64) -> f64 {
+    if x < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::Lanczos,
+    ];
+
+    #[test]
+    fn weights_integrate_to_one() {
+        for kind in KINDS {
+            for radius in [0.5, kind.default_radius(), 2.5] {
+                let filter = Filter::new(kind, radius);
+                let steps = 200;
+                let d = 2.0 * radius / steps as f64;
+                let offset = |k: i32| -radius + (k as f64 + 0.5) * d;
+                let mut integral = 0.0;
+                for j in 0..steps {
+                    for i in 0..steps {
+                        integral += filter.evaluate(offset(i), offset(j)) * d * d;
+                    }
+                }
+                assert!((integral - 1.0).abs() < 1e-3, "{:?} {}", kind, radius);
+            }
+        }
+    }
+
+    #[test]
+    fn interpolating_filters_form_a_partition_of_unity() {
+        // Each sample hands out a total weight of one to the pixels around it
+        for (kind, radius) in [
+            (FilterKind::Box, 0.5),
+            (FilterKind::Tent, 1.0),
+            (FilterKind::Mitchell, 2.0),
+        ] {
+            let filter = Filter::new(kind, radius);
+            for (x, y) in [(0.0, 0.0), (0.25, 0.1), (0.4, 0.45), (0.49, 0.0)] {
+                let mut total = 0.0;
+                for j in -3..=3 {
+                    for i in -3..=3 {
+                        total += filter.evaluate(i as f64 - x, j as f64 - y);
+                    }
+                }
+                assert!((total - 1.0).abs() < 1e-6, "{:?} at {} {}", kind, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn sharpening_filters_have_negative_lobes() {
+        for (kind, x) in [(FilterKind::Mitchell, 1.5), (FilterKind::Lanczos, 1.5)] {
+            let filter = Filter::new(kind, kind.default_radius());
+            assert!(filter.evaluate(x, 0.0) < 0.0, "{:?}", kind);
+            assert!(filter.evaluate(0.0, 0.0) > 0.0, "{:?}", kind);
+        }
+        for kind in [FilterKind::Box, FilterKind::Tent, FilterKind::Gaussian] {
+            let filter = Filter::new(kind, kind.default_radius());
+            assert!(filter.evaluate(0.3, -0.2) > 0.0, "{:?}", kind);
+            assert_eq!(filter.evaluate(filter.radius, 0.0), 0.0, "{:?}", kind);
+        }
+    }
+}
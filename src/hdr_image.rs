@@ -0,0 +1,225 @@
+use crate::color::*;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// A floating-point RGB image, stored row by row from the top-left corner.
+#[derive(Clone)]
+pub struct HdrImage {
+    /// Width of the image in pixels.
+    pub width: usize,
+    /// Height of the image in pixels.
+    pub height: usize,
+    /// Linear RGB pixel values.
+    pub pixels: Vec<Color>,
+}
+
+impl HdrImage {
+    /// Creates a new black image with the given dimensions.
+    pub fn new(width: usize, height: usize) -> Self {
+        HdrImage {
+            width,
+            height,
+            pixels: vec![Color::zero(); width * height],
+        }
+    }
+
+    /// Returns the pixel at column `x` and row `y`.
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Loads a Radiance `.hdr` or OpenEXR `.exr` image, chosen by file extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("hdr") => HdrImage::load_hdr(path),
+            Some(ext) if ext.eq_ignore_ascii_case("exr") => HdrImage::load_exr(path),
+            _ => Err(invalid_data("expected a .hdr or .exr image")),
+        }
+    }
+
+    /// Loads an OpenEXR image, keeping the RGB channels of its first layer.
+    pub fn load_exr<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| HdrImage::new(resolution.width(), resolution.height()),
+            |image: &mut HdrImage, position, (r, g, b, _): (f32, f32, f32, f32)| {
+                let index = position.y() * image.width + position.x();
+                image.pixels[index] = Color::new(r as f64, g as f64, b as f64);
+            },
+        )
+        .map_err(|e| invalid_data(&e.to_string()))?;
+        Ok(image.layer_data.channel_data.pixels)
+    }
+
+    /// Loads a Radiance RGBE image, either flat or with run-length encoded scanlines.
+    pub fn load_hdr<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        let mut pos = 0;
+
+        // Header lines end with an empty line, followed by the resolution string
+        let read_line = |pos: &mut usize| -> String {
+            let start = *pos;
+            while *pos < data.len() && data[*pos] != b'\n' {
+                *pos += 1;
+            }
+            let line = String::from_utf8_lossy(&data[start..*pos]).into_owned();
+            *pos += 1;
+            line
+        };
+        let magic = read_line(&mut pos);
+        if !magic.starts_with("#?") {
+            return Err(invalid_data("missing Radiance header"));
+        }
+        loop {
+            let line = read_line(&mut pos);
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+                return Err(invalid_data("only RGBE Radiance images are supported"));
+            }
+            if pos >= data.len() {
+                return Err(invalid_data("truncated Radiance header"));
+            }
+        }
+        let resolution = read_line(&mut pos);
+        let fields: Vec<&str> = resolution.split_whitespace().collect();
+        if fields.len() != 4 || fields[0] != "-Y" || fields[2] != "+X" {
+            return Err(invalid_data("unsupported Radiance image orientation"));
+        }
+        let height: usize = fields[1].parse().map_err(|_| invalid_data("bad height"))?;
+        let width: usize = fields[3].parse().map_err(|_| invalid_data("bad width"))?;
+
+        let mut image = HdrImage::new(width, height);
+        let mut scanline = vec![[0u8; 4]; width];
+        for y in 0..height {
+            pos = read_scanline(&data, pos, &mut scanline)?;
+            for (x, rgbe) in scanline.iter().enumerate() {
+                image.pixels[y * width + x] = rgbe_to_color(*rgbe);
+            }
+        }
+        Ok(image)
+    }
+}
+
+// Decodes one scanline starting at `pos`, returning the position after it.
+fn read_scanline(data: &[u8], mut pos: usize, scanline: &mut [[u8; 4]]) -> Result<usize> {
+    let width = scanline.len();
+    let truncated = || invalid_data("truncated Radiance image");
+    let header = data.get(pos..pos + 4).ok_or_else(truncated)?;
+    let is_rle = (8..0x8000).contains(&width)
+        && header[0] == 2
+        && header[1] == 2
+        && (((header[2] as usize) << 8) | header[3] as usize) == width;
+
+    if !is_rle {
+        for pixel in scanline.iter_mut() {
+            let bytes = data.get(pos..pos + 4).ok_or_else(truncated)?;
+            pixel.copy_from_slice(bytes);
+            pos += 4;
+        }
+        return Ok(pos);
+    }
+
+    // Each of the four components is run-length encoded separately
+    pos += 4;
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data.get(pos).ok_or_else(truncated)? as usize;
+            pos += 1;
+            if count > 128 {
+                let run = count - 128;
+                let value = *data.get(pos).ok_or_else(truncated)?;
+                pos += 1;
+                if x + run > width {
+                    return Err(invalid_data("bad Radiance run length"));
+                }
+                for pixel in &mut scanline[x..x + run] {
+                    pixel[component] = value;
+                }
+                x += run;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("bad Radiance run length"));
+                }
+                let values = data.get(pos..pos + count).ok_or_else(truncated)?;
+                for (pixel, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[component] = *value;
+                }
+                pos += count;
+                x += count;
+            }
+        }
+    }
+    Ok(pos)
+}
+
+// Converts a shared-exponent RGBE pixel to linear RGB.
+fn rgbe_to_color(rgbe: [u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::zero();
+    }
+    let scale = 2f64.powi(rgbe[3] as i32 - 136);
+    Color::new(
+        (rgbe[0] as f64 + 0.5) * scale,
+        (rgbe[1] as f64 + 0.5) * scale,
+        (rgbe[2] as f64 + 0.5) * scale,
+    )
+}
+
+// Builds an `InvalidData` I/O error with the given message.
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_run_length_encoded_scanlines() {
+        // Width 8: every component mixes a run with literal values
+        let mut data = vec![2, 2, 0, 8];
+        for component in 0..4u8 {
+            data.extend([128 + 5, component]);
+            data.extend([3, 10 + component, 20 + component, 30 + component]);
+        }
+        data.push(0xff);
+        let mut scanline = [[0u8; 4]; 8];
+        let end = read_scanline(&data, 0, &mut scanline).unwrap();
+        assert_eq!(end, data.len() - 1);
+        assert_eq!(scanline[0], [0, 1, 2, 3]);
+        assert_eq!(scanline[4], [0, 1, 2, 3]);
+        assert_eq!(scanline[5], [10, 11, 12, 13]);
+        assert_eq!(scanline[7], [30, 31, 32, 33]);
+    }
+
+    #[test]
+    fn reads_flat_scanlines_when_too_narrow_for_rle() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut scanline = [[0u8; 4]; 2];
+        assert_eq!(read_scanline(&data, 0, &mut scanline).unwrap(), 8);
+        assert_eq!(scanline, [[1, 2, 3, 4], [5, 6, 7, 8]]);
+    }
+
+    #[test]
+    fn rejects_runs_past_the_end_of_the_scanline() {
+        let mut scanline = [[0u8; 4]; 8];
+        let overlong = [2, 2, 0, 8, 128 + 9, 0];
+        assert!(read_scanline(&overlong, 0, &mut scanline).is_err());
+        let truncated = [2, 2, 0, 8, 4, 1, 2];
+        assert!(read_scanline(&truncated, 0, &mut scanline).is_err());
+    }
+
+    #[test]
+    fn converts_shared_exponents() {
+        let black = rgbe_to_color([255, 255, 255, 0]);
+        assert_eq!((black.0.x(), black.0.y(), black.0.z()), (0.0, 0.0, 0.0));
+        let c = rgbe_to_color([128, 64, 0, 129]);
+        assert_eq!(c.0.x(), 128.5 / 128.0);
+        assert_eq!(c.0.y(), 64.5 / 128.0);
+        assert_eq!(c.0.z(), 0.5 / 128.0);
+    }
+}
@@ -14,6 +14,8 @@ pub struct HitRecord {
     pub front_face: bool,
     /// Material of the object at the point of intersection.
     pub mat: Option<Rc<dyn Material>>,
+    /// Index of the hit object in the scene's top-level list.
+    pub object_id: usize,
 }
 
 impl HitRecord {
@@ -35,6 +37,7 @@ impl HitRecord {
             t: 0.0,
             front_face: false,
             mat: None,
+            object_id: 0,
         }
     }
 }
@@ -43,4 +46,9 @@ impl HitRecord {
 pub trait Hittable {
     /// Determines if a ray intersects with the object.
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
+
+    /// Returns the point the object is placed at `time`, for objects that have one.
+    fn center(&self, _time: f64) -> Option<Point3> {
+        None
+    }
 }
@@ -1,9 +1,12 @@
+use crate::{animation::*, hittable::*, interval::*, ray::*, stats::*, vec3::*};
+use std::collections::HashMap;
 use std::rc::Rc;
-use crate::{hittable::*, interval::*, ray::*};
 
 /// Represents a collection of hittable objects in the scene.
 pub struct HittableList {
     objects: Vec<Rc<dyn Hittable>>,
+    // Indices of the objects added under a name.
+    names: HashMap<String, usize>,
 }
 
 impl HittableList {
@@ -11,6 +14,7 @@ impl HittableList {
     pub fn new() -> Self {
         HittableList {
             objects: Vec::new(),
+            names: HashMap::new(),
         }
     }
 
@@ -19,6 +23,38 @@ impl HittableList {
         self.objects.push(object);
     }
 
+    /// Adds a hittable object to the list under a name it can be looked up by.
+    pub fn add_named(&mut self, name: &str, object: Rc<dyn Hittable>) {
+        self.names.insert(name.to_string(), self.objects.len());
+        self.add(object);
+    }
+
+    /// Returns the center at `time` of the object added under `name`, or why there is none.
+    pub fn center_of(&self, name: &str, time: f64) -> Result<Point3, String> {
+        let Some(&index) = self.names.get(name) else {
+            return Err(format!("no object named {}", name));
+        };
+        self.objects[index]
+            .center(time)
+            .ok_or_else(|| format!("object {} has no center to focus on", name))
+    }
+
+    /// Moves the object added under `name` by an animation, returning `false` when there is
+    /// no such object.
+    pub fn animate(
+        &mut self,
+        name: &str,
+        animation: ObjectAnimation,
+        interpolation: Interpolation,
+    ) -> bool {
+        let Some(&index) = self.names.get(name) else {
+            return false;
+        };
+        let object = Rc::clone(&self.objects[index]);
+        self.objects[index] = Rc::new(Animated::new(object, animation, interpolation));
+        true
+    }
+
     // pub fn clear(&mut self) {
     //     self.objects.clear();
     // }
@@ -27,15 +63,17 @@ impl HittableList {
 impl Hittable for HittableList {
     /// Checks if the ray intersects with any object in the list.
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        NODES_VISITED.increment();
         let mut temp_rec: HitRecord = HitRecord::default();
         let mut hit_anything: bool = false;
         let mut closest_so_far: f64 = ray_t.max;
 
-        for object in &self.objects {
+        for (index, object) in self.objects.iter().enumerate() {
             if object.hit(r, Interval::new(ray_t.min, closest_so_far), &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
                 *rec = temp_rec.clone();
+                rec.object_id = index;
             }
         }
         hit_anything
@@ -12,8 +12,8 @@ impl Interval {
     pub fn new(min: f64, max: f64) -> Self {
         Interval { min, max }
     }
-    
-    /// Checks if a value is within the interval, inclusive of the endpoints.
+
+    // Checks if a value is within the interval, inclusive of the endpoints.
     // pub fn contains(&self, x: f64) -> bool {
     //     self.min <= x && x <= self.max
     // }
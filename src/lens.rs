@@ -0,0 +1,325 @@
+use crate::{aperture::*, vec3::*};
+use std::hash::{Hash, Hasher};
+
+/// One refracting surface of a lens system, or the aperture stop.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LensElement {
+    /// Radius of curvature in millimetres, positive when the center of curvature lies towards
+    /// the film; zero for the flat aperture stop.
+    pub radius: f64,
+
+    /// Distance along the axis to the next surface, or to the film after the last one.
+    pub thickness: f64,
+
+    /// Index of refraction of the glass behind the surface, towards the film; air is 1.
+    pub ior: f64,
+
+    /// Diameter of the surface's clear opening in millimetres.
+    pub aperture: f64,
+}
+
+/// Lens system of spherical elements, traced exactly instead of approximated by a thin lens.
+///
+/// Elements are listed from the front of the lens to the rear, as in lens patents. Lens space
+/// has the film at z = 0 and the axis along z towards the scene, with millimetres as units;
+/// the scene is taken to be in metres.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LensSystem {
+    /// Surfaces from the front of the lens to the rear.
+    pub elements: Vec<LensElement>,
+}
+
+// Where light crossing a lens system along the axis comes to a focus.
+struct Cardinal {
+    // Principal plane and focal point on the scene side.
+    scene_principal: f64,
+    scene_focus: f64,
+    // Principal plane on the film side, where the focal length in air is the same.
+    film_principal: f64,
+}
+
+impl LensSystem {
+    /// Loads a lens system from a text file holding one element per line as its radius,
+    /// thickness, index of refraction and aperture, as in pbrt's lens files. Lines starting
+    /// with `#` are comments, and an index of zero stands for air.
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let invalid =
+            |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+        let text = std::fs::read_to_string(path)?;
+        let mut elements = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values: Vec<f64> = line
+                .split_whitespace()
+                .map(|v| v.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid(format!("line {}: expected numbers", number + 1)))?;
+            let [radius, thickness, ior, aperture] = values[..] else {
+                return Err(invalid(format!(
+                    "line {}: expected four values",
+                    number + 1
+                )));
+            };
+            elements.push(LensElement {
+                radius,
+                thickness,
+                ior: if ior == 0.0 { 1.0 } else { ior },
+                aperture,
+            });
+        }
+        if elements.is_empty() {
+            return Err(invalid("the lens file has no elements".to_string()));
+        }
+        Ok(LensSystem { elements })
+    }
+
+    /// Returns the distance of the rear surface from the film.
+    pub fn rear_z(&self) -> f64 {
+        self.elements.last().map_or(0.0, |e| e.thickness)
+    }
+
+    /// Returns the radius of the rear surface's opening.
+    pub fn rear_radius(&self) -> f64 {
+        self.elements.last().map_or(0.0, |e| 0.5 * e.aperture)
+    }
+
+    /// Moves the lens away from the film so objects at `distance` millimetres from the film
+    /// are in focus, returning the distance from the film to the rear principal plane.
+    pub fn focus(&mut self, distance: f64) -> Option<f64> {
+        // The object distance depends on where the lens ends up, so converge on it
+        let mut film_principal = 0.0;
+        for _ in 0..20 {
+            let cardinal = self.cardinal_points()?;
+            let focal_length = cardinal.scene_focus - cardinal.scene_principal;
+            let object = distance - cardinal.scene_principal;
+            if object <= focal_length {
+                return None;
+            }
+            let image = focal_length * object / (object - focal_length);
+            let shift = image - cardinal.film_principal;
+            self.elements.last_mut()?.thickness += shift;
+            film_principal = cardinal.film_principal + shift;
+            if shift.abs() < 1e-9 {
+                break;
+            }
+        }
+        Some(film_principal)
+    }
+
+    /// Traces a ray from the film out of the front of the lens, returning the ray leaving the
+    /// lens, or `None` when an element or the aperture stop blocks it.
+    ///
+    /// The aperture stop, the element of zero radius, takes the given shape.
+    pub fn trace_from_film(
+        &self,
+        origin: Point3,
+        direction: Vec3,
+        stop: &ApertureShape,
+    ) -> Option<(Point3, Vec3)> {
+        let (mut origin, mut direction) = (origin, Vec3::unit_vector(direction));
+        let mut z = 0.0;
+        for k in (0..self.elements.len()).rev() {
+            let element = &self.elements[k];
+            z += element.thickness;
+            let (point, normal) = self.intersect(element, z, origin, direction)?;
+            let half = 0.5 * element.aperture;
+            if element.radius == 0.0 {
+                if !stop.contains(point.x() / half, point.y() / half) {
+                    return None;
+                }
+            } else {
+                if point.x() * point.x() + point.y() * point.y() > half * half {
+                    return None;
+                }
+                let outside = if k == 0 {
+                    1.0
+                } else {
+                    self.elements[k - 1].ior
+                };
+                direction = refract(direction, normal, element.ior / outside)?;
+            }
+            origin = point;
+        }
+        Some((origin, direction))
+    }
+
+    // Traces a ray from the scene into the front of the lens and out towards the film.
+    fn trace_from_scene(&self, origin: Point3, direction: Vec3) -> Option<(Point3, Vec3)> {
+        let (mut origin, mut direction) = (origin, Vec3::unit_vector(direction));
+        let mut z: f64 = self.elements.iter().map(|e| e.thickness).sum();
+        for (k, element) in self.elements.iter().enumerate() {
+            let (point, normal) = self.intersect(element, z, origin, direction)?;
+            let half = 0.5 * element.aperture;
+            if point.x() * point.x() + point.y() * point.y() > half * half {
+                return None;
+            }
+            if element.radius != 0.0 {
+                let outside = if k == 0 {
+                    1.0
+                } else {
+                    self.elements[k - 1].ior
+                };
+                direction = refract(direction, normal, outside / element.ior)?;
+            }
+            origin = point;
+            z -= element.thickness;
+        }
+        Some((origin, direction))
+    }
+
+    // Intersects a ray with the surface of an element whose vertex is at `z`, returning the
+    // hit point and the surface normal facing the ray.
+    fn intersect(
+        &self,
+        element: &LensElement,
+        z: f64,
+        origin: Point3,
+        direction: Vec3,
+    ) -> Option<(Point3, Vec3)> {
+        if element.radius == 0.0 {
+            let t = (z - origin.z()) / direction.z();
+            if t.is_nan() || t <= 0.0 {
+                return None;
+            }
+            return Some((
+                origin + direction * t,
+                Vec3::new(0.0, 0.0, -direction.z().signum()),
+            ));
+        }
+        let center = Point3::new(0.0, 0.0, z - element.radius);
+        let oc = origin - center;
+        let b = Vec3::dot(&oc, &direction);
+        let c = oc.length_squared() - element.radius * element.radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        // Of the two crossings of the sphere, the surface is the one near the vertex
+        let root = discriminant.sqrt();
+        let t = [-b - root, -b + root]
+            .into_iter()
+            .filter(|&t| t > 1e-9)
+            .min_by(|&a, &b| {
+                let za = (origin.z() + direction.z() * a - z).abs();
+                let zb = (origin.z() + direction.z() * b - z).abs();
+                za.total_cmp(&zb)
+            })?;
+        let point = origin + direction * t;
+        let mut normal = Vec3::unit_vector(point - center);
+        if Vec3::dot(&normal, &direction) > 0.0 {
+            normal = -normal;
+        }
+        Some((point, normal))
+    }
+
+    // Finds the principal planes and focal points by tracing rays parallel to the axis close
+    // to it through the lens in both directions.
+    fn cardinal_points(&self) -> Option<Cardinal> {
+        let height = 0.001 * self.elements[0].aperture.min(2.0 * self.rear_radius());
+        let front: f64 = self.elements.iter().map(|e| e.thickness).sum();
+        // Where a ray leaving the lens crosses the axis, and where it has the height it
+        // entered with
+        let planes = |(origin, direction): (Point3, Vec3)| {
+            let focus = origin.z() - origin.x() * direction.z() / direction.x();
+            let principal = origin.z() - (origin.x() - height) * direction.z() / direction.x();
+            (principal, focus)
+        };
+        let into_film = Point3::new(height, 0.0, front + 1.0);
+        let (film_principal, _) =
+            planes(self.trace_from_scene(into_film, Vec3::new(0.0, 0.0, -1.0))?);
+        let into_scene = Point3::new(height, 0.0, -1.0);
+        let (scene_principal, scene_focus) = planes(self.trace_from_film(
+            into_scene,
+            Vec3::new(0.0, 0.0, 1.0),
+            &ApertureShape::Circle,
+        )?);
+        Some(Cardinal {
+            scene_principal,
+            scene_focus,
+            film_principal,
+        })
+    }
+}
+
+impl Hash for LensSystem {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.elements.len().hash(state);
+        for element in &self.elements {
+            let values = [
+                element.radius,
+                element.thickness,
+                element.ior,
+                element.aperture,
+            ];
+            for value in values {
+                value.to_bits().hash(state);
+            }
+        }
+    }
+}
+
+// Refracts a unit direction through a surface with a normal facing it, where `eta` is the
+// index of refraction on the incoming side over the one on the outgoing side; `None` on total
+// internal reflection.
+fn refract(direction: Vec3, normal: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = -Vec3::dot(&direction, &normal);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t > 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(direction * eta + normal * (eta * cos_i - cos_t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cemented achromatic doublet of crown and flint glass, with a focal length of 117.79 mm
+    // and a back focal length of 112.85 mm by paraxial ray transfer matrices.
+    fn doublet() -> LensSystem {
+        let element = |radius, thickness, ior| LensElement {
+            radius,
+            thickness,
+            ior,
+            aperture: 25.0,
+        };
+        LensSystem {
+            elements: vec![
+                element(60.0, 6.0, 1.5168),
+                element(-45.0, 2.5, 1.6727),
+                element(-200.0, 50.0, 1.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn focusing_at_infinity_puts_the_focal_point_on_the_film() {
+        let mut lens = doublet();
+        let principal = lens.focus(1e12).unwrap();
+        assert!((principal - 117.7946).abs() < 1e-3, "{}", principal);
+        assert!((lens.rear_z() - 112.8539).abs() < 1e-3, "{}", lens.rear_z());
+    }
+
+    #[test]
+    fn focused_lens_images_the_object_on_the_film() {
+        let mut lens = doublet();
+        let distance = 2000.0;
+        let principal = lens.focus(distance).unwrap();
+        // Closer objects focus behind the focal point
+        assert!(principal > 117.8);
+        // Rays close to the axis, away from the spherical aberration of the rim
+        for slope in [0.0001, -0.0002] {
+            let (origin, direction) = lens
+                .trace_from_scene(Point3::new(0.0, 0.0, distance), Vec3::new(slope, 0.0, -1.0))
+                .unwrap();
+            let crossing = origin.z() - origin.x() * direction.z() / direction.x();
+            assert!(crossing.abs() < 1e-3, "{}", crossing);
+        }
+        // Objects inside the focal length cannot be focused
+        assert!(lens.focus(100.0).is_none());
+    }
+}
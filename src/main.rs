@@ -1,19 +1,63 @@
-use crate::{camera::*, color::*, hittable_list::*, material::*, rtweekend::*, sphere::*, vec3::*};
+use crate::{
+    animation::*, aperture::*, camera::*, cli::*, color::*, denoise::*, display::*, environment::*,
+    hittable_list::*, lens::*, material::*, progress::*, render_control::*, rtweekend::*, sky::*,
+    sphere::*, stats::*, thin_film::*, vec3::*,
+};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
 
+mod animation;
+mod aov;
+mod aperture;
 mod camera;
+mod cli;
 mod color;
+mod denoise;
+mod display;
+mod distributed;
+mod environment;
+mod film;
+mod filter;
+mod hdr_image;
 mod hittable;
 mod hittable_list;
 mod interval;
+mod lens;
 mod material;
+mod medium;
+mod physical;
+mod post;
+mod progress;
+mod projection;
 mod ray;
+mod render_control;
 mod rtweekend;
+mod sampler;
+mod sky;
+mod spectrum;
 mod sphere;
+mod stats;
+mod stereo;
+mod thin_film;
+mod tile;
 mod vec3;
 
 // Main function
 fn main() {
+    // Parsing command-line options
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            std::process::exit(2);
+        }
+    };
+
+    // Seeding the random numbers used to build the scene
+    let scene_start = Instant::now();
+    let mut rng = Rng::new(options.seed);
+
     // Creating a mutable HittableList to represent the world
     let mut world: HittableList = HittableList::new();
 
@@ -25,74 +69,332 @@ fn main() {
         Some(ground_material),
     )));
 
-    // Looping through a grid of spheres to populate the world with random materials
+    // Looping through a grid of spheres to populate the world with random materials, where the
+    // showcased materials take shares from the diffuse, metal and glass spheres
     for a in -20..20 {
         for b in -20..20 {
-            let choose_mat = random_double();
+            let choose_mat = random_double(&mut rng);
             let center: Point3 = Point3::new(
-                a as f64 + 0.9 * random_double(),
+                a as f64 + 0.9 * random_double(&mut rng),
                 0.2,
-                b as f64 + 0.9 * random_double(),
+                b as f64 + 0.9 * random_double(&mut rng),
             );
 
             if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 let sphere_material: Rc<dyn Material>;
 
-                if choose_mat < 0.8 {
-                    let albedo: Vec3 = Vec3::random() * Vec3::random();
+                if choose_mat < 0.77 || (!options.material_showcase && choose_mat < 0.8) {
+                    let albedo: Vec3 = Vec3::random(&mut rng) * Vec3::random(&mut rng);
                     sphere_material = Rc::new(Lambertian::new(albedo.into_color()));
                     world.add(Rc::new(Sphere::new(center, 0.2, Some(sphere_material))));
-                } else if choose_mat < 0.95 {
-                    let albedo: Vec3 = Vec3::random_r(0.5, 1.0);
-                    let fuzz = random_double_range(0.0, 0.5);
+                } else if choose_mat < 0.8 {
+                    // Marble-like translucent solid, red light travelling furthest
+                    let albedo = Color::new(0.99, 0.98, 0.97);
+                    let mean_free_path = Color::new(0.06, 0.04, 0.03);
+                    sphere_material = Rc::new(Subsurface::new(albedo, mean_free_path, 1.5));
+                    world.add(Rc::new(Sphere::new(center, 0.2, Some(sphere_material))));
+                } else if choose_mat < 0.93 || (!options.material_showcase && choose_mat < 0.95) {
+                    let albedo: Vec3 = Vec3::random_r(&mut rng, 0.5, 1.0);
+                    let fuzz = random_double_range(&mut rng, 0.0, 0.5);
                     sphere_material = Rc::new(Metal::new(albedo.into_color(), fuzz));
                     world.add(Rc::new(Sphere::new(center, 0.2, Some(sphere_material))));
-                } else {
+                } else if choose_mat < 0.95 {
+                    // Oil-slick metal with a thin iridescent coating
+                    let film = ThinFilm::new(random_double_range(&mut rng, 250.0, 600.0), 1.45);
+                    let albedo = Color::new(0.3, 0.3, 0.3);
+                    sphere_material = Rc::new(Metal::new(albedo, 0.0).with_film(film));
+                    world.add(Rc::new(Sphere::new(center, 0.2, Some(sphere_material))));
+                } else if options.material_showcase && choose_mat < 0.965 {
+                    // Soap bubble: a water film around air
+                    let film = ThinFilm::new(random_double_range(&mut rng, 250.0, 600.0), 1.33);
+                    sphere_material = Rc::new(Dielectric::new(1.0).with_film(film));
+                    world.add(Rc::new(Sphere::new(center, 0.2, Some(sphere_material))));
+                } else if choose_mat < 0.98 || !options.spectral {
                     sphere_material = Rc::new(Dielectric::new(1.5));
                     world.add(Rc::new(Sphere::new(center, 0.2, Some(sphere_material))));
+                } else {
+                    // Diamond, whose strong dispersion only shows up in spectral mode
+                    sphere_material = Rc::new(Dielectric::cauchy(2.385, 0.0117));
+                    world.add(Rc::new(Sphere::new(center, 0.2, Some(sphere_material))));
                 }
             }
         }
     }
 
-    // Adding three more spheres with different materials to the world
-    let material1 = Rc::new(Dielectric::new(1.5));
-    world.add(Rc::new(Sphere::new(
-        Point3::new(0.0, 1.0, 0.0),
-        1.0,
-        Some(material1),
-    )));
+    // Adding three more spheres with different materials to the world, named so the camera
+    // can focus on them
+    // BK7 crown glass in spectral mode, where its dispersion shows up
+    let material1 = if options.spectral {
+        Rc::new(Dielectric::sellmeier(
+            [1.03961212, 0.231792344, 1.01046945],
+            [0.00600069867, 0.0200179144, 103.560653],
+        ))
+    } else {
+        Rc::new(Dielectric::new(1.5))
+    };
+    world.add_named(
+        "glass",
+        Rc::new(Sphere::new(
+            Point3::new(0.0, 1.0, 0.0),
+            1.0,
+            Some(material1),
+        )),
+    );
 
     let material2 = Rc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
-    world.add(Rc::new(Sphere::new(
-        Point3::new(-4.0, 1.0, 0.0),
-        1.0,
-        Some(material2),
-    )));
+    world.add_named(
+        "diffuse",
+        Rc::new(Sphere::new(
+            Point3::new(-4.0, 1.0, 0.0),
+            1.0,
+            Some(material2),
+        )),
+    );
 
     let material3 = Rc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
-    world.add(Rc::new(Sphere::new(
-        Point3::new(4.0, 1.0, 1.0),
-        1.0,
-        Some(material3),
-    )));
+    world.add_named(
+        "metal",
+        Rc::new(Sphere::new(
+            Point3::new(4.0, 1.0, 1.0),
+            1.0,
+            Some(material3),
+        )),
+    );
 
     // Creating and configuring the camera
     let mut cam: Camera = Camera::default();
 
-    cam.aspect_ratio = 16.0 / 9.0;
+    // Panoramas take the shape that holds them
+    cam.aspect_ratio = options
+        .projection
+        .natural_aspect_ratio()
+        .unwrap_or(16.0 / 9.0);
     cam.image_width = 1200;
-    cam.samples_per_pixel = 1;
+    cam.samples_per_pixel = options.samples_per_pixel;
+    cam.max_samples_per_pixel = options.max_samples_per_pixel;
+    cam.adaptive_threshold = options.adaptive_threshold;
+    if let Some(output) = &options.output {
+        cam.output = output.clone();
+    }
+    cam.heatmap = options.heatmap.clone();
+    cam.checkpoint = options.checkpoint.clone();
+    cam.checkpoint_interval = options.checkpoint_interval;
+    cam.resume = options.resume;
+    cam.scene_hash = options.scene_hash();
+    cam.tile_size = options.tile_size;
+    cam.tile_order = options.tile_order;
+    cam.region = options.region;
+    cam.crop = options.crop;
+    cam.worker_timeout = options.worker_timeout;
+    cam.aovs = options.aovs.clone();
+    cam.aov_exr = options.aov_exr.clone();
+    cam.filter = options.filter;
+    cam.effects = options.effects;
+    cam.display = DisplayTransform {
+        exposure: options.exposure,
+        tone_mapper: options.tone_mapper,
+        working_space: options.working_space,
+    };
+    cam.denoiser = options
+        .denoise
+        .then(|| Denoiser::new(options.denoise_strength));
     cam.max_depth = 50;
 
+    cam.projection = options.projection;
     cam.vfov = 20.0;
+    cam.view_height = options.view_height;
+    cam.fisheye_fov = options.fisheye_fov;
+    cam.stereo = options.stereo;
     cam.lookfrom = Point3::new(13.0, 2.0, 3.0);
     cam.lookat = Point3::new(0.0, 0.0, 0.0);
     cam.vup = Vec3::new(0.0, 1.0, 0.0);
 
     cam.defocus_angle = 0.6;
     cam.focus_dist = 10.0;
+    cam.physical = options.physical;
+    cam.cat_eye = options.cat_eye;
+    if let Some(blades) = options.aperture_blades {
+        cam.aperture = ApertureShape::Polygon {
+            blades,
+            rotation: options.aperture_rotation,
+        };
+    }
+
+    // Shaping the aperture after an image, and tracing a lens system instead of the thin lens
+    if let Some(path) = &options.aperture_image {
+        match ApertureMask::load(path) {
+            Ok(mask) => cam.aperture = ApertureShape::Mask(Arc::new(mask)),
+            Err(err) => {
+                eprintln!("error: cannot load {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &options.lens {
+        match LensSystem::load(path) {
+            Ok(lens) => cam.lens = Some(lens),
+            Err(err) => {
+                eprintln!("error: cannot load {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    // Rendering the scene
-    let _ = cam.render(&world);
+    // Moving the animated objects, and the camera to the start of the animation
+    let animation = options
+        .animation
+        .as_ref()
+        .map(|path| match Animation::load(path) {
+            Ok(animation) => animation,
+            Err(err) => {
+                eprintln!("error: cannot load {}: {}", path, err);
+                std::process::exit(1);
+            }
+        });
+    if let Some(animation) = &animation {
+        for (name, object) in &animation.objects {
+            if !world.animate(name, object.clone(), animation.interpolation) {
+                eprintln!("error: no object named {}", name);
+                std::process::exit(1);
+            }
+        }
+        cam.animate(animation, 0.0);
+    }
+
+    // Spreading the samples over the shutter time, half a frame without a physical camera
+    if options.motion_blur {
+        let shutter = options.physical.map_or(0.5 / options.fps, |p| p.shutter);
+        cam.shutter = Some(shutter);
+    }
+
+    // Focusing on a named object, or on what the focus pixel sees, at the camera's time
+    let focus = |cam: &mut Camera| {
+        if let Some(name) = &options.focus_object {
+            let distance = world.center_of(name, cam.time).and_then(|point| {
+                cam.focus_distance_to(point)
+                    .ok_or_else(|| format!("object {} is not in front of the camera", name))
+            });
+            match distance {
+                Ok(distance) => cam.focus_dist = distance,
+                Err(message) => {
+                    eprintln!("error: {}", message);
+                    std::process::exit(1);
+                }
+            }
+        } else if options.autofocus {
+            match cam.autofocus(&world, options.focus_pixel) {
+                Some(distance) => cam.focus_dist = distance,
+                None => eprintln!(
+                    "warning: autofocus found nothing to focus on, keeping {} m",
+                    cam.focus_dist
+                ),
+            }
+        }
+    };
+    focus(&mut cam);
+
+    cam.spectral = options.spectral;
+    cam.seed = options.seed;
+    cam.sampler = options.sampler;
+
+    // Lighting the scene with the physical sky, placing the sun by date when given
+    if options.sky {
+        let sun = match (options.location, options.datetime) {
+            (Some((latitude, longitude)), Some((year, month, day, hour))) => {
+                solar_position(latitude, longitude, year, month, day, hour)
+            }
+            _ => sun_direction(options.sun_elevation, options.sun_azimuth),
+        };
+        let ground_albedo = Color::new(
+            options.ground_albedo,
+            options.ground_albedo,
+            options.ground_albedo,
+        );
+        let sky = PhysicalSky::new(sun, options.turbidity, ground_albedo);
+        cam.background = Rc::new(sky.with_intensity(options.sky_intensity));
+    }
+
+    // Lighting the scene with an environment map instead of the sky gradient
+    if let Some(path) = &options.environment {
+        match EnvironmentMap::load(path) {
+            Ok(map) => {
+                cam.background = Rc::new(
+                    map.with_rotation(options.environment_rotation)
+                        .with_intensity(options.environment_intensity),
+                )
+            }
+            Err(err) => {
+                eprintln!("error: cannot load {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    record_phase("Building scene", scene_start.elapsed());
+
+    // Stopping the render on the time budget, noise target or Ctrl-C, pressed twice to quit
+    let mut control = RenderControl::new();
+    if let Some(budget) = options.time_budget {
+        control = control.with_time_budget(budget);
+    }
+    if let Some(target) = options.noise_target {
+        control = control.with_noise_target(target);
+    }
+    if !options.quiet {
+        let mut bar = ProgressBar::new();
+        control = control.with_progress_callback(move |progress| bar.update(progress));
+    }
+    let handler_control = control.clone();
+    let handler = ctrlc::set_handler(move || {
+        if handler_control.is_cancelled() {
+            std::process::exit(130);
+        }
+        eprintln!(
+            "\nStopping at {:.1}% after the current tile, press Ctrl-C again to quit",
+            100.0 * handler_control.progress()
+        );
+        handler_control.cancel();
+    });
+    if let Err(err) = handler {
+        eprintln!("warning: cannot handle Ctrl-C: {}", err);
+    }
+
+    // Rendering the scene, locally or spread over worker processes, or the frames of the
+    // animation one after another
+    let result = match (&options.coordinator, &options.worker, options.frames) {
+        (Some(address), _, _) => cam.render_distributed(address, &control),
+        (_, Some(address), _) => cam.render_worker(&world, address),
+        (_, _, Some((first, last))) => (first..=last)
+            .take_while(|_| !control.is_cancelled())
+            .try_for_each(|frame| {
+                let time = frame as f64 / options.fps;
+                if let Some(animation) = &animation {
+                    cam.animate(animation, time);
+                }
+                focus(&mut cam);
+                // Every frame writes its own images and checkpoint
+                let numbered = |path: &String| {
+                    let (stem, extension) = split_extension(path);
+                    format!("{}_{:04}{}", stem, frame, extension)
+                };
+                cam.output = match &options.output {
+                    Some(output) => numbered(output),
+                    None => format!("frame_{:04}.png", frame),
+                };
+                cam.heatmap = options.heatmap.as_ref().map(numbered);
+                cam.checkpoint = options.checkpoint.as_ref().map(numbered);
+                cam.aov_exr = options.aov_exr.as_ref().map(numbered);
+                // Every frame has the whole time budget
+                if let Some(budget) = options.time_budget {
+                    control.set_deadline(Some(Instant::now() + budget));
+                }
+                println!("Frame {} at {:.3} s", frame, time);
+                cam.render(&world, &control)
+            }),
+        _ => cam.render(&world, &control),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
 }
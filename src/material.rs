@@ -1,7 +1,13 @@
-use crate::{color::*, hittable::*, ray::*, rtweekend::*, vec3::*};
+use crate::{color::*, hittable::*, medium::*, ray::*, rtweekend::*, thin_film::*, vec3::*};
+use std::f64::consts::PI;
+use std::hash::Hash;
 
 /// Represents a material that can interact with rays in the scene.
-pub trait Material {
+pub trait Material: std::fmt::Debug {
+    /// Feeds the kind of material and the bits of its parameters to `state`, which identifies
+    /// equal materials in material ID passes.
+    fn hash_parameters(&self, state: &mut StableHasher);
+
     /// Computes the scattered ray and attenuation after interaction.
     fn scatter(
         &self,
@@ -9,7 +15,19 @@ pub trait Material {
         rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
+        rng: &mut Rng,
     ) -> bool;
+
+    /// Returns the density of `scatter` choosing the scattered direction, for diffuse materials
+    /// that can share directions with light sampling. Other materials return zero.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    /// Returns the medium filling the inside of closed objects made of this material.
+    fn interior(&self) -> Option<&Medium> {
+        None
+    }
 }
 
 /// Lambertian material, representing matte surfaces with diffuse reflection.
@@ -27,14 +45,20 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
+    fn hash_parameters(&self, state: &mut StableHasher) {
+        "lambertian".hash(state);
+        self.albedo.hash(state);
+    }
+
     fn scatter(
         &self,
-        r_in: &Ray,
+        _r_in: &Ray,
         rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
+        rng: &mut Rng,
     ) -> bool {
-        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+        let mut scatter_direction = rec.normal + Vec3::random_unit_vector(rng);
         if scatter_direction.near_zero() {
             scatter_direction = rec.normal;
         }
@@ -42,6 +66,11 @@ impl Material for Lambertian {
         *attenuation = self.albedo;
         true
     }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cos_theta = Vec3::dot(&rec.normal, &Vec3::unit_vector(scattered.direction()));
+        f64::max(cos_theta, 0.0) / PI
+    }
 }
 
 /// Metal material, representing reflective surfaces.
@@ -51,42 +80,169 @@ pub struct Metal {
     albedo: Color,
     // Controls the amount of fuzziness or roughness of the material's reflective surface.
     fuzz: f64,
+    // Optional thin coating on top of the metal, producing iridescent reflections.
+    film: Option<ThinFilm>,
 }
 
 impl Metal {
     /// Creates a new Metal material with the given albedo color and fuzziness factor.
     pub fn new(albedo: Color, fuzz: f64) -> Self {
         let fuzz = if fuzz < 1.0 { fuzz } else { 1.0 };
-        Metal { albedo, fuzz }
+        Metal {
+            albedo,
+            fuzz,
+            film: None,
+        }
+    }
+
+    /// Returns a copy of the material coated with the given thin film.
+    pub fn with_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    // Complex index of refraction (n, k) per channel whose normal-incidence reflectance is the
+    // albedo, using the albedo as the edge tint too (Gulbrandsen, "Artist Friendly Metallic
+    // Fresnel").
+    fn substrate_ior(&self) -> (Color, Color) {
+        let ior = |r: f64| {
+            let r = r.clamp(0.0, 0.99);
+            let (n_min, n_max) = ((1.0 - r) / (1.0 + r), (1.0 + r.sqrt()) / (1.0 - r.sqrt()));
+            let n = r * n_min + (1.0 - r) * n_max;
+            let k2 = (r * (n + 1.0).powi(2) - (n - 1.0).powi(2)) / (1.0 - r);
+            (n, k2.max(0.0).sqrt())
+        };
+        let [(nr, kr), (ng, kg), (nb, kb)] = [
+            ior(self.albedo.0.x()),
+            ior(self.albedo.0.y()),
+            ior(self.albedo.0.z()),
+        ];
+        (Color::new(nr, ng, nb), Color::new(kr, kg, kb))
     }
 }
 
 impl Material for Metal {
+    fn hash_parameters(&self, state: &mut StableHasher) {
+        "metal".hash(state);
+        self.albedo.hash(state);
+        self.fuzz.to_bits().hash(state);
+        self.film.hash(state);
+    }
+
     fn scatter(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
+        rng: &mut Rng,
     ) -> bool {
         let reflected = Vec3::reflect(Vec3::unit_vector(r_in.direction()), rec.normal);
-        *scattered = Ray::new(rec.p, reflected + Vec3::random_unit_vector() * self.fuzz);
-        *attenuation = self.albedo;
+        *scattered = Ray::new(rec.p, reflected + Vec3::random_unit_vector(rng) * self.fuzz);
+        *attenuation = match &self.film {
+            Some(film) => {
+                let cos_theta = Vec3::dot(&Vec3::unit_vector(-r_in.direction()), &rec.normal);
+                let cos_theta = cos_theta.clamp(0.0, 1.0);
+                let (n, k) = self.substrate_ior();
+                film.reflectance_color(cos_theta, 1.0, n, k, r_in.wavelength())
+            }
+            None => self.albedo,
+        };
         Vec3::dot(&scattered.direction(), &rec.normal) > 0.0
     }
 }
 
+/// Wavelength dependence of a dielectric's index of refraction.
+#[derive(Debug, Copy, Clone)]
+pub enum Dispersion {
+    /// The same index of refraction at every wavelength.
+    Constant,
+    /// Cauchy's equation n = A + B / λ², with λ in micrometres.
+    Cauchy { a: f64, b: f64 },
+    /// Three-term Sellmeier equation, with the C coefficients in micrometres squared.
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// Evaluates the index of refraction at a wavelength in nanometres.
+    ///
+    /// Dispersive indices are at least one, also where the equations break down, such as at a
+    /// Sellmeier resonance or with coefficients that give a negative squared index.
+    pub fn ior(&self, ir: f64, lambda: f64) -> f64 {
+        let l = lambda / 1000.0;
+        let l2 = l * l;
+        let n = match *self {
+            Dispersion::Constant => return ir,
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>();
+                n2.sqrt()
+            }
+        };
+        if n.is_finite() {
+            n.max(1.0)
+        } else {
+            1.0
+        }
+    }
+}
+
 /// Dielectric material, representing transparent materials like glass.
 #[derive(Debug, Copy, Clone)]
 pub struct Dielectric {
     // Refractive index of the material, determining how light bends as it passes through.
     ir: f64,
+    // How the refractive index varies with wavelength in spectral mode.
+    dispersion: Dispersion,
+    // Optional thin coating on the outer surface, producing iridescent reflections.
+    film: Option<ThinFilm>,
 }
 
 impl Dielectric {
+    /// Wavelength in nanometres at which dispersive materials are evaluated in RGB mode (the
+    /// sodium d-line).
+    const REFERENCE_WAVELENGTH: f64 = 587.6;
+
     /// Creates a new Dielectric material with the given index of refraction.
     pub fn new(ir: f64) -> Self {
-        Dielectric { ir }
+        Dielectric {
+            ir,
+            dispersion: Dispersion::Constant,
+            film: None,
+        }
+    }
+
+    /// Creates a dispersive Dielectric following Cauchy's equation.
+    pub fn cauchy(a: f64, b: f64) -> Self {
+        Dielectric::dispersive(Dispersion::Cauchy { a, b })
+    }
+
+    /// Creates a dispersive Dielectric following the Sellmeier equation.
+    pub fn sellmeier(b: [f64; 3], c: [f64; 3]) -> Self {
+        Dielectric::dispersive(Dispersion::Sellmeier { b, c })
+    }
+
+    // Creates a Dielectric whose RGB-mode index is taken at the reference wavelength.
+    fn dispersive(dispersion: Dispersion) -> Self {
+        Dielectric {
+            ir: dispersion.ior(1.0, Dielectric::REFERENCE_WAVELENGTH),
+            dispersion,
+            film: None,
+        }
+    }
+
+    /// Returns a copy of the material coated with the given thin film.
+    pub fn with_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    /// Returns the index of refraction for a ray's wavelength, or the RGB-mode index.
+    pub fn ior(&self, wavelength: Option<f64>) -> f64 {
+        match wavelength {
+            Some(lambda) => self.dispersion.ior(self.ir, lambda),
+            None => self.ir,
+        }
     }
 
     /// Computes the reflectance of a dielectric surface.
@@ -98,35 +254,193 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
+    fn hash_parameters(&self, state: &mut StableHasher) {
+        "dielectric".hash(state);
+        self.ir.to_bits().hash(state);
+        match self.dispersion {
+            Dispersion::Constant => 0u8.hash(state),
+            Dispersion::Cauchy { a, b } => (1u8, a.to_bits(), b.to_bits()).hash(state),
+            Dispersion::Sellmeier { b, c } => {
+                2u8.hash(state);
+                for value in b.iter().chain(&c) {
+                    value.to_bits().hash(state);
+                }
+            }
+        }
+        self.film.hash(state);
+    }
+
     fn scatter(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
+        rng: &mut Rng,
     ) -> bool {
         *attenuation = Color::new(1.0, 1.0, 1.0);
-        let refraction_ratio: f64 = if rec.front_face {
-            1.0 / self.ir
-        } else {
-            self.ir
-        };
+        let ir = self.ior(r_in.wavelength());
+        let refraction_ratio: f64 = if rec.front_face { 1.0 / ir } else { ir };
 
         let unit_direction: Vec3 = Vec3::unit_vector(r_in.direction());
         let cos_theta: f64 = f64::min(Vec3::dot(&(-unit_direction), &rec.normal), 1.0);
         let sin_theta: f64 = f64::sqrt(1.0 - cos_theta * cos_theta);
 
         let cannot_refract: bool = refraction_ratio * sin_theta > 1.0;
-        let direction: Vec3;
+        let reflect: bool = if cannot_refract {
+            true
+        } else if let Some(film) = &self.film {
+            // Choose by the average film reflectance and reweight each channel accordingly
+            let (n_outer, n_inner) = if rec.front_face { (1.0, ir) } else { (ir, 1.0) };
+            let n_inner = Color::new(n_inner, n_inner, n_inner);
+            let k_inner = Color::new(0.0, 0.0, 0.0);
+            let r = film.reflectance_color(cos_theta, n_outer, n_inner, k_inner, r_in.wavelength());
+            let p = r.average();
+            if p > random_double(rng) {
+                *attenuation = r * (1.0 / p);
+                true
+            } else {
+                *attenuation = (Color::new(1.0, 1.0, 1.0) - r) * (1.0 / (1.0 - p));
+                false
+            }
+        } else {
+            Dielectric::reflectance(cos_theta, refraction_ratio) > random_double(rng)
+        };
 
-        if cannot_refract || Dielectric::reflectance(cos_theta, refraction_ratio) > random_double()
-        {
-            direction = Vec3::reflect(unit_direction, rec.normal);
+        let direction: Vec3 = if reflect {
+            Vec3::reflect(unit_direction, rec.normal)
         } else {
-            direction = Vec3::refract(&unit_direction, &rec.normal, refraction_ratio);
-        }
+            Vec3::refract(&unit_direction, &rec.normal, refraction_ratio)
+        };
 
         *scattered = Ray::new(rec.p, direction);
         true
     }
 }
+
+/// Subsurface scattering material, for translucent solids like skin, wax and marble.
+///
+/// Light refracts through a dielectric boundary and random-walks through the medium inside,
+/// so it should only be used on closed objects.
+#[derive(Debug, Copy, Clone)]
+pub struct Subsurface {
+    // Smooth dielectric interface between the outside and the interior.
+    boundary: Dielectric,
+    // Scattering medium filling the object.
+    medium: Medium,
+}
+
+impl Subsurface {
+    /// Creates a new Subsurface material from the single-scattering albedo, the mean free path
+    /// per color channel and the index of refraction of the boundary.
+    pub fn new(albedo: Color, mean_free_path: Color, ir: f64) -> Self {
+        Subsurface {
+            boundary: Dielectric::new(ir),
+            medium: Medium::from_albedo(albedo, mean_free_path),
+        }
+    }
+}
+
+impl Material for Subsurface {
+    fn hash_parameters(&self, state: &mut StableHasher) {
+        "subsurface".hash(state);
+        self.boundary.hash_parameters(state);
+        self.medium.hash(state);
+    }
+
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+        rng: &mut Rng,
+    ) -> bool {
+        self.boundary
+            .scatter(r_in, rec, attenuation, scattered, rng)
+    }
+
+    fn interior(&self) -> Option<&Medium> {
+        Some(&self.medium)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_index_ignores_the_wavelength() {
+        let glass = Dielectric::new(1.5);
+        assert_eq!(glass.ior(None), 1.5);
+        assert_eq!(glass.ior(Some(400.0)), 1.5);
+    }
+
+    #[test]
+    fn cauchy_index_follows_the_inverse_square_wavelength() {
+        let glass = Dielectric::cauchy(1.5, 0.004);
+        assert!((glass.ior(Some(500.0)) - 1.516).abs() < 1e-12);
+        assert!(glass.ior(Some(400.0)) > glass.ior(Some(700.0)));
+    }
+
+    #[test]
+    fn sellmeier_bk7_matches_its_catalogue_index() {
+        let bk7 = Dielectric::sellmeier(
+            [1.03961212, 0.231792344, 1.01046945],
+            [0.00600069867, 0.0200179144, 103.560653],
+        );
+        // Catalogue values at the d, F and C lines
+        assert!((bk7.ior(Some(587.6)) - 1.5168).abs() < 1e-4);
+        assert!((bk7.ior(Some(486.1)) - 1.5224).abs() < 1e-4);
+        assert!((bk7.ior(Some(656.3)) - 1.5143).abs() < 1e-4);
+        // RGB mode uses the index at the d line
+        assert_eq!(bk7.ior(None), bk7.ior(Some(587.6)));
+    }
+
+    #[test]
+    fn broken_dispersion_coefficients_keep_a_valid_index() {
+        // A resonance at exactly 500 nm, and coefficients making the squared index negative
+        let resonant = Dielectric::sellmeier([1.0, 0.0, 0.0], [0.25, 0.0, 0.0]);
+        let negative = Dielectric::sellmeier([-3.0, 0.0, 0.0], [0.0, 0.0, 0.0]);
+        let thin = Dielectric::cauchy(0.5, 0.0);
+        for glass in [resonant, negative, thin] {
+            for lambda in [400.0, 500.0, 700.0] {
+                let ior = glass.ior(Some(lambda));
+                assert!(ior.is_finite() && ior >= 1.0, "{:?} at {}", glass, lambda);
+            }
+        }
+    }
+
+    #[test]
+    fn metal_substrate_reflects_its_albedo_head_on() {
+        let albedo = Color::new(0.9, 0.6, 0.2);
+        let (n, k) = Metal::new(albedo, 0.0).substrate_ior();
+        // A film of zero thickness leaves the bare substrate
+        let r = ThinFilm::new(0.0, 1.4).reflectance_color(1.0, 1.0, n, k, None);
+        assert!((r.0.x() - 0.9).abs() < 1e-9);
+        assert!((r.0.y() - 0.6).abs() < 1e-9);
+        assert!((r.0.z() - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn subsurface_refracts_like_its_boundary_and_fills_its_interior() {
+        let marble = Subsurface::new(Color::new(0.9, 0.9, 0.9), Color::new(0.1, 0.1, 0.1), 1.5);
+        let glass = Dielectric::new(1.5);
+        assert!(marble.interior().is_some());
+        assert!(glass.interior().is_none());
+
+        let r = Ray::new(Point3::new(0.0, 1.0, 1.0), Vec3::new(0.3, -1.0, -1.0));
+        let mut rec = HitRecord::default();
+        rec.p = Point3::zero();
+        rec.set_face_normal(&r, &Vec3::new(0.0, 1.0, 0.0));
+        for seed in 0..32 {
+            let (mut a, mut b) = (Color::zero(), Color::zero());
+            let (mut sa, mut sb) = (r, r);
+            let hit_a = marble.scatter(&r, &rec, &mut a, &mut sa, &mut Rng::new(seed));
+            let hit_b = glass.scatter(&r, &rec, &mut b, &mut sb, &mut Rng::new(seed));
+            assert_eq!(hit_a, hit_b);
+            assert_eq!(a.0.e, b.0.e);
+            assert_eq!(sa.direction().e, sb.direction().e);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{color::*, ray::*, rtweekend::*, spectrum::*};
+use std::hash::{Hash, Hasher};
+
+/// Outcome of sampling a free-flight distance along a ray inside a medium.
+pub enum FreeFlight {
+    /// The ray scatters at ray parameter `t`, with the path weight of the event.
+    Scatter { t: f64, weight: Color },
+    /// The ray reaches the end of the segment, with the path weight of the transmission.
+    Escape { weight: Color },
+}
+
+/// A homogeneous participating medium with per-channel absorption and scattering.
+#[derive(Debug, Copy, Clone)]
+pub struct Medium {
+    // Absorption coefficient per unit length.
+    sigma_a: Color,
+    // Scattering coefficient per unit length.
+    sigma_s: Color,
+}
+
+impl Hash for Medium {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sigma_a.hash(state);
+        self.sigma_s.hash(state);
+    }
+}
+
+impl Medium {
+    /// Creates a new medium from its absorption and scattering coefficients.
+    pub fn new(sigma_a: Color, sigma_s: Color) -> Self {
+        Medium { sigma_a, sigma_s }
+    }
+
+    /// Creates a medium from its single-scattering albedo and mean free path per channel.
+    pub fn from_albedo(albedo: Color, mean_free_path: Color) -> Self {
+        let sigma_t = Color::new(
+            1.0 / mean_free_path.0.x(),
+            1.0 / mean_free_path.0.y(),
+            1.0 / mean_free_path.0.z(),
+        );
+        let sigma_s = sigma_t * albedo;
+        Medium::new(sigma_t - sigma_s, sigma_s)
+    }
+
+    /// Samples where a ray travelling up to parameter `t_max` first interacts with the medium.
+    ///
+    /// The distance is drawn from one channel picked at random, and the weight is the
+    /// one-sample MIS estimate over all channels so differing mean free paths stay unbiased.
+    pub fn sample(&self, r: &Ray, t_max: f64, rng: &mut Rng) -> FreeFlight {
+        let sigma_s = spectral_sample(self.sigma_s, r.wavelength());
+        let sigma_t = sigma_s + spectral_sample(self.sigma_a, r.wavelength());
+        let speed = r.direction().length();
+        let distance = t_max * speed;
+
+        let channel = ((random_double(rng) * 3.0) as usize).min(2);
+        let d = -(1.0 - random_double(rng)).ln() / sigma_t.0.e[channel];
+        let transmittance = |d: f64| {
+            Color::new(
+                (-sigma_t.0.x() * d).exp(),
+                (-sigma_t.0.y() * d).exp(),
+                (-sigma_t.0.z() * d).exp(),
+            )
+        };
+
+        if d < distance {
+            let tr = transmittance(d);
+            let pdf = (sigma_t * tr).average();
+            FreeFlight::Scatter {
+                t: d / speed,
+                weight: sigma_s * tr * (1.0 / pdf),
+            }
+        } else {
+            let tr = transmittance(distance);
+            let pdf = tr.average();
+            FreeFlight::Escape {
+                weight: if pdf > 0.0 {
+                    tr * (1.0 / pdf)
+                } else {
+                    Color::zero()
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::*;
+
+    // Averages the escape and scatter weights of many free flights over a segment of length one.
+    fn mean_weights(medium: &Medium, samples: usize) -> ([f64; 3], [f64; 3]) {
+        let r = Ray::new(Point3::zero(), Vec3::new(1.0, 0.0, 0.0));
+        let mut rng = Rng::new(7);
+        let mut escape = [0.0; 3];
+        let mut scatter = [0.0; 3];
+        for _ in 0..samples {
+            let (sum, weight) = match medium.sample(&r, 1.0, &mut rng) {
+                FreeFlight::Scatter { t, weight } => {
+                    assert!(t > 0.0 && t < 1.0);
+                    (&mut scatter, weight)
+                }
+                FreeFlight::Escape { weight } => (&mut escape, weight),
+            };
+            for (sum, weight) in sum.iter_mut().zip(weight.0.e) {
+                *sum += weight / samples as f64;
+            }
+        }
+        (escape, scatter)
+    }
+
+    #[test]
+    fn albedo_sets_the_share_of_scattering() {
+        let medium = Medium::from_albedo(Color::new(0.5, 0.8, 1.0), Color::new(0.5, 0.25, 0.1));
+        let sigma_t = [2.0, 4.0, 10.0];
+        let albedo = [0.5, 0.8, 1.0];
+        for c in 0..3 {
+            let sigma_a = medium.sigma_a.0.e[c];
+            let sigma_s = medium.sigma_s.0.e[c];
+            assert!((sigma_a + sigma_s - sigma_t[c]).abs() < 1e-12);
+            assert!((sigma_s / sigma_t[c] - albedo[c]).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn escape_weight_matches_the_transmittance() {
+        let medium = Medium::new(Color::new(0.5, 1.0, 2.0), Color::new(0.5, 0.5, 1.0));
+        let (escape, _) = mean_weights(&medium, 200_000);
+        for (c, sigma_t) in [1.0_f64, 1.5, 3.0].iter().enumerate() {
+            let expected = (-sigma_t).exp();
+            assert!(
+                (escape[c] - expected).abs() < 0.01,
+                "channel {}: {} vs {}",
+                c,
+                escape[c],
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn white_medium_conserves_energy_across_channels() {
+        // Differing mean free paths exercise the one-sample MIS over channels
+        let medium = Medium::from_albedo(Color::new(1.0, 1.0, 1.0), Color::new(1.0, 0.5, 0.2));
+        let (escape, scatter) = mean_weights(&medium, 200_000);
+        for c in 0..3 {
+            let total = escape[c] + scatter[c];
+            assert!((total - 1.0).abs() < 0.02, "channel {}: {}", c, total);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::hash::{Hash, Hasher};
+
+/// Settings of a real camera, from which the field of view, depth of field and exposure
+/// follow, so renders can match photographs taken with the same settings.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PhysicalCamera {
+    /// Focal length of the lens in millimetres.
+    pub focal_length: f64,
+
+    /// Width of the sensor in millimetres.
+    pub sensor_width: f64,
+
+    /// Height of the sensor in millimetres.
+    pub sensor_height: f64,
+
+    /// Focal length divided by the diameter of the aperture.
+    pub f_number: f64,
+
+    /// Time the shutter is open, in seconds.
+    pub shutter: f64,
+
+    /// Sensitivity of the sensor.
+    pub iso: f64,
+
+    /// Luminance in cd/m² of a scene radiance of one. The physical sky's default intensity,
+    /// 0.1 per kcd/m², makes this 10,000.
+    pub radiance_unit: f64,
+}
+
+impl PhysicalCamera {
+    /// Creates a 50 mm lens on a full-frame sensor at f/8, 1/125 s and ISO 100, which exposes
+    /// a radiance of one at about one.
+    pub fn default() -> Self {
+        PhysicalCamera {
+            focal_length: 50.0,
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            f_number: 8.0,
+            shutter: 1.0 / 125.0,
+            iso: 100.0,
+            radiance_unit: 10_000.0,
+        }
+    }
+
+    /// Returns the vertical field of view in degrees of an image with the given aspect ratio,
+    /// cut from the middle of the sensor.
+    pub fn vfov(&self, aspect_ratio: f64) -> f64 {
+        let height = self.sensor_height.min(self.sensor_width / aspect_ratio);
+        2.0 * (0.5 * height / self.focal_length).atan().to_degrees()
+    }
+
+    /// Returns the focal length in millimetres giving the vertical field of view `vfov` in
+    /// degrees to an image with the given aspect ratio; the inverse of `vfov`.
+    pub fn focal_length_for(&self, vfov: f64, aspect_ratio: f64) -> f64 {
+        let height = self.sensor_height.min(self.sensor_width / aspect_ratio);
+        0.5 * height / (0.5 * vfov.to_radians()).tan()
+    }
+
+    /// Returns the defocus angle in degrees of the aperture, seen from the focus distance in
+    /// metres.
+    pub fn defocus_angle(&self, focus_dist: f64) -> f64 {
+        let aperture_radius = 0.5 * self.focal_length / self.f_number / 1000.0;
+        2.0 * (aperture_radius / focus_dist).atan().to_degrees()
+    }
+
+    /// Returns the exposure value at ISO 100 of the aperture and shutter speed.
+    pub fn exposure_value(&self) -> f64 {
+        (self.f_number * self.f_number / self.shutter * 100.0 / self.iso).log2()
+    }
+
+    /// Returns the factor scaling scene radiance to the sensor's response, where one is the
+    /// luminance that saturates the sensor.
+    pub fn exposure_scale(&self) -> f64 {
+        // Saturation-based sensitivity, with the usual factor of 78 / (100 · 0.65)
+        let max_luminance = 1.2 * self.exposure_value().exp2();
+        self.radiance_unit / max_luminance
+    }
+}
+
+impl Hash for PhysicalCamera {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let settings = [
+            self.focal_length,
+            self.sensor_width,
+            self.sensor_height,
+            self.f_number,
+            self.shutter,
+            self.iso,
+            self.radiance_unit,
+        ];
+        for value in settings {
+            value.to_bits().hash(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the default camera with another aperture, shutter speed and sensitivity.
+    fn camera(f_number: f64, shutter: f64, iso: f64) -> PhysicalCamera {
+        PhysicalCamera {
+            f_number,
+            shutter,
+            iso,
+            ..PhysicalCamera::default()
+        }
+    }
+
+    #[test]
+    fn exposure_values_follow_the_usual_table() {
+        let ev = |f_number, shutter, iso| camera(f_number, shutter, iso).exposure_value();
+        assert!(ev(1.0, 1.0, 100.0).abs() < 1e-12);
+        // Sunny 16: f/16 at 1/125 s is about EV 15
+        assert!((ev(16.0, 1.0 / 125.0, 100.0) - 14.966).abs() < 1e-3);
+        // A stop of aperture, shutter or sensitivity is one EV
+        assert!((ev(2.0_f64.sqrt(), 1.0, 100.0) - 1.0).abs() < 1e-12);
+        assert!((ev(1.0, 0.5, 100.0) - 1.0).abs() < 1e-12);
+        assert!((ev(1.0, 1.0, 200.0) + 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn focal_lengths_give_back_the_field_of_view() {
+        let camera = PhysicalCamera::default();
+        for aspect_ratio in [1.0, 1.5, 16.0 / 9.0] {
+            let vfov = camera.vfov(aspect_ratio);
+            let focal_length = camera.focal_length_for(vfov, aspect_ratio);
+            assert!((focal_length - 50.0).abs() < 1e-9);
+        }
+        // A full-frame sensor is 24 mm high, so 24 mm lenses see 53.13 degrees vertically
+        assert!(
+            (camera.focal_length_for(2.0 * 0.5_f64.atan().to_degrees(), 1.5) - 24.0).abs() < 1e-9
+        );
+    }
+
+    #[test]
+    fn default_camera_exposes_a_radiance_of_one_at_about_one() {
+        let scale = PhysicalCamera::default().exposure_scale();
+        assert!((scale - 10_000.0 / (1.2 * 8_000.0)).abs() < 1e-12);
+        let brighter = camera(8.0, 2.0 / 125.0, 100.0).exposure_scale();
+        assert!((brighter / scale - 2.0).abs() < 1e-12);
+    }
+}
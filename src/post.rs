@@ -0,0 +1,358 @@
+use crate::{color::*, rtweekend::*, tile::*};
+use std::f64::consts::PI;
+
+/// Lens and film effects applied to the rendered radiance before the display transform.
+///
+/// Every effect is off at zero strength. Effects that depend on the position in the picture,
+/// like vignetting, are placed relative to the whole image, so a rendered region looks as it
+/// does in the full frame.
+#[derive(Debug, Copy, Clone)]
+pub struct PostEffects {
+    /// Luminance above which pixels bloom and glare.
+    pub threshold: f64,
+
+    /// Strength of the soft glow around bright pixels.
+    pub bloom: f64,
+
+    /// Radius of the glow, as a fraction of the image diagonal.
+    pub bloom_radius: f64,
+
+    /// Strength of the star-shaped streaks from bright pixels.
+    pub glare: f64,
+
+    /// Number of streaks around every bright pixel.
+    pub glare_streaks: u32,
+
+    /// Length of the streaks, as a fraction of the image diagonal.
+    pub glare_length: f64,
+
+    /// Darkening of the image corners, following the cosine-fourth falloff of a lens whose
+    /// corners are at this tangent of the off-axis angle.
+    pub vignette: f64,
+
+    /// Separation in pixels of the red and blue images at the image corners.
+    pub chromatic_aberration: f64,
+
+    /// Standard deviation of the film grain, relative to the pixel's brightness.
+    pub grain: f64,
+}
+
+impl PostEffects {
+    /// Creates effects that are all off.
+    pub fn default() -> Self {
+        PostEffects {
+            threshold: 1.0,
+            bloom: 0.0,
+            bloom_radius: 0.02,
+            glare: 0.0,
+            glare_streaks: 6,
+            glare_length: 0.1,
+            vignette: 0.0,
+            chromatic_aberration: 0.0,
+            grain: 0.0,
+        }
+    }
+
+    /// Returns whether any effect is on.
+    pub fn is_active(&self) -> bool {
+        self.bloom > 0.0
+            || self.glare > 0.0
+            || self.vignette > 0.0
+            || self.chromatic_aberration != 0.0
+            || self.grain > 0.0
+    }
+
+    /// Applies the effects to the colors of `area`, given in row-major order, of an image of
+    /// `width` by `height` pixels. `seed` draws the film grain, so equal seeds give the same
+    /// grain.
+    pub fn apply(&self, colors: &mut [Color], area: Tile, (width, height): (i32, i32), seed: u64) {
+        if !self.is_active() {
+            return;
+        }
+        let image = Image {
+            area,
+            center: (0.5 * (width - 1) as f64, 0.5 * (height - 1) as f64),
+            half_diagonal: 0.5 * ((width * width + height * height) as f64).sqrt(),
+        };
+
+        if self.chromatic_aberration != 0.0 {
+            self.apply_chromatic_aberration(colors, &image);
+        }
+        if self.vignette > 0.0 {
+            for ((i, j), color) in area.pixels().zip(colors.iter_mut()) {
+                let r = image.radius(i, j) * self.vignette;
+                let cos2 = 1.0 / (1.0 + r * r);
+                *color = *color * (cos2 * cos2);
+            }
+        }
+        if self.bloom > 0.0 || self.glare > 0.0 {
+            let bright: Vec<Color> = colors.iter().map(|&c| self.bright_part(c)).collect();
+            if self.bloom > 0.0 {
+                let sigma = self.bloom_radius * 2.0 * image.half_diagonal / 3.0;
+                let glow = gaussian_blur(&bright, &area, sigma);
+                for (color, glow) in colors.iter_mut().zip(glow) {
+                    *color += glow * self.bloom;
+                }
+            }
+            if self.glare > 0.0 {
+                let length = self.glare_length * 2.0 * image.half_diagonal;
+                let streaks = self.glare_streaks.max(1);
+                for k in 0..streaks {
+                    let angle = PI / 4.0 + 2.0 * PI * k as f64 / streaks as f64;
+                    let streak = streak(&bright, &area, (angle.cos(), angle.sin()), length);
+                    for (color, streak) in colors.iter_mut().zip(streak) {
+                        *color += streak * (self.glare / streaks as f64);
+                    }
+                }
+            }
+        }
+        if self.grain > 0.0 {
+            for ((i, j), color) in area.pixels().zip(colors.iter_mut()) {
+                // Monochrome Gaussian noise, fixed per pixel by the seed
+                let pixel = (j as i64 * width as i64 + i as i64) as u64;
+                let mut rng = Rng::for_sample(seed, pixel, u64::MAX);
+                let u1 = 1.0 - random_double(&mut rng);
+                let u2 = random_double(&mut rng);
+                let normal = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+                *color = *color * (1.0 + self.grain * normal).max(0.0);
+            }
+        }
+    }
+
+    /// Returns the part of a color above the threshold, fading in over a soft knee.
+    fn bright_part(&self, color: Color) -> Color {
+        let luminance = color.luminance();
+        if luminance <= 0.0 {
+            return Color::zero();
+        }
+        let knee = 0.5 * self.threshold;
+        let excess = luminance - self.threshold;
+        let soft = (excess + knee).max(0.0).min(2.0 * knee);
+        let amount = if excess > 0.0 {
+            excess
+        } else {
+            soft * soft / (4.0 * knee + 1e-9)
+        };
+        color * (amount / luminance)
+    }
+
+    /// Shifts the red image outwards and the blue image inwards from the image center, as a
+    /// lens bending colors differently does.
+    fn apply_chromatic_aberration(&self, colors: &mut [Color], image: &Image) {
+        let shift = self.chromatic_aberration / (2.0 * image.half_diagonal);
+        let original = colors.to_vec();
+        let (cx, cy) = image.center;
+        for ((i, j), color) in image.area.pixels().zip(colors.iter_mut()) {
+            let (dx, dy) = (i as f64 - cx, j as f64 - cy);
+            let red = sample(
+                &original,
+                &image.area,
+                cx + dx * (1.0 - shift),
+                cy + dy * (1.0 - shift),
+            );
+            let blue = sample(
+                &original,
+                &image.area,
+                cx + dx * (1.0 + shift),
+                cy + dy * (1.0 + shift),
+            );
+            *color = Color::new(red.0.x(), color.0.y(), blue.0.z());
+        }
+    }
+}
+
+// Placement of the rendered area in the image.
+struct Image {
+    area: Tile,
+    center: (f64, f64),
+    half_diagonal: f64,
+}
+
+impl Image {
+    // Returns the distance of a pixel from the image center, one at the corners.
+    fn radius(&self, i: i32, j: i32) -> f64 {
+        let (dx, dy) = (i as f64 - self.center.0, j as f64 - self.center.1);
+        (dx * dx + dy * dy).sqrt() / self.half_diagonal
+    }
+}
+
+// Returns the color at a position in frame coordinates, interpolating bilinearly between the
+// pixels of `area` and clamping at its edges.
+fn sample(colors: &[Color], area: &Tile, x: f64, y: f64) -> Color {
+    let x = (x - area.x as f64).clamp(0.0, (area.width - 1) as f64);
+    let y = (y - area.y as f64).clamp(0.0, (area.height - 1) as f64);
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let (x1, y1) = ((x0 + 1).min(area.width - 1), (y0 + 1).min(area.height - 1));
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+    let at = |x: i32, y: i32| colors[(y * area.width + x) as usize];
+    let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+    let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+// Blurs an image with a Gaussian of the given standard deviation in pixels, approximated by
+// three box blurs in each direction.
+fn gaussian_blur(colors: &[Color], area: &Tile, sigma: f64) -> Vec<Color> {
+    // Three boxes of width w have a variance of 3 (w² - 1) / 12
+    let radius = (((4.0 * sigma * sigma + 1.0).sqrt() - 1.0) / 2.0)
+        .round()
+        .max(0.0) as usize;
+    let (width, height) = (area.width as usize, area.height as usize);
+    let mut image = colors.to_vec();
+    for _ in 0..3 {
+        image = box_blur(&image, width, height, radius, (1, width));
+        image = box_blur(&image, width, height, radius, (width, 1));
+    }
+    image
+}
+
+// Blurs every line of an image with a box of `radius` pixels on each side. Lines run along
+// `step` elements, and consecutive lines start `stride` elements apart, so rows and columns
+// are blurred alike. Pixels past the edges repeat the edge pixels.
+fn box_blur(
+    colors: &[Color],
+    width: usize,
+    height: usize,
+    radius: usize,
+    (step, stride): (usize, usize),
+) -> Vec<Color> {
+    let (length, lines) = if step == 1 {
+        (width, height)
+    } else {
+        (height, width)
+    };
+    let scale = 1.0 / (2 * radius + 1) as f64;
+    let mut blurred = vec![Color::zero(); colors.len()];
+    for line in 0..lines {
+        let at = |k: isize| colors[line * stride + k.clamp(0, length as isize - 1) as usize * step];
+        let mut sum = Color::zero();
+        for k in -(radius as isize)..=radius as isize {
+            sum += at(k);
+        }
+        for k in 0..length {
+            blurred[line * stride + k * step] = sum * scale;
+            sum += at(k as isize + radius as isize + 1);
+            sum = sum - at(k as isize - radius as isize);
+        }
+    }
+    blurred
+}
+
+// Smears an image along a direction into a streak fading over about `length` pixels, in four
+// passes of four taps each whose spacing grows fourfold per pass.
+fn streak(colors: &[Color], area: &Tile, (dx, dy): (f64, f64), length: f64) -> Vec<Color> {
+    // Attenuation per pixel so the streak fades to a twentieth over its length
+    let attenuation = (0.05f64.ln() / length.max(1.0)).exp();
+    let mut image = colors.to_vec();
+    let mut spacing = 1.0;
+    for _ in 0..4 {
+        let weights: Vec<f64> = (0..4)
+            .map(|tap| attenuation.powf(spacing * tap as f64))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        image = area
+            .pixels()
+            .map(|(i, j)| {
+                let mut sum = Color::zero();
+                for (tap, weight) in weights.iter().enumerate() {
+                    let offset = spacing * tap as f64;
+                    let (x, y) = (i as f64 - dx * offset, j as f64 - dy * offset);
+                    // Nothing streaks in from outside the area
+                    let inside = (area.x as f64..=(area.x + area.width - 1) as f64).contains(&x)
+                        && (area.y as f64..=(area.y + area.height - 1) as f64).contains(&y);
+                    if inside {
+                        sum += sample(&image, area, x, y) * (weight / total);
+                    }
+                }
+                sum
+            })
+            .collect();
+        spacing *= 4.0;
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the colors of an area of a smoothly varying image, in row-major order.
+    fn gradient(area: Tile) -> Vec<Color> {
+        area.pixels()
+            .map(|(i, j)| Color::new(0.5 + 0.02 * i as f64, 0.5, 0.5 + 0.03 * j as f64))
+            .collect()
+    }
+
+    #[test]
+    fn inactive_effects_leave_colors_alone() {
+        let area = Tile::new(0, 0, 8, 6);
+        let mut effects = PostEffects::default();
+        effects.threshold = 0.1;
+        effects.bloom_radius = 0.5;
+        assert!(!effects.is_active());
+        let mut colors = gradient(area);
+        effects.apply(&mut colors, area, (8, 6), 1);
+        for (color, original) in colors.iter().zip(gradient(area)) {
+            assert_eq!(color.0.e, original.0.e);
+        }
+    }
+
+    #[test]
+    fn vignette_darkens_the_corners_only() {
+        let area = Tile::new(0, 0, 9, 7);
+        let mut effects = PostEffects::default();
+        effects.vignette = 1.0;
+        let mut colors = vec![Color::new(1.0, 1.0, 1.0); 9 * 7];
+        effects.apply(&mut colors, area, (9, 7), 1);
+        let at = |i: usize, j: usize| colors[j * 9 + i].0.x();
+        assert_eq!(at(4, 3), 1.0);
+        // The corners of the image are at an off-axis tangent of one, a falloff to a quarter,
+        // a little beyond the centers of the corner pixels
+        for (i, j) in [(0, 0), (8, 0), (0, 6), (8, 6)] {
+            assert!(at(i, j) > 0.25 && at(i, j) < 0.4, "{}", at(i, j));
+        }
+        assert!(at(4, 3) > at(2, 3) && at(2, 3) > at(0, 3));
+    }
+
+    #[test]
+    fn regions_match_the_full_frame() {
+        let frame = Tile::new(0, 0, 24, 16);
+        let region = Tile::new(10, 4, 8, 8);
+        let mut effects = PostEffects::default();
+        effects.vignette = 0.8;
+        effects.chromatic_aberration = 1.0;
+        effects.grain = 0.1;
+        let mut full = gradient(frame);
+        effects.apply(&mut full, frame, (24, 16), 7);
+        let mut part = gradient(region);
+        effects.apply(&mut part, region, (24, 16), 7);
+
+        // Chromatic aberration samples neighbouring pixels, which the region has away from its
+        // edges
+        for ((i, j), color) in region.pixels().zip(&part) {
+            if !(11..17).contains(&i) || !(5..11).contains(&j) {
+                continue;
+            }
+            let expected = full[(j * 24 + i) as usize];
+            for (a, b) in color.0.e.iter().zip(expected.0.e) {
+                assert!((a - b).abs() < 1e-9, "({}, {}): {} vs {}", i, j, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn grain_follows_the_seed() {
+        let area = Tile::new(0, 0, 8, 8);
+        let mut effects = PostEffects::default();
+        effects.grain = 0.2;
+        let grainy = |seed: u64| {
+            let mut colors = vec![Color::new(0.5, 0.5, 0.5); 64];
+            effects.apply(&mut colors, area, (8, 8), seed);
+            colors.iter().map(|c| c.0.e).collect::<Vec<_>>()
+        };
+        assert_eq!(grainy(3), grainy(3));
+        assert_ne!(grainy(3), grainy(4));
+        // The grain is monochrome
+        assert!(grainy(3).iter().all(|[r, g, b]| r == g && g == b));
+    }
+}
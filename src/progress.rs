@@ -0,0 +1,144 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// State of a running render, passed to progress callbacks.
+#[derive(Debug, Copy, Clone)]
+pub struct Progress {
+    /// Fraction of the render done, between 0 and 1.
+    pub fraction: f64,
+
+    /// Pass over the image being rendered, starting at 1.
+    pub pass: u32,
+
+    /// Time since rendering started.
+    pub elapsed: Duration,
+
+    /// Rays traced per second since rendering started.
+    pub rays_per_second: f64,
+
+    /// Whether rendering has stopped; this is the last report.
+    pub finished: bool,
+}
+
+impl Progress {
+    /// Returns the estimated time left, once some progress has been made.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.finished {
+            return Some(Duration::ZERO);
+        }
+        if self.fraction <= 0.0 {
+            return None;
+        }
+        let remaining = self.elapsed.as_secs_f64() * (1.0 - self.fraction) / self.fraction;
+        Duration::try_from_secs_f64(remaining).ok()
+    }
+}
+
+/// Progress bar drawn on one line of the terminal's standard error.
+pub struct ProgressBar {
+    // Time the bar was last drawn, to limit redraws.
+    last_draw: Option<Instant>,
+}
+
+impl ProgressBar {
+    /// Width of the bar in characters.
+    const WIDTH: usize = 30;
+
+    /// Shortest time between redraws.
+    const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Creates a progress bar that has not been drawn yet.
+    pub fn new() -> Self {
+        ProgressBar { last_draw: None }
+    }
+
+    /// Redraws the bar, ending the line on the last report.
+    pub fn update(&mut self, progress: &Progress) {
+        let due = self
+            .last_draw
+            .is_none_or(|last| last.elapsed() >= ProgressBar::REDRAW_INTERVAL);
+        if !due && !progress.finished {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+
+        let filled = (progress.fraction * ProgressBar::WIDTH as f64).round() as usize;
+        let filled = filled.min(ProgressBar::WIDTH);
+        let eta = match progress.eta() {
+            Some(eta) => format_duration(eta),
+            None => "--".to_string(),
+        };
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {:5.1}%  pass {}  ETA {}  {:.2} Mrays/s ",
+            "#".repeat(filled),
+            "-".repeat(ProgressBar::WIDTH - filled),
+            100.0 * progress.fraction,
+            progress.pass,
+            eta,
+            progress.rays_per_second / 1e6,
+        );
+        if progress.finished {
+            let _ = writeln!(stderr);
+        }
+        let _ = stderr.flush();
+    }
+}
+
+// Formats a duration as hours, minutes and seconds.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}h{:02}m{:02}s",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else if seconds >= 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns a report of an unfinished render.
+    fn report(fraction: f64, elapsed: Duration) -> Progress {
+        Progress {
+            fraction,
+            pass: 1,
+            elapsed,
+            rays_per_second: 0.0,
+            finished: false,
+        }
+    }
+
+    #[test]
+    fn eta_extrapolates_the_time_taken_so_far() {
+        assert_eq!(report(0.0, Duration::from_secs(5)).eta(), None);
+        assert_eq!(
+            report(0.25, Duration::from_secs(10)).eta(),
+            Some(Duration::from_secs(30))
+        );
+        let finished = Progress {
+            finished: true,
+            ..report(0.5, Duration::from_secs(10))
+        };
+        assert_eq!(finished.eta(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn durations_show_hours_minutes_and_seconds() {
+        assert_eq!(format_duration(Duration::from_millis(9500)), "9s");
+        assert_eq!(format_duration(Duration::from_secs(61)), "1m01s");
+        assert_eq!(
+            format_duration(Duration::from_secs(3 * 3600 + 5)),
+            "3h00m05s"
+        );
+    }
+}
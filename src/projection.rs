@@ -0,0 +1,203 @@
+use crate::vec3::*;
+use std::f64::consts::PI;
+
+/// How the camera maps the image onto rays into the scene.
+///
+/// Panoramic projections (fisheye, equirectangular and cube map) send every ray from the camera
+/// position, without depth of field.
+#[derive(Debug, Copy, Clone, PartialEq, Hash)]
+pub enum Projection {
+    /// Rays spread from the camera position through the image, with the vertical field of view.
+    Perspective,
+    /// Parallel rays from every point of the image, which keeps sizes independent of the
+    /// distance, as in architectural and technical drawings.
+    Orthographic,
+    /// Circular fisheye filling the image height, with the fisheye field of view.
+    Fisheye(FisheyeMapping),
+    /// Full sphere of directions, longitude along x and latitude along y, with the view
+    /// direction in the image center.
+    Equirectangular,
+    /// Six square 90 degree views in a 3×2 grid: left, front and right on top, and back, up
+    /// and down below.
+    CubeMap,
+}
+
+/// How a fisheye lens spreads the angle from the view direction over the image.
+#[derive(Debug, Copy, Clone, PartialEq, Hash)]
+pub enum FisheyeMapping {
+    /// Distance from the center proportional to the angle, as in most measurement fisheyes.
+    Equidistant,
+    /// Equal image areas for equal solid angles, as in most photographic fisheyes.
+    Equisolid,
+}
+
+impl Projection {
+    /// Parses a projection name as used on the command line.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "perspective" => Some(Projection::Perspective),
+            "orthographic" | "ortho" => Some(Projection::Orthographic),
+            "fisheye" | "fisheye-equidistant" => {
+                Some(Projection::Fisheye(FisheyeMapping::Equidistant))
+            }
+            "fisheye-equisolid" => Some(Projection::Fisheye(FisheyeMapping::Equisolid)),
+            "equirectangular" | "latlong" => Some(Projection::Equirectangular),
+            "cubemap" => Some(Projection::CubeMap),
+            _ => None,
+        }
+    }
+
+    /// Returns whether rays leave the camera position in directions given by the image
+    /// position alone, instead of through a plane in front of the camera.
+    pub fn is_panoramic(self) -> bool {
+        !matches!(self, Projection::Perspective | Projection::Orthographic)
+    }
+
+    /// Returns the width to height ratio that covers the projection without distortion or
+    /// gaps, for projections that have one.
+    pub fn natural_aspect_ratio(self) -> Option<f64> {
+        match self {
+            Projection::Perspective | Projection::Orthographic => None,
+            Projection::Fisheye(_) => Some(1.0),
+            Projection::Equirectangular => Some(2.0),
+            Projection::CubeMap => Some(1.5),
+        }
+    }
+
+    /// Returns the direction of a panoramic projection through position (`x`, `y`) of the
+    /// image, each from 0 to 1 with y downwards, or `None` outside the fisheye circle.
+    ///
+    /// The direction is a unit vector in camera space, along the camera's `u`, `v` and `w`
+    /// axes, so the view direction is -z. `fisheye_fov` is the fisheye's full field of view in
+    /// degrees.
+    pub fn panoramic_direction(
+        self,
+        (x, y): (f64, f64),
+        aspect_ratio: f64,
+        fisheye_fov: f64,
+    ) -> Option<Vec3> {
+        match self {
+            Projection::Perspective | Projection::Orthographic => None,
+            Projection::Fisheye(mapping) => {
+                // Distance from the center, one at the top and bottom of the image
+                let (sx, sy) = ((2.0 * x - 1.0) * aspect_ratio, 1.0 - 2.0 * y);
+                let r = (sx * sx + sy * sy).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let max_theta = 0.5 * fisheye_fov.to_radians();
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * max_theta,
+                    FisheyeMapping::Equisolid => {
+                        2.0 * (r * (0.5 * max_theta).sin()).clamp(-1.0, 1.0).asin()
+                    }
+                };
+                let (cos_phi, sin_phi) = if r > 0.0 {
+                    (sx / r, sy / r)
+                } else {
+                    (1.0, 0.0)
+                };
+                Some(Vec3::new(
+                    theta.sin() * cos_phi,
+                    theta.sin() * sin_phi,
+                    -theta.cos(),
+                ))
+            }
+            Projection::Equirectangular => {
+                let longitude = (2.0 * x - 1.0) * PI;
+                let latitude = (0.5 - y) * PI;
+                Some(Vec3::new(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    -latitude.cos() * longitude.cos(),
+                ))
+            }
+            Projection::CubeMap => {
+                let column = ((3.0 * x).floor() as usize).min(2);
+                let row = ((2.0 * y).floor() as usize).min(1);
+                let a = 2.0 * (3.0 * x - column as f64) - 1.0;
+                let b = 1.0 - 2.0 * (2.0 * y - row as f64);
+                // Forward, right and up directions of each face
+                let (forward, right, up) = match (row, column) {
+                    (0, 0) => ((-1.0, 0.0, 0.0), (0.0, 0.0, -1.0), (0.0, 1.0, 0.0)),
+                    (0, 1) => ((0.0, 0.0, -1.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)),
+                    (0, _) => ((1.0, 0.0, 0.0), (0.0, 0.0, 1.0), (0.0, 1.0, 0.0)),
+                    (_, 0) => ((0.0, 0.0, 1.0), (-1.0, 0.0, 0.0), (0.0, 1.0, 0.0)),
+                    (_, 1) => ((0.0, 1.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, 1.0)),
+                    (_, _) => ((0.0, -1.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, -1.0)),
+                };
+                let axis = |(x, y, z): (f64, f64, f64)| Vec3::new(x, y, z);
+                Some(Vec3::unit_vector(
+                    axis(forward) + axis(right) * a + axis(up) * b,
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the direction through an image position, which must exist.
+    fn direction(projection: Projection, position: (f64, f64)) -> (f64, f64, f64) {
+        let d = projection
+            .panoramic_direction(position, 1.0, 180.0)
+            .unwrap();
+        (d.x(), d.y(), d.z())
+    }
+
+    // Asserts that two directions agree.
+    fn assert_near((x, y, z): (f64, f64, f64), (ex, ey, ez): (f64, f64, f64)) {
+        let error = (x - ex).abs() + (y - ey).abs() + (z - ez).abs();
+        assert!(error < 1e-9, "{:?} is not {:?}", (x, y, z), (ex, ey, ez));
+    }
+
+    #[test]
+    fn cube_map_faces_look_along_their_axes() {
+        let cube = Projection::CubeMap;
+        let center = |column: f64, row: f64| ((column + 0.5) / 3.0, (row + 0.5) / 2.0);
+        assert_near(direction(cube, center(0.0, 0.0)), (-1.0, 0.0, 0.0));
+        assert_near(direction(cube, center(1.0, 0.0)), (0.0, 0.0, -1.0));
+        assert_near(direction(cube, center(2.0, 0.0)), (1.0, 0.0, 0.0));
+        assert_near(direction(cube, center(0.0, 1.0)), (0.0, 0.0, 1.0));
+        assert_near(direction(cube, center(1.0, 1.0)), (0.0, 1.0, 0.0));
+        assert_near(direction(cube, center(2.0, 1.0)), (0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn cube_map_faces_meet_at_their_edges() {
+        let cube = Projection::CubeMap;
+        let s = 0.5_f64.sqrt();
+        let edge = 1e-12;
+        // Left and front, front and right, along the top row
+        assert_near(direction(cube, (1.0 / 3.0 - edge, 0.25)), (-s, 0.0, -s));
+        assert_near(direction(cube, (1.0 / 3.0 + edge, 0.25)), (-s, 0.0, -s));
+        assert_near(direction(cube, (2.0 / 3.0 - edge, 0.25)), (s, 0.0, -s));
+        assert_near(direction(cube, (2.0 / 3.0 + edge, 0.25)), (s, 0.0, -s));
+        // The up face's bottom edge continues the front face's top edge
+        assert_near(direction(cube, (0.5, 0.0)), (0.0, s, -s));
+        assert_near(direction(cube, (0.5, 1.0 - edge)), (0.0, s, -s));
+    }
+
+    #[test]
+    fn fisheye_angles_grow_to_half_the_field_of_view_at_the_rim() {
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid] {
+            let fisheye = Projection::Fisheye(mapping);
+            assert_near(direction(fisheye, (0.5, 0.5)), (0.0, 0.0, -1.0));
+            // The top and right of the circle look sideways with a 180 degree field of view
+            assert_near(direction(fisheye, (0.5, 0.0)), (0.0, 1.0, 0.0));
+            assert_near(direction(fisheye, (1.0, 0.5)), (1.0, 0.0, 0.0));
+            assert!(fisheye
+                .panoramic_direction((0.95, 0.05), 1.0, 180.0)
+                .is_none());
+        }
+        // Halfway to the rim, equidistant lenses see half the angle and equisolid ones more
+        let halfway = |mapping| direction(Projection::Fisheye(mapping), (0.75, 0.5));
+        let s = 0.5_f64.sqrt();
+        assert_near(halfway(FisheyeMapping::Equidistant), (s, 0.0, -s));
+        let (x, _, z) = halfway(FisheyeMapping::Equisolid);
+        let theta = x.atan2(-z);
+        assert!((theta - 2.0 * (0.5 * s).asin()).abs() < 1e-9);
+    }
+}
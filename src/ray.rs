@@ -6,6 +6,10 @@ pub struct Ray {
     orig: Point3,
     // The direction vector of the ray.
     dir: Vec3,
+    // The wavelength carried by the ray in nanometres, for spectral rendering.
+    wavelength: Option<f64>,
+    // The time in seconds at which the ray sees the scene, placing animated objects.
+    time: f64,
 }
 
 impl Ray {
@@ -14,6 +18,8 @@ impl Ray {
         Ray {
             orig: Vec3::zero(),
             dir: Vec3::zero(),
+            wavelength: None,
+            time: 0.0,
         }
     }
     /// Constructs a new Ray with the specified components.
@@ -21,9 +27,23 @@ impl Ray {
         Ray {
             orig: origin,
             dir: direction,
+            wavelength: None,
+            time: 0.0,
         }
     }
 
+    /// Returns a copy of the ray carrying the given wavelength in nanometres.
+    pub fn with_wavelength(mut self, wavelength: Option<f64>) -> Self {
+        self.wavelength = wavelength;
+        self
+    }
+
+    /// Returns a copy of the ray seeing the scene at the given time in seconds.
+    pub fn with_time(mut self, time: f64) -> Self {
+        self.time = time;
+        self
+    }
+
     // Returns the origin of the ray.
     pub fn origin(&self) -> Point3 {
         self.orig
@@ -34,6 +54,16 @@ impl Ray {
         self.dir
     }
 
+    // Returns the wavelength of the ray, if it is a spectral ray.
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
+    }
+
+    // Returns the time at which the ray sees the scene.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
     // Computes the point along the ray at a given parameter t.
     pub fn at(&self, t: f64) -> Point3 {
         self.orig + self.dir * t
@@ -0,0 +1,151 @@
+use crate::progress::*;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Handle controlling a running render.
+///
+/// Clones share the same state, so a clone given to another thread or a signal handler can
+/// cancel the render or read its progress. A cancelled render stops at the end of the current
+/// tile and still writes the image accumulated so far.
+#[derive(Clone)]
+pub struct RenderControl {
+    state: Arc<ControlState>,
+}
+
+// State shared by all clones of a `RenderControl`.
+struct ControlState {
+    // Set once the render should stop.
+    cancelled: AtomicBool,
+    // Time at which the render stops.
+    deadline: Mutex<Option<Instant>>,
+    // Mean relative error of the pixels at which the render stops.
+    noise_target: Mutex<Option<f64>>,
+    // Fraction of the render done, as the bits of an `f64`.
+    progress: AtomicU64,
+    // Function called with every progress report.
+    callback: Mutex<Option<ProgressCallback>>,
+}
+
+/// Function receiving progress reports of a render.
+pub type ProgressCallback = Box<dyn FnMut(&Progress) + Send>;
+
+impl RenderControl {
+    /// Creates a handle without a deadline or noise target.
+    pub fn new() -> Self {
+        RenderControl {
+            state: Arc::new(ControlState {
+                cancelled: AtomicBool::new(false),
+                deadline: Mutex::new(None),
+                noise_target: Mutex::new(None),
+                progress: AtomicU64::new(0.0f64.to_bits()),
+                callback: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Returns the handle with a deadline the given time from now.
+    pub fn with_time_budget(self, budget: Duration) -> Self {
+        self.set_deadline(Some(Instant::now() + budget));
+        self
+    }
+
+    /// Returns the handle with a noise target.
+    ///
+    /// Rendering then keeps taking passes over the whole image past `samples_per_pixel` until
+    /// the mean relative error of the pixels falls below the target.
+    pub fn with_noise_target(self, target: f64) -> Self {
+        *self.state.noise_target.lock().unwrap() = Some(target);
+        self
+    }
+
+    /// Returns the handle calling `callback` with progress reports, about once per tile.
+    pub fn with_progress_callback<F: FnMut(&Progress) + Send + 'static>(self, callback: F) -> Self {
+        *self.state.callback.lock().unwrap() = Some(Box::new(callback));
+        self
+    }
+
+    /// Asks the render to stop.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether the render was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the time at which the render stops, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        *self.state.deadline.lock().unwrap()
+    }
+
+    /// Sets or clears the time at which the render stops, also while it is running.
+    pub fn set_deadline(&self, deadline: Option<Instant>) {
+        *self.state.deadline.lock().unwrap() = deadline;
+    }
+
+    /// Returns the noise target, if any.
+    pub fn noise_target(&self) -> Option<f64> {
+        *self.state.noise_target.lock().unwrap()
+    }
+
+    /// Returns whether the render should stop, because it was cancelled or ran out of time.
+    pub fn should_stop(&self) -> bool {
+        self.is_cancelled() || self.deadline().is_some_and(|d| Instant::now() >= d)
+    }
+
+    /// Returns the fraction of the render done, between 0 and 1.
+    pub fn progress(&self) -> f64 {
+        f64::from_bits(self.state.progress.load(Ordering::Relaxed))
+    }
+
+    /// Records the progress of the render and passes it to the progress callback.
+    pub fn report(&self, progress: Progress) {
+        let fraction = progress.fraction.clamp(0.0, 1.0);
+        self.state
+            .progress
+            .store(fraction.to_bits(), Ordering::Relaxed);
+        // The callback runs outside the lock, so it may use the handle itself
+        let taken = self.state.callback.lock().unwrap().take();
+        if let Some(mut callback) = taken {
+            callback(&Progress {
+                fraction,
+                ..progress
+            });
+            let mut slot = self.state.callback.lock().unwrap();
+            if slot.is_none() {
+                *slot = Some(callback);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_callbacks_may_use_the_handle() {
+        let control = RenderControl::new();
+        let handle = control.clone();
+        let control = control.with_progress_callback(move |progress| {
+            if handle.progress() >= 0.5 {
+                handle.report(*progress);
+                handle.cancel();
+            }
+        });
+        let progress = |fraction| Progress {
+            fraction,
+            pass: 1,
+            elapsed: Duration::ZERO,
+            rays_per_second: 0.0,
+            finished: false,
+        };
+        control.report(progress(0.25));
+        assert!(!control.is_cancelled());
+        control.report(progress(0.75));
+        assert!(control.is_cancelled());
+        assert_eq!(control.progress(), 0.75);
+    }
+}
@@ -1,17 +1,149 @@
 use std::f64::consts::PI;
-use rand::Rng;
 
 // Converts degrees to radians using the formula: radians = degrees * π / 180.
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
+/// Seedable pseudo-random number generator (SplitMix64).
+///
+/// Generators are passed explicitly so that the same seed always gives the same scene and the
+/// same image. Each pixel sample gets its own generator, independent of rendering order, whose
+/// first values can come from a pixel sampler.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    // Current position in the sequence.
+    state: u64,
+    // Leading values supplied by a pixel sampler, returned before pseudo-random ones.
+    samples: Vec<f64>,
+    // Index of the next sampler value to return.
+    next_sample: usize,
+    // End of the block of sampler values being read, after which values are pseudo-random.
+    block_end: usize,
+}
+
+impl Rng {
+    /// Creates a new generator from a seed.
+    pub fn new(seed: u64) -> Self {
+        Rng {
+            state: seed,
+            samples: Vec::new(),
+            next_sample: 0,
+            block_end: 0,
+        }
+    }
+
+    /// Returns a copy of the generator that first returns the given sample values.
+    pub fn with_samples(mut self, samples: Vec<f64>) -> Self {
+        self.block_end = samples.len();
+        self.samples = samples;
+        self.next_sample = 0;
+        self
+    }
+
+    /// Reads the sampler values of dimensions `start` to `start + count` next, then
+    /// pseudo-random values, so each sampling decision gets the same dimensions however many
+    /// values the decisions before it used.
+    pub fn start_dimensions(&mut self, start: usize, count: usize) {
+        self.next_sample = start;
+        self.block_end = start + count;
+    }
+
+    /// Creates the generator for one sample of one pixel of a render with the given seed.
+    pub fn for_sample(seed: u64, pixel: u64, sample: u64) -> Self {
+        let pixel_stream = mix(seed ^ mix(pixel.wrapping_add(0x632b_e59b_d9b4_e019)));
+        let sample_stream = mix(sample.wrapping_add(0x8cb9_2ba7_2f3d_8dd7));
+        Rng::new(mix(pixel_stream ^ sample_stream))
+    }
+
+    /// Returns the next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+}
+
+/// Hasher with a fixed, documented algorithm: every value is fed as 64 bits into the SplitMix64
+/// mixer. Unlike the standard library's hasher, its results stay the same across builds and
+/// platforms, so they can identify scenes in files and between processes.
+#[derive(Debug, Clone)]
+pub struct StableHasher {
+    // Hash of the values written so far.
+    state: u64,
+}
+
+impl StableHasher {
+    /// Creates a hasher without any values.
+    pub fn new() -> Self {
+        StableHasher {
+            state: 0x243f_6a88_85a3_08d3,
+        }
+    }
+}
+
+impl std::hash::Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        mix(self.state)
+    }
+
+    // Writes bytes in little-endian groups of eight, then their count.
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
+        }
+        self.write_u64(bytes.len() as u64);
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.state = mix(self.state ^ value).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    }
+
+    fn write_u8(&mut self, value: u8) {
+        self.write_u64(value as u64);
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write_u64(value as u64);
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    fn write_i32(&mut self, value: i32) {
+        self.write_u64(value as i64 as u64);
+    }
+
+    fn write_i64(&mut self, value: i64) {
+        self.write_u64(value as u64);
+    }
+
+    fn write_isize(&mut self, value: isize) {
+        self.write_u64(value as i64 as u64);
+    }
+}
+
+// SplitMix64 output function, scrambling the bits of a 64-bit value.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
 // Generates a random floating-point number between 0.0 (inclusive) and 1.0 (exclusive).
-pub fn random_double() -> f64 {
-    rand::thread_rng().gen::<f64>()
+pub fn random_double(rng: &mut Rng) -> f64 {
+    if rng.next_sample < rng.block_end {
+        if let Some(&value) = rng.samples.get(rng.next_sample) {
+            rng.next_sample += 1;
+            return value;
+        }
+    }
+    (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64
 }
 
 // Generates a random floating-point number within the specified range [min, max).
-pub fn random_double_range(min: f64, max: f64) -> f64 {
-    min + (max - min) * random_double()
+pub fn random_double_range(rng: &mut Rng, min: f64, max: f64) -> f64 {
+    min + (max - min) * random_double(rng)
 }
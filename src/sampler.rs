@@ -0,0 +1,568 @@
+use crate::rtweekend::*;
+use std::sync::OnceLock;
+
+/// Number of leading dimensions of each pixel sample that come from the sampler.
+///
+/// Every sampling decision reads its own fixed dimensions: the pixel position, the lens
+/// position, the shutter time, the wavelength, then a block for each of the first two
+/// bounces. Decisions needing more values than their dimensions, and later bounces, get
+/// pseudo-random numbers.
+pub const SAMPLER_DIMENSIONS: usize = 20;
+
+/// First of the two dimensions of the position in the pixel.
+pub const PIXEL_DIMENSION: usize = 0;
+
+/// First of the two dimensions of the position on the lens.
+pub const LENS_DIMENSION: usize = 2;
+
+/// Dimension of the time within the shutter interval.
+pub const TIME_DIMENSION: usize = 4;
+
+/// Dimension of the wavelength of spectral rays.
+pub const WAVELENGTH_DIMENSION: usize = 5;
+
+/// First dimension of the block of the first bounce.
+pub const BOUNCE_DIMENSION: usize = 6;
+
+/// Dimensions of each bounce: two for the free flight through a medium, two for the scattered
+/// direction, one for choosing between the material and the environment, and two for the
+/// direction towards the environment.
+pub const DIMENSIONS_PER_BOUNCE: usize = 7;
+
+/// Generates well-distributed sample values for the dimensions of each pixel sample.
+pub trait Sampler {
+    /// Returns the value in [0, 1) of one dimension of sample `index` of pixel (`x`, `y`).
+    fn sample(&self, x: i32, y: i32, index: u32, dimension: u32) -> f64;
+
+    /// Returns the leading dimensions of one pixel sample.
+    fn pixel_sample(&self, x: i32, y: i32, index: u32) -> Vec<f64> {
+        (0..SAMPLER_DIMENSIONS as u32)
+            .map(|d| self.sample(x, y, index, d))
+            .collect()
+    }
+}
+
+/// Available sample generators.
+#[derive(Debug, Copy, Clone, PartialEq, Hash)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers.
+    Independent,
+    /// Jittered samples, one per cell of a grid over each pair of dimensions.
+    Stratified,
+    /// Halton sequence with random digit permutations per pixel.
+    Halton,
+    /// Sobol sequence with hash-based Owen scrambling per pixel.
+    Sobol,
+    /// Sobol sequence shifted per pixel by a blue-noise mask, spreading error as blue noise.
+    BlueNoise,
+}
+
+impl SamplerKind {
+    /// Parses a sampler name as used on the command line.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "independent" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            "blue-noise" => Some(SamplerKind::BlueNoise),
+            _ => None,
+        }
+    }
+
+    /// Creates a sampler of this kind for renders where every pixel takes `base_samples`
+    /// before adaptive sampling, with the given seed.
+    pub fn create(self, base_samples: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler { seed }),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(base_samples, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler { seed }),
+            SamplerKind::Sobol => Box::new(SobolSampler { seed }),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler { seed }),
+        }
+    }
+}
+
+// Hashes the inputs into a well-mixed 64-bit value.
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x2545_f491_4f6c_dd1d, |h, &v| {
+        Rng::new(h ^ v.wrapping_mul(0x9e37_79b9_7f4a_7c15)).next_u64()
+    })
+}
+
+// Converts the top 53 bits of a hash to a value in [0, 1).
+fn hash_to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Converts a 32-bit fixed-point fraction to a value in [0, 1).
+fn fixed_to_unit(x: u32) -> f64 {
+    x as f64 / 4_294_967_296.0
+}
+
+// Identifies a pixel for hashing.
+fn pixel_key(x: i32, y: i32) -> u64 {
+    ((x as u32 as u64) << 32) | y as u32 as u64
+}
+
+/// Independent uniform random numbers for every dimension.
+pub struct IndependentSampler {
+    // Seed decorrelating different renders.
+    seed: u64,
+}
+
+impl Sampler for IndependentSampler {
+    fn sample(&self, x: i32, y: i32, index: u32, dimension: u32) -> f64 {
+        hash_to_unit(hash(&[
+            self.seed,
+            pixel_key(x, y),
+            index as u64,
+            dimension as u64,
+        ]))
+    }
+}
+
+/// Jittered stratification: each pair of dimensions is split into a grid with one cell per
+/// sample of a block of samples, and the cells are visited in a different random order for
+/// every pair and block.
+///
+/// The first block holds the samples every pixel takes, and each later block as many as all
+/// blocks before it, like the passes of adaptive sampling, so every pass is stratified on its
+/// own.
+pub struct StratifiedSampler {
+    // Samples in the first block.
+    base: u32,
+    // Seed decorrelating different renders.
+    seed: u64,
+}
+
+impl StratifiedSampler {
+    /// Creates a new stratified sampler for renders where every pixel takes `base_samples`
+    /// before adaptive sampling. Without a limit, `u32::MAX`, the blocks double from a single
+    /// sample.
+    pub fn new(base_samples: u32, seed: u64) -> Self {
+        let base = if base_samples == u32::MAX {
+            1
+        } else {
+            base_samples.max(1)
+        };
+        StratifiedSampler { base, seed }
+    }
+
+    // Returns the first sample and the number of samples of the block holding `index`.
+    fn block(&self, index: u32) -> (u32, u32) {
+        if index < self.base {
+            return (0, self.base);
+        }
+        let start = self.base << (index / self.base).ilog2();
+        (start, start)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn sample(&self, x: i32, y: i32, index: u32, dimension: u32) -> f64 {
+        let (start, size) = self.block(index);
+        let nx = (size as f64).sqrt().ceil() as u32;
+        let ny = size.div_ceil(nx);
+        let pair = dimension / 2;
+        let key = hash(&[self.seed, pixel_key(x, y), pair as u64, start as u64]);
+        let cell = permute(index - start, nx * ny, key as u32);
+        let jitter = hash_to_unit(hash(&[key, index as u64, dimension as u64]));
+        if dimension.is_multiple_of(2) {
+            ((cell % nx) as f64 + jitter) / nx as f64
+        } else {
+            ((cell / nx) as f64 + jitter) / ny as f64
+        }
+    }
+}
+
+// Maps `index` in [0, n) to a pseudo-random permutation of [0, n) selected by `seed`.
+//
+// A bijective hash over the enclosing power of two is applied repeatedly until the result
+// falls back inside the range (cycle walking).
+fn permute(index: u32, n: u32, seed: u32) -> u32 {
+    if n <= 1 {
+        return 0;
+    }
+    let bits = 32 - (n - 1).leading_zeros();
+    let mask = if bits == 32 {
+        u32::MAX
+    } else {
+        (1 << bits) - 1
+    };
+    let shift = (bits / 2).max(1);
+    let mut i = index;
+    loop {
+        i = (i ^ seed) & mask;
+        i = i.wrapping_mul(0xe170_893d) & mask;
+        i ^= i >> shift;
+        i = (i ^ (seed >> 16)) & mask;
+        i = i.wrapping_mul(0x0929_eb3f) & mask;
+        i ^= i >> shift;
+        i = i.wrapping_mul(0x6935_fa69) & mask;
+        i ^= i >> 1;
+        if i < n {
+            return i;
+        }
+    }
+}
+
+/// Halton sequence, one prime base per dimension, with random digit permutations per pixel to
+/// break the correlation between dimensions with large bases.
+pub struct HaltonSampler {
+    // Seed decorrelating different renders.
+    seed: u64,
+}
+
+impl HaltonSampler {
+    /// Prime bases of the leading dimensions.
+    const PRIMES: [u32; SAMPLER_DIMENSIONS] = [
+        2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71,
+    ];
+}
+
+// Reflects the base-`base` digits of `index` about the radix point, permuting each digit
+// position, including the trailing zeros, with its own permutation.
+fn scrambled_radical_inverse(base: u32, mut index: u32, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv = inv_base;
+    let mut result = 0.0;
+    let mut position = 0;
+    while inv > 1e-12 {
+        let digit_seed = hash(&[seed, position]) as u32;
+        result += permute(index % base, base, digit_seed) as f64 * inv;
+        index /= base;
+        inv *= inv_base;
+        position += 1;
+    }
+    result.min(1.0 - f64::EPSILON)
+}
+
+impl Sampler for HaltonSampler {
+    fn sample(&self, x: i32, y: i32, index: u32, dimension: u32) -> f64 {
+        let base = HaltonSampler::PRIMES[dimension as usize % SAMPLER_DIMENSIONS];
+        let seed = hash(&[self.seed, pixel_key(x, y), dimension as u64]);
+        scrambled_radical_inverse(base, index, seed)
+    }
+}
+
+/// Primitive polynomial degree, coefficients and initial direction numbers of Sobol
+/// dimensions 2 to 20 (Joe and Kuo).
+const SOBOL_PARAMETERS: [(u32, u32, [u32; 7]); SAMPLER_DIMENSIONS - 1] = [
+    (1, 0, [1, 0, 0, 0, 0, 0, 0]),
+    (2, 1, [1, 3, 0, 0, 0, 0, 0]),
+    (3, 1, [1, 3, 1, 0, 0, 0, 0]),
+    (3, 2, [1, 1, 1, 0, 0, 0, 0]),
+    (4, 1, [1, 1, 3, 3, 0, 0, 0]),
+    (4, 4, [1, 3, 5, 13, 0, 0, 0]),
+    (5, 2, [1, 1, 5, 5, 17, 0, 0]),
+    (5, 4, [1, 1, 5, 5, 5, 0, 0]),
+    (5, 7, [1, 1, 7, 11, 19, 0, 0]),
+    (5, 11, [1, 1, 5, 1, 1, 0, 0]),
+    (5, 13, [1, 1, 1, 3, 11, 0, 0]),
+    (5, 14, [1, 3, 5, 5, 31, 0, 0]),
+    (6, 1, [1, 3, 3, 9, 7, 49, 0]),
+    (6, 13, [1, 1, 1, 15, 21, 21, 0]),
+    (6, 16, [1, 3, 1, 13, 27, 49, 0]),
+    (6, 19, [1, 1, 1, 15, 7, 5, 0]),
+    (6, 22, [1, 3, 1, 15, 13, 25, 0]),
+    (6, 25, [1, 1, 5, 5, 19, 61, 0]),
+    (7, 1, [1, 3, 7, 11, 23, 15, 103]),
+];
+
+// Generator matrix columns (direction numbers) of each Sobol dimension.
+fn sobol_directions() -> &'static [[u32; 32]; SAMPLER_DIMENSIONS] {
+    static DIRECTIONS: OnceLock<[[u32; 32]; SAMPLER_DIMENSIONS]> = OnceLock::new();
+    DIRECTIONS.get_or_init(|| {
+        let mut directions = [[0u32; 32]; SAMPLER_DIMENSIONS];
+        // The first dimension is the van der Corput sequence
+        for (k, v) in directions[0].iter_mut().enumerate() {
+            *v = 1 << (31 - k);
+        }
+        for (d, &(s, a, m)) in SOBOL_PARAMETERS.iter().enumerate() {
+            let s = s as usize;
+            let v = &mut directions[d + 1];
+            for k in 0..32 {
+                v[k] = if k < s {
+                    m[k] << (31 - k)
+                } else {
+                    let mut value = v[k - s] ^ (v[k - s] >> s);
+                    for i in 1..s {
+                        if (a >> (s - 1 - i)) & 1 == 1 {
+                            value ^= v[k - i];
+                        }
+                    }
+                    value
+                };
+            }
+        }
+        directions
+    })
+}
+
+// Returns one dimension of the unscrambled Sobol point with the given index.
+fn sobol(index: u32, dimension: u32) -> u32 {
+    let v = &sobol_directions()[dimension as usize % SAMPLER_DIMENSIONS];
+    let mut result = 0;
+    let mut i = index;
+    let mut k = 0;
+    while i > 0 {
+        if i & 1 == 1 {
+            result ^= v[k];
+        }
+        i >>= 1;
+        k += 1;
+    }
+    result
+}
+
+// Owen scrambles a 32-bit fraction with the hash-based nested uniform scramble of Burley (2020).
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut v = x.reverse_bits();
+    v = v.wrapping_add(seed);
+    v ^= v.wrapping_mul(0x6c50_b47c);
+    v ^= v.wrapping_mul(0xb82f_1e52);
+    v ^= v.wrapping_mul(0xc7af_e638);
+    v ^= v.wrapping_mul(0x8d22_f6e6);
+    v.reverse_bits()
+}
+
+/// Sobol sequence, Owen scrambled independently for every pixel and dimension.
+pub struct SobolSampler {
+    // Seed decorrelating different renders.
+    seed: u64,
+}
+
+impl Sampler for SobolSampler {
+    fn sample(&self, x: i32, y: i32, index: u32, dimension: u32) -> f64 {
+        let pixel_seed = hash(&[self.seed, pixel_key(x, y)]);
+        // Shuffle the sample order so that dimension pairs are not aligned across pixels
+        let index = owen_scramble(index, pixel_seed as u32);
+        let scramble = hash(&[pixel_seed, dimension as u64]) as u32;
+        fixed_to_unit(owen_scramble(sobol(index, dimension), scramble))
+    }
+}
+
+/// Side length of the tiled blue-noise mask.
+const BLUE_NOISE_SIZE: usize = 64;
+
+// Blue-noise threshold mask in [0, 1), built once with the void-and-cluster method.
+fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let n = BLUE_NOISE_SIZE;
+        let size = n * n;
+
+        // Toroidal Gaussian energy kernel indexed by offset
+        let sigma = 1.5;
+        let mut kernel = vec![0.0; size];
+        for dy in 0..n {
+            for dx in 0..n {
+                let wx = dx.min(n - dx) as f64;
+                let wy = dy.min(n - dy) as f64;
+                kernel[dy * n + dx] = (-(wx * wx + wy * wy) / (2.0 * sigma * sigma)).exp();
+            }
+        }
+        let update = |energy: &mut [f64], p: usize, sign: f64| {
+            let (px, py) = (p % n, p / n);
+            for (q, e) in energy.iter_mut().enumerate() {
+                let dx = (q % n + n - px) % n;
+                let dy = (q / n + n - py) % n;
+                *e += sign * kernel[dy * n + dx];
+            }
+        };
+        let tightest_cluster = |pattern: &[bool], energy: &[f64]| {
+            (0..size)
+                .filter(|&p| pattern[p])
+                .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap()
+        };
+        let largest_void = |pattern: &[bool], energy: &[f64]| {
+            (0..size)
+                .filter(|&p| !pattern[p])
+                .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap()
+        };
+
+        // Random initial pattern, relaxed by moving points from clusters into voids
+        let mut rng = Rng::new(0x5eed_b10e);
+        let mut pattern = vec![false; size];
+        let mut energy = vec![0.0; size];
+        let initial_count = size / 10;
+        let mut placed = 0;
+        while placed < initial_count {
+            let p = (random_double(&mut rng) * size as f64) as usize;
+            if !pattern[p] {
+                pattern[p] = true;
+                update(&mut energy, p, 1.0);
+                placed += 1;
+            }
+        }
+        loop {
+            let cluster = tightest_cluster(&pattern, &energy);
+            pattern[cluster] = false;
+            update(&mut energy, cluster, -1.0);
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // Rank the initial points by removing clusters, then fill voids in order
+        let mut rank = vec![0usize; size];
+        let (mut ones, mut ones_energy) = (pattern.clone(), energy.clone());
+        for r in (0..initial_count).rev() {
+            let cluster = tightest_cluster(&ones, &ones_energy);
+            ones[cluster] = false;
+            update(&mut ones_energy, cluster, -1.0);
+            rank[cluster] = r;
+        }
+        for r in initial_count..size {
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+            rank[void] = r;
+        }
+        rank.iter()
+            .map(|&r| (r as f64 + 0.5) / size as f64)
+            .collect()
+    })
+}
+
+/// Globally scrambled Sobol sequence, shifted per pixel by a blue-noise mask so neighbouring
+/// pixels get well-spread offsets and the remaining noise looks like fine blue noise.
+pub struct BlueNoiseSampler {
+    // Seed decorrelating different renders.
+    seed: u64,
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn sample(&self, x: i32, y: i32, index: u32, dimension: u32) -> f64 {
+        let n = BLUE_NOISE_SIZE;
+        // Each dimension reads the mask at a different toroidal offset
+        let offset = hash(&[self.seed, dimension as u64]);
+        let mx = (x as usize + offset as usize % n) % n;
+        let my = (y as usize + (offset >> 32) as usize % n) % n;
+        let shift = blue_noise_mask()[my * n + mx];
+
+        let scramble = (offset >> 16) as u32;
+        let value = fixed_to_unit(owen_scramble(sobol(index, dimension), scramble));
+        (value + shift).fract()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks that the values fall one into each of `n` equal intervals of [0, 1).
+    fn one_per_interval(values: impl Iterator<Item = f64>, n: usize) -> bool {
+        let mut counts = vec![0; n];
+        for value in values {
+            counts[(value * n as f64) as usize] += 1;
+        }
+        counts.iter().all(|&count| count == 1)
+    }
+
+    #[test]
+    fn sobol_starts_with_the_van_der_corput_sequence() {
+        let values: Vec<f64> = (0..8).map(|i| fixed_to_unit(sobol(i, 0))).collect();
+        assert_eq!(values, [0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875]);
+    }
+
+    #[test]
+    fn every_sobol_dimension_is_stratified() {
+        for dimension in 0..SAMPLER_DIMENSIONS as u32 {
+            for bits in [2, 5, 8] {
+                let n = 1 << bits;
+                let values = (0..n as u32).map(|i| fixed_to_unit(sobol(i, dimension)));
+                assert!(one_per_interval(values, n), "dimension {}", dimension);
+            }
+        }
+    }
+
+    #[test]
+    fn first_two_sobol_dimensions_fill_every_elementary_interval() {
+        // 16 points put one point in every box of area 1/16
+        for columns in [1, 2, 4, 8, 16] {
+            let rows = 16 / columns;
+            let mut counts = [0; 16];
+            for i in 0..16 {
+                let x = fixed_to_unit(sobol(i, 0));
+                let y = fixed_to_unit(sobol(i, 1));
+                let cell = (y * rows as f64) as usize * columns + (x * columns as f64) as usize;
+                counts[cell] += 1;
+            }
+            assert!(
+                counts.iter().all(|&count| count == 1),
+                "{} columns",
+                columns
+            );
+        }
+    }
+
+    #[test]
+    fn owen_scrambling_keeps_the_stratification() {
+        for seed in [1, 0xdead_beef, 0x1234_5678] {
+            let values = (0..64).map(|i| fixed_to_unit(owen_scramble(sobol(i, 3), seed)));
+            assert!(one_per_interval(values, 64));
+        }
+        // but moves the points
+        assert_ne!(owen_scramble(sobol(5, 0), 1), sobol(5, 0));
+    }
+
+    #[test]
+    fn scrambled_halton_keeps_the_stratification() {
+        for base in [2, 3, 5] {
+            let n = base * base * base;
+            let values = (0..n).map(|i| scrambled_radical_inverse(base, i, 42));
+            assert!(one_per_interval(values, n as usize), "base {}", base);
+        }
+    }
+
+    #[test]
+    fn permute_visits_every_index_once() {
+        for n in [1, 2, 7, 64, 100] {
+            let mut seen = vec![false; n as usize];
+            for i in 0..n {
+                seen[permute(i, n, 0x9e37_79b9) as usize] = true;
+            }
+            assert!(seen.iter().all(|&s| s));
+        }
+    }
+
+    #[test]
+    fn stratified_samples_cover_every_cell_of_each_pass() {
+        // Four samples every pixel takes, then adaptive passes of 4 and 8 more
+        let sampler = StratifiedSampler::new(4, 7);
+        for (start, side) in [(0, 2), (4, 2), (8, 3)] {
+            let size = start.max(4);
+            let mut counts = vec![0; side * side];
+            for index in start..start + size {
+                let x = sampler.sample(3, 4, index as u32, 2);
+                let y = sampler.sample(3, 4, index as u32, 3);
+                counts[(y * side as f64) as usize * side + (x * side as f64) as usize] += 1;
+            }
+            assert!(
+                counts.iter().all(|&count| count <= 1),
+                "pass from {}",
+                start
+            );
+        }
+    }
+
+    #[test]
+    fn dimension_blocks_read_fixed_dimensions() {
+        let samples: Vec<f64> = (0..SAMPLER_DIMENSIONS).map(|d| d as f64 / 100.0).collect();
+        let mut rng = Rng::new(1).with_samples(samples);
+        rng.start_dimensions(LENS_DIMENSION, 2);
+        assert_eq!(random_double(&mut rng), 0.02);
+        assert_eq!(random_double(&mut rng), 0.03);
+        // Values past the block are pseudo-random, not the next dimension
+        assert_ne!(random_double(&mut rng), 0.04);
+        rng.start_dimensions(BOUNCE_DIMENSION + DIMENSIONS_PER_BOUNCE, 1);
+        assert_eq!(random_double(&mut rng), 0.13);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::{color::*, environment::*, rtweekend::*, spectrum::*, vec3::*};
+use std::f64::consts::PI;
+
+/// Preetham analytic daylight sky with a sun disk, for outdoor scenes.
+///
+/// Radiance is computed in kcd/m² and scaled by the intensity. Directions below the horizon see
+/// a diffuse ground lit by the sky and the sun.
+pub struct PhysicalSky {
+    // Unit vector pointing towards the sun.
+    sun_direction: Vec3,
+    // Angle between the zenith and the sun, in radians.
+    sun_theta: f64,
+    // Perez distribution coefficients A to E for luminance and the two chromaticities.
+    perez: [[f64; 5]; 3],
+    // Zenith luminance and chromaticities divided by their Perez value at the zenith.
+    zenith: [f64; 3],
+    // Radiance of the sun disk after atmospheric extinction.
+    sun_radiance: Color,
+    // Cosine of the angular radius of the sun disk.
+    sun_cos_max: f64,
+    // Radiance reflected by the ground.
+    ground_radiance: Color,
+    // Scale converting kcd/m² to the renderer's radiance units.
+    intensity: f64,
+}
+
+impl PhysicalSky {
+    /// Angular radius of the sun disk in degrees.
+    const SUN_ANGULAR_RADIUS: f64 = 0.2667;
+
+    /// Luminance of the sun before atmospheric extinction, in kcd/m².
+    const SUN_LUMINANCE: f64 = 1.6e6;
+
+    /// Probability of sampling the sun disk rather than the whole sphere.
+    const SUN_SAMPLE_PROBABILITY: f64 = 0.5;
+
+    /// Creates a new sky from the direction towards the sun, the atmospheric turbidity (2 for a
+    /// clear sky to about 10 for haze) and the albedo of the ground.
+    pub fn new(sun_direction: Vec3, turbidity: f64, ground_albedo: Color) -> Self {
+        let sun_direction = Vec3::unit_vector(sun_direction);
+        // The model is only valid for daylight, so the sun never sinks below the horizon
+        let sun_theta = sun_direction
+            .y()
+            .clamp(0.0, 1.0)
+            .acos()
+            .min(0.5 * PI - 1e-3);
+        let t = turbidity;
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        // Zenith luminance and chromaticity
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * sun_theta);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (s1, s2, s3) = (sun_theta, sun_theta * sun_theta, sun_theta.powi(3));
+        let zenith_cx = t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s1)
+            + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s1 + 0.00394)
+            + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s1 + 0.25886);
+        let zenith_cy = t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s1)
+            + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s1 + 0.00516)
+            + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s1 + 0.26688);
+        let zenith_values = [zenith_y.max(0.0), zenith_cx, zenith_cy];
+        let mut zenith = [0.0; 3];
+        for i in 0..3 {
+            zenith[i] = zenith_values[i] / perez_function(&perez[i], 0.0, sun_theta);
+        }
+
+        let mut sky = PhysicalSky {
+            sun_direction,
+            sun_theta,
+            perez,
+            zenith,
+            sun_radiance: sun_transmittance(sun_theta, t) * PhysicalSky::SUN_LUMINANCE,
+            sun_cos_max: degrees_to_radians(PhysicalSky::SUN_ANGULAR_RADIUS).cos(),
+            ground_radiance: Color::zero(),
+            intensity: 0.1,
+        };
+        sky.ground_radiance = sky.horizontal_irradiance() * ground_albedo * (1.0 / PI);
+        sky
+    }
+
+    /// Returns a copy of the sky with radiance scaled by the given factor per kcd/m².
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    // Radiance of the sky dome alone, in kcd/m², for a unit direction above the horizon.
+    fn sky_radiance(&self, d: &Vec3) -> Color {
+        let theta = d.y().clamp(0.01, 1.0).acos();
+        let gamma = Vec3::dot(d, &self.sun_direction).clamp(-1.0, 1.0).acos();
+        let value = |i: usize| self.zenith[i] * perez_function(&self.perez[i], theta, gamma);
+        let (luminance, cx, cy) = (value(0), value(1), value(2));
+
+        // xyY to XYZ
+        let xyz = Vec3::new(
+            cx / cy * luminance,
+            luminance,
+            (1.0 - cx - cy) / cy * luminance,
+        );
+        xyz_to_linear_srgb(xyz)
+    }
+
+    // Irradiance on the ground from the sky dome and the sun, in kcd/m² times steradians.
+    fn horizontal_irradiance(&self) -> Color {
+        let (n_theta, n_phi) = (32, 64);
+        let d_theta = 0.5 * PI / n_theta as f64;
+        let d_phi = 2.0 * PI / n_phi as f64;
+        let mut irradiance = Color::zero();
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let d = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let weight = theta.cos() * theta.sin() * d_theta * d_phi;
+                irradiance += self.sky_radiance(&d) * weight;
+            }
+        }
+        let sun_solid_angle = 2.0 * PI * (1.0 - self.sun_cos_max);
+        irradiance + self.sun_radiance * (sun_solid_angle * self.sun_theta.cos())
+    }
+
+    // Density of uniformly sampling a direction inside the sun disk.
+    fn sun_pdf(&self) -> f64 {
+        1.0 / (2.0 * PI * (1.0 - self.sun_cos_max))
+    }
+}
+
+impl Environment for PhysicalSky {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let d = Vec3::unit_vector(*direction);
+        if d.y() < 0.0 {
+            return self.ground_radiance * self.intensity;
+        }
+        let mut radiance = self.sky_radiance(&d);
+        if Vec3::dot(&d, &self.sun_direction) >= self.sun_cos_max {
+            radiance += self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    fn is_sampled(&self) -> bool {
+        true
+    }
+
+    fn sample_direction(&self, rng: &mut Rng) -> Vec3 {
+        if random_double(rng) >= PhysicalSky::SUN_SAMPLE_PROBABILITY {
+            return Vec3::random_unit_vector(rng);
+        }
+
+        // Uniform direction inside the cone subtended by the sun
+        let cos_theta = 1.0 - random_double(rng) * (1.0 - self.sun_cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * random_double(rng);
+        let w = self.sun_direction;
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = Vec3::unit_vector(Vec3::cross(&w, &a));
+        let u = Vec3::cross(&w, &v);
+        u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + w * cos_theta
+    }
+
+    fn pdf_value(&self, direction: &Vec3) -> f64 {
+        let d = Vec3::unit_vector(*direction);
+        let mut pdf = (1.0 - PhysicalSky::SUN_SAMPLE_PROBABILITY) / (4.0 * PI);
+        if Vec3::dot(&d, &self.sun_direction) >= self.sun_cos_max {
+            pdf += PhysicalSky::SUN_SAMPLE_PROBABILITY * self.sun_pdf();
+        }
+        pdf
+    }
+}
+
+// Perez sky distribution for a view at zenith angle `theta` and angle `gamma` from the sun.
+fn perez_function(c: &[f64; 5], theta: f64, gamma: f64) -> f64 {
+    (1.0 + c[0] * (c[1] / theta.cos()).exp())
+        * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+}
+
+// Fraction of sunlight passing through the atmosphere at the red, green and blue wavelengths,
+// from Rayleigh and aerosol extinction.
+fn sun_transmittance(sun_theta: f64, turbidity: f64) -> Color {
+    let zenith_degrees = sun_theta.to_degrees();
+    let air_mass = 1.0 / (sun_theta.cos() + 0.15 * (93.885 - zenith_degrees).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let transmittance = |lambda_um: f64| {
+        let rayleigh = 0.008735 * lambda_um.powf(-4.08);
+        let aerosol = beta * lambda_um.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    };
+    Color::new(
+        transmittance(0.680),
+        transmittance(0.550),
+        transmittance(0.440),
+    )
+}
+
+/// Returns the direction towards the sun for an elevation above the horizon and an azimuth
+/// clockwise from north, both in degrees. North is -z and east is +x.
+pub fn sun_direction(elevation: f64, azimuth: f64) -> Vec3 {
+    let elevation = degrees_to_radians(elevation);
+    let azimuth = degrees_to_radians(azimuth);
+    Vec3::new(
+        elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+        -elevation.cos() * azimuth.cos(),
+    )
+}
+
+/// Computes the direction towards the sun seen from a latitude and longitude in degrees (north
+/// and east positive) on a calendar date at a time of day in UTC hours.
+///
+/// Uses the NOAA low-precision solar position equations, accurate to a fraction of a degree.
+pub fn solar_position(
+    latitude: f64,
+    longitude: f64,
+    year: i32,
+    month: u32,
+    day: u32,
+    hour: f64,
+) -> Vec3 {
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_before_month = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let mut day_of_year = days_before_month[(month.clamp(1, 12) - 1) as usize] + day;
+    if leap && month > 2 {
+        day_of_year += 1;
+    }
+    let days_in_year = if leap { 366.0 } else { 365.0 };
+
+    // Fractional year in radians
+    let g = 2.0 * PI / days_in_year * (day_of_year as f64 - 1.0 + (hour - 12.0) / 24.0);
+    let equation_of_time = 229.18
+        * (0.000075 + 0.001868 * g.cos()
+            - 0.032077 * g.sin()
+            - 0.014615 * (2.0 * g).cos()
+            - 0.040849 * (2.0 * g).sin());
+    let declination = 0.006918 - 0.399912 * g.cos() + 0.070257 * g.sin()
+        - 0.006758 * (2.0 * g).cos()
+        + 0.000907 * (2.0 * g).sin()
+        - 0.002697 * (3.0 * g).cos()
+        + 0.00148 * (3.0 * g).sin();
+
+    // Hour angle from true solar time in minutes
+    let solar_time = hour * 60.0 + equation_of_time + 4.0 * longitude;
+    let hour_angle = degrees_to_radians(solar_time / 4.0 - 180.0);
+    let lat = degrees_to_radians(latitude);
+
+    let cos_zenith =
+        lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos();
+    let elevation = 90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees();
+    let azimuth = hour_angle
+        .sin()
+        .atan2(hour_angle.cos() * lat.sin() - declination.tan() * lat.cos())
+        .to_degrees()
+        + 180.0;
+    sun_direction(elevation, azimuth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the elevation and azimuth in degrees of a direction from `sun_direction`.
+    fn elevation_azimuth(d: Vec3) -> (f64, f64) {
+        let elevation = d.y().asin().to_degrees();
+        let azimuth = d.x().atan2(-d.z()).to_degrees().rem_euclid(360.0);
+        (elevation, azimuth)
+    }
+
+    #[test]
+    fn sun_direction_points_along_the_compass() {
+        let (elevation, azimuth) = elevation_azimuth(sun_direction(30.0, 135.0));
+        assert!((elevation - 30.0).abs() < 1e-9);
+        assert!((azimuth - 135.0).abs() < 1e-9);
+        assert!(sun_direction(0.0, 90.0).x() > 0.999);
+    }
+
+    #[test]
+    fn noon_sun_at_the_june_solstice() {
+        // Greenwich at 12:00 UTC: due south at 90° - 51.48° + 23.44°
+        let (elevation, azimuth) = elevation_azimuth(solar_position(51.48, 0.0, 2024, 6, 21, 12.0));
+        assert!((elevation - 61.96).abs() < 0.3, "elevation {}", elevation);
+        assert!((azimuth - 180.0).abs() < 1.0, "azimuth {}", azimuth);
+        // Overhead on the Tropic of Cancer at local solar noon
+        let (elevation, _) = elevation_azimuth(solar_position(23.44, 0.0, 2024, 6, 21, 12.03));
+        assert!(elevation > 89.5, "elevation {}", elevation);
+    }
+
+    #[test]
+    fn equinox_sun_rises_in_the_east_and_sets_in_the_west() {
+        let (elevation, azimuth) = elevation_azimuth(solar_position(0.0, 0.0, 2024, 3, 20, 6.12));
+        assert!(elevation.abs() < 1.0, "elevation {}", elevation);
+        assert!((azimuth - 90.0).abs() < 1.0, "azimuth {}", azimuth);
+        let (elevation, azimuth) = elevation_azimuth(solar_position(0.0, 0.0, 2024, 3, 20, 18.12));
+        assert!(elevation.abs() < 1.0, "elevation {}", elevation);
+        assert!((azimuth - 270.0).abs() < 1.0, "azimuth {}", azimuth);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{color::*, rtweekend::*, vec3::*};
+use std::sync::OnceLock;
+
+/// Shortest wavelength sampled by the spectral integrator, in nanometres.
+pub const LAMBDA_MIN: f64 = 380.0;
+
+/// Longest wavelength sampled by the spectral integrator, in nanometres.
+pub const LAMBDA_MAX: f64 = 780.0;
+
+/// Samples a wavelength uniformly over the visible range.
+pub fn sample_wavelength(rng: &mut Rng) -> f64 {
+    random_double_range(rng, LAMBDA_MIN, LAMBDA_MAX)
+}
+
+// Piecewise Gaussian used by the multi-lobe colour matching function fit.
+fn lobe(lambda: f64, mu: f64, sigma_lo: f64, sigma_hi: f64) -> f64 {
+    let sigma = if lambda < mu { sigma_lo } else { sigma_hi };
+    let t = (lambda - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// Evaluates the CIE 1931 colour matching functions at a wavelength in nanometres.
+///
+/// Uses the multi-lobe Gaussian fit by Wyman, Sloan and Shirley (2013).
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let x = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+    Vec3::new(x, y, z)
+}
+
+/// Converts CIE XYZ to linear sRGB (D65).
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Color {
+    Color::new(
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    )
+}
+
+// Smooth step from 0 to 1 between `lo` and `hi`.
+fn smoothstep(lo: f64, hi: f64, x: f64) -> f64 {
+    let t = ((x - lo) / (hi - lo)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Evaluates an RGB color as a spectrum at a wavelength in nanometres.
+///
+/// The blue, green and red channels weight three smooth bands that sum to one
+/// everywhere, so white stays flat and albedos in [0, 1] stay energy conserving.
+pub fn rgb_to_spectrum(color: Color, lambda: f64) -> f64 {
+    let red = smoothstep(570.0, 610.0, lambda);
+    let blue = 1.0 - smoothstep(470.0, 510.0, lambda);
+    let green = 1.0 - red - blue;
+    color.0.x() * red + color.0.y() * green + color.0.z() * blue
+}
+
+/// Projects a color onto the wavelength carried by a ray, if any.
+///
+/// Spectral rays get a gray color holding the spectrum value; RGB rays are unchanged.
+pub fn spectral_sample(color: Color, wavelength: Option<f64>) -> Color {
+    match wavelength {
+        Some(lambda) => {
+            let s = rgb_to_spectrum(color, lambda);
+            Color::new(s, s, s)
+        }
+        None => color,
+    }
+}
+
+// Linear sRGB of a flat unit spectrum, used to white balance spectral samples.
+fn white_point() -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let mut xyz = Vec3::zero();
+        let mut lambda = LAMBDA_MIN;
+        while lambda < LAMBDA_MAX {
+            xyz += cie_xyz(lambda + 0.5);
+            lambda += 1.0;
+        }
+        xyz_to_linear_srgb(xyz)
+    })
+}
+
+/// Converts the radiance carried at one uniformly sampled wavelength to linear sRGB.
+///
+/// The result is an unbiased estimate, scaled so a flat unit spectrum averages to white.
+pub fn wavelength_to_rgb(radiance: f64, lambda: f64) -> Color {
+    let xyz = cie_xyz(lambda) * (radiance * (LAMBDA_MAX - LAMBDA_MIN));
+    let rgb = xyz_to_linear_srgb(xyz);
+    let white = white_point();
+    Color::new(
+        rgb.0.x() / white.0.x(),
+        rgb.0.y() / white.0.y(),
+        rgb.0.z() / white.0.z(),
+    )
+}
@@ -1,4 +1,4 @@
-use crate::{hittable::*, interval::*, material::*, ray::*, vec3::*};
+use crate::{hittable::*, interval::*, material::*, ray::*, stats::*, vec3::*};
 use std::rc::Rc;
 
 #[derive(Clone)]
@@ -25,6 +25,7 @@ impl Sphere {
 impl Hittable for Sphere {
     // Implements the hit function for the sphere.
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        INTERSECTION_TESTS.increment();
         // Vector from ray origin to sphere center.
         let oc: Vec3 = r.origin() - self.center;
         // Squared length of the ray direction.
@@ -58,7 +59,12 @@ impl Hittable for Sphere {
         // Set the face normal of the hit record.
         rec.set_face_normal(r, &outward_normal);
         // Set the material of the hit record.
-        rec.mat = self.mat.as_ref().map(|m| Rc::clone(m));
+        rec.mat = self.mat.as_ref().map(Rc::clone);
         true
     }
+
+    // Returns the center of the sphere.
+    fn center(&self, _time: f64) -> Option<Point3> {
+        Some(self.center)
+    }
 }
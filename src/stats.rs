@@ -0,0 +1,136 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Counter of events during a render.
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    /// Creates a counter at zero.
+    pub const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    /// Counts one event.
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts several events, such as those reported by a worker process.
+    pub fn add(&self, count: u64) {
+        self.0.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Returns the number of events counted.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Sets the counter back to zero.
+    pub fn reset(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Rays generated by the camera.
+pub static CAMERA_RAYS: Counter = Counter::new();
+
+/// Rays traced into the scene, from the camera and from scattering.
+pub static RAYS_TRACED: Counter = Counter::new();
+
+/// Ray-primitive intersection tests.
+pub static INTERSECTION_TESTS: Counter = Counter::new();
+
+/// Nodes of the scene hierarchy visited by rays; without a BVH these are the object lists.
+pub static NODES_VISITED: Counter = Counter::new();
+
+// Names and durations of the phases of the program, in the order they first ran.
+struct Phases(Vec<(&'static str, Duration)>);
+
+impl Phases {
+    // Creates an empty list of phases.
+    const fn new() -> Self {
+        Phases(Vec::new())
+    }
+
+    // Adds time spent in a phase, to the earlier time of a phase with the same name.
+    fn record(&mut self, name: &'static str, duration: Duration) {
+        match self.0.iter_mut().find(|(phase, _)| *phase == name) {
+            Some((_, total)) => *total += duration,
+            None => self.0.push((name, duration)),
+        }
+    }
+}
+
+// Phases recorded since the statistics were last printed.
+static PHASES: Mutex<Phases> = Mutex::new(Phases::new());
+
+/// Adds time spent in a phase of the program, such as building the scene or writing images.
+pub fn record_phase(name: &'static str, duration: Duration) {
+    PHASES.lock().unwrap().record(name, duration);
+}
+
+/// Sets the counters back to zero at the start of a render, so the statistics of every
+/// render or animation frame only count its own work.
+pub fn reset_counters() {
+    for counter in [
+        &CAMERA_RAYS,
+        &RAYS_TRACED,
+        &INTERSECTION_TESTS,
+        &NODES_VISITED,
+    ] {
+        counter.reset();
+    }
+}
+
+/// Prints the counters and the time spent per phase since the statistics were last printed.
+pub fn print_statistics() {
+    let camera_rays = CAMERA_RAYS.get();
+    let rays = RAYS_TRACED.get();
+    println!("Statistics:");
+    println!("  Camera rays:         {:>14}", camera_rays);
+    println!(
+        "  Bounces:             {:>14}",
+        rays.saturating_sub(camera_rays)
+    );
+    println!("  Intersection tests:  {:>14}", INTERSECTION_TESTS.get());
+    println!("  Nodes visited:       {:>14}", NODES_VISITED.get());
+    let phases = std::mem::replace(&mut *PHASES.lock().unwrap(), Phases::new());
+    for (name, duration) in phases.0 {
+        println!(
+            "  {:<20} {:>13.3}s",
+            format!("{}:", name),
+            duration.as_secs_f64()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_add_up_events_until_reset() {
+        let counter = Counter::new();
+        counter.increment();
+        counter.add(41);
+        assert_eq!(counter.get(), 42);
+        counter.reset();
+        assert_eq!(counter.get(), 0);
+    }
+
+    #[test]
+    fn phases_add_up_in_the_order_they_first_ran() {
+        let mut phases = Phases::new();
+        phases.record("Setup", Duration::from_millis(5));
+        phases.record("Sampling", Duration::from_secs(2));
+        phases.record("Setup", Duration::from_millis(10));
+        assert_eq!(
+            phases.0,
+            [
+                ("Setup", Duration::from_millis(15)),
+                ("Sampling", Duration::from_secs(2)),
+            ]
+        );
+    }
+}
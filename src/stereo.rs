@@ -0,0 +1,168 @@
+use crate::tile::*;
+use std::hash::{Hash, Hasher};
+
+/// One of the two views of a stereo pair.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+/// How the two eyes are aimed so that objects at the convergence distance line up.
+#[derive(Debug, Copy, Clone, PartialEq, Hash)]
+pub enum StereoMode {
+    /// Both eyes turn towards the convergence point, which adds vertical parallax towards the
+    /// image corners.
+    ToeIn,
+    /// The eyes look in parallel and their views shift sideways to overlap at the convergence
+    /// distance, without vertical parallax.
+    OffAxis,
+}
+
+/// How the two views are arranged in the written images.
+#[derive(Debug, Copy, Clone, PartialEq, Hash)]
+pub enum StereoLayout {
+    /// Left view in the left half of one image, right view in the right half.
+    SideBySide,
+    /// Left view in the top half of one image, right view in the bottom half.
+    TopBottom,
+    /// Each view in its own image, `output_left.ppm` and `output_right.ppm`.
+    Separate,
+}
+
+/// Settings of stereoscopic rendering.
+///
+/// Both views are rendered as halves of one frame, so tiles, regions, checkpoints and
+/// distributed rendering cover the pair. Panoramic projections render omni-directional stereo,
+/// where every direction sees the scene from the eyes of a head turned towards it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Stereo {
+    /// Distance between the eyes in scene units.
+    pub interocular: f64,
+
+    /// Distance at which the views line up, so objects there appear at the screen; without
+    /// it, the focus distance.
+    pub convergence: Option<f64>,
+
+    /// How the eyes are aimed.
+    pub mode: StereoMode,
+
+    /// How the views are arranged in the written images.
+    pub layout: StereoLayout,
+}
+
+impl StereoMode {
+    /// Parses a stereo mode name as used on the command line.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "toe-in" => Some(StereoMode::ToeIn),
+            "off-axis" | "parallel" => Some(StereoMode::OffAxis),
+            _ => None,
+        }
+    }
+}
+
+impl StereoLayout {
+    /// Parses a stereo layout name as used on the command line.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "side-by-side" | "sbs" => Some(StereoLayout::SideBySide),
+            "top-bottom" => Some(StereoLayout::TopBottom),
+            "separate" => Some(StereoLayout::Separate),
+            _ => None,
+        }
+    }
+}
+
+impl Stereo {
+    /// Creates off-axis stereo with a human interocular distance of 6.4 cm, in metres, shown
+    /// side by side.
+    pub fn default() -> Self {
+        Stereo {
+            interocular: 0.064,
+            convergence: None,
+            mode: StereoMode::OffAxis,
+            layout: StereoLayout::SideBySide,
+        }
+    }
+
+    /// Returns the size of the frame holding both views of the given size.
+    pub fn frame_size(&self, (width, height): (i32, i32)) -> (i32, i32) {
+        match self.layout {
+            StereoLayout::TopBottom => (width, 2 * height),
+            StereoLayout::SideBySide | StereoLayout::Separate => (2 * width, height),
+        }
+    }
+
+    /// Returns the part of the frame holding an eye's view of the given size.
+    pub fn view_area(&self, eye: Eye, (width, height): (i32, i32)) -> Tile {
+        let index = match eye {
+            Eye::Left => 0,
+            Eye::Right => 1,
+        };
+        match self.layout {
+            StereoLayout::TopBottom => Tile::new(0, index * height, width, height),
+            StereoLayout::SideBySide | StereoLayout::Separate => {
+                Tile::new(index * width, 0, width, height)
+            }
+        }
+    }
+
+    /// Returns the eye whose view holds pixel (`i`, `j`) of the frame, with views of the given
+    /// size.
+    pub fn eye_at(&self, (i, j): (i32, i32), (width, height): (i32, i32)) -> Eye {
+        let second = match self.layout {
+            StereoLayout::TopBottom => j >= height,
+            StereoLayout::SideBySide | StereoLayout::Separate => i >= width,
+        };
+        if second {
+            Eye::Right
+        } else {
+            Eye::Left
+        }
+    }
+}
+
+impl Hash for Stereo {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.interocular.to_bits().hash(state);
+        self.convergence.map(f64::to_bits).hash(state);
+        self.mode.hash(state);
+        self.layout.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn views_tile_the_frame_and_hold_their_eyes() {
+        let size = (5, 3);
+        for layout in [
+            StereoLayout::SideBySide,
+            StereoLayout::TopBottom,
+            StereoLayout::Separate,
+        ] {
+            let stereo = Stereo {
+                layout,
+                ..Stereo::default()
+            };
+            let (width, height) = stereo.frame_size(size);
+            let left = stereo.view_area(Eye::Left, size);
+            let right = stereo.view_area(Eye::Right, size);
+            assert_eq!((left.width, left.height), size);
+            assert_eq!((right.width, right.height), size);
+            assert_eq!(left.intersect(&right), None, "{:?}", layout);
+
+            let frame = Tile::new(0, 0, width, height);
+            assert_eq!(frame.pixels().count(), 2 * 5 * 3);
+            for (i, j) in frame.pixels() {
+                let eye = stereo.eye_at((i, j), size);
+                let view = stereo.view_area(eye, size);
+                let pixel = Tile::new(i, j, 1, 1);
+                assert_eq!(view.intersect(&pixel), Some(pixel), "{:?}", layout);
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{color::*, spectrum::*};
+use std::f64::consts::PI;
+use std::hash::{Hash, Hasher};
+
+/// A thin transparent coating whose interference tints reflections, like soap or oil.
+#[derive(Debug, Copy, Clone)]
+pub struct ThinFilm {
+    // Thickness of the film in nanometres.
+    thickness: f64,
+    // Refractive index of the film.
+    ior: f64,
+}
+
+impl ThinFilm {
+    /// Wavelengths in nanometres standing in for the red, green and blue channels in RGB mode.
+    const RGB_WAVELENGTHS: [f64; 3] = [630.0, 532.0, 465.0];
+
+    /// Creates a new film with the given thickness in nanometres and index of refraction.
+    pub fn new(thickness: f64, ior: f64) -> Self {
+        ThinFilm { thickness, ior }
+    }
+
+    /// Computes the unpolarized reflectance of the film at one wavelength in nanometres.
+    ///
+    /// Light arrives from a medium of index `n_outer` at angle `cos_theta` and the film sits on a
+    /// substrate of complex index `n_inner + i k_inner`, where `k_inner` is zero for dielectrics
+    /// and the extinction coefficient for metals. Multiple reflections inside the film are
+    /// summed (Airy).
+    pub fn reflectance(
+        &self,
+        cos_theta: f64,
+        n_outer: f64,
+        n_inner: f64,
+        k_inner: f64,
+        lambda: f64,
+    ) -> f64 {
+        let sin2_outer = 1.0 - cos_theta * cos_theta;
+        let sin2_film = sin2_outer * (n_outer / self.ior).powi(2);
+        let sin2_inner = sin2_outer * (n_outer / n_inner).powi(2);
+        if sin2_film >= 1.0 || (k_inner == 0.0 && sin2_inner >= 1.0) {
+            // Total internal reflection at one of the two interfaces
+            return 1.0;
+        }
+        let cos_film = (1.0 - sin2_film).sqrt();
+
+        // Snell's law with the complex substrate index gives a complex cosine, real for
+        // dielectrics
+        let eta = Complex::new(n_inner, k_inner);
+        let sin_inner = Complex::new(self.ior * sin2_film.sqrt(), 0.0) / eta;
+        let cos_inner = (Complex::new(1.0, 0.0) - sin_inner * sin_inner).sqrt();
+        let film = Complex::new(self.ior, 0.0);
+        let cos_film_c = Complex::new(cos_film, 0.0);
+
+        // Phase difference between successive reflections inside the film
+        let delta = 4.0 * PI * self.ior * self.thickness * cos_film / lambda;
+        let phase = Complex::new(delta.cos(), delta.sin());
+
+        let airy = |r12: f64, r23: Complex| {
+            let r12 = Complex::new(r12, 0.0);
+            let round_trip = r23 * phase;
+            (r12 + round_trip).norm_sqr() / (Complex::new(1.0, 0.0) + r12 * round_trip).norm_sqr()
+        };
+        let rs = airy(
+            fresnel_s(n_outer, cos_theta, self.ior, cos_film),
+            (film * cos_film_c - eta * cos_inner) / (film * cos_film_c + eta * cos_inner),
+        );
+        let rp = airy(
+            fresnel_p(n_outer, cos_theta, self.ior, cos_film),
+            (eta * cos_film_c - film * cos_inner) / (eta * cos_film_c + film * cos_inner),
+        );
+        0.5 * (rs + rp)
+    }
+
+    /// Computes the film reflectance for a ray's wavelength, or per channel in RGB mode.
+    ///
+    /// `n_inner` and `k_inner` give the substrate's complex index per channel so tinted
+    /// substrates like metals work.
+    pub fn reflectance_color(
+        &self,
+        cos_theta: f64,
+        n_outer: f64,
+        n_inner: Color,
+        k_inner: Color,
+        wavelength: Option<f64>,
+    ) -> Color {
+        match wavelength {
+            Some(lambda) => {
+                let n = rgb_to_spectrum(n_inner, lambda);
+                let k = rgb_to_spectrum(k_inner, lambda);
+                let r = self.reflectance(cos_theta, n_outer, n, k, lambda);
+                Color::new(r, r, r)
+            }
+            None => {
+                let [lr, lg, lb] = ThinFilm::RGB_WAVELENGTHS;
+                Color::new(
+                    self.reflectance(cos_theta, n_outer, n_inner.0.x(), k_inner.0.x(), lr),
+                    self.reflectance(cos_theta, n_outer, n_inner.0.y(), k_inner.0.y(), lg),
+                    self.reflectance(cos_theta, n_outer, n_inner.0.z(), k_inner.0.z(), lb),
+                )
+            }
+        }
+    }
+}
+
+impl Hash for ThinFilm {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.thickness.to_bits().hash(state);
+        self.ior.to_bits().hash(state);
+    }
+}
+
+// Fresnel amplitude reflection coefficient for s-polarized light.
+fn fresnel_s(n1: f64, cos1: f64, n2: f64, cos2: f64) -> f64 {
+    (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2)
+}
+
+// Fresnel amplitude reflection coefficient for p-polarized light.
+fn fresnel_p(n1: f64, cos1: f64, n2: f64, cos2: f64) -> f64 {
+    (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2)
+}
+
+// Complex number, for the Fresnel coefficients of absorbing substrates.
+#[derive(Debug, Copy, Clone)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    // Principal square root, with a non-negative real part.
+    fn sqrt(self) -> Self {
+        let r = self.norm_sqr().sqrt();
+        let re = (0.5 * (r + self.re)).max(0.0).sqrt();
+        let im = (0.5 * (r - self.re)).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl std::ops::Div for Complex {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        let d = other.norm_sqr();
+        Complex::new(
+            (self.re * other.re + self.im * other.im) / d,
+            (self.im * other.re - self.re * other.im) / d,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarter_wave_coating_cancels_the_reflection() {
+        // Index the geometric mean of air and glass, a quarter wave thick
+        let n = 1.5_f64.sqrt();
+        let film = ThinFilm::new(550.0 / (4.0 * n), n);
+        assert!(film.reflectance(1.0, 1.0, 1.5, 0.0, 550.0) < 1e-12);
+        assert!(film.reflectance(1.0, 1.0, 1.5, 0.0, 450.0) > 1e-3);
+    }
+
+    #[test]
+    fn absorbing_substrate_matches_the_bare_fresnel_reflectance() {
+        let (n, k) = (0.2, 3.0);
+        let bare = ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+        let r = ThinFilm::new(0.0, 1.3).reflectance(1.0, 1.0, n, k, 500.0);
+        assert!((r - bare).abs() < 1e-12);
+        // Thick enough to interfere, but the metal still reflects most light at any angle
+        let r = ThinFilm::new(300.0, 1.3).reflectance(0.5, 1.0, n, k, 500.0);
+        assert!(r > 0.5 && r <= 1.0);
+    }
+}
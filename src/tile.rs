@@ -0,0 +1,215 @@
+/// Rectangle of pixels rendered together.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    /// Column of the leftmost pixel.
+    pub x: i32,
+
+    /// Row of the top pixel.
+    pub y: i32,
+
+    /// Width in pixels.
+    pub width: i32,
+
+    /// Height in pixels.
+    pub height: i32,
+}
+
+impl Tile {
+    /// Creates a tile.
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Tile {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Returns the part of the tile inside `other`, or `None` if they do not overlap.
+    pub fn intersect(&self, other: &Tile) -> Option<Tile> {
+        let x0 = self.x.max(other.x);
+        let y0 = self.y.max(other.y);
+        let x1 = (self.x + self.width).min(other.x + other.width);
+        let y1 = (self.y + self.height).min(other.y + other.height);
+        if x0 >= x1 || y0 >= y1 {
+            return None;
+        }
+        Some(Tile::new(x0, y0, x1 - x0, y1 - y0))
+    }
+
+    /// Returns the tile grown by `margin` pixels on every side.
+    pub fn expand(&self, margin: i32) -> Tile {
+        Tile::new(
+            self.x - margin,
+            self.y - margin,
+            self.width + 2 * margin,
+            self.height + 2 * margin,
+        )
+    }
+
+    /// Returns whether the pixel (i, j) lies in the tile.
+    pub fn contains(&self, i: i32, j: i32) -> bool {
+        (self.x..self.x + self.width).contains(&i) && (self.y..self.y + self.height).contains(&j)
+    }
+
+    /// Returns the pixel coordinates in the tile, row by row.
+    pub fn pixels(&self) -> impl Iterator<Item = (i32, i32)> {
+        let (x, width) = (self.x, self.width);
+        (self.y..self.y + self.height).flat_map(move |j| (x..x + width).map(move |i| (i, j)))
+    }
+}
+
+/// Order in which the tiles of an image are rendered.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TileOrder {
+    /// Row by row from the top left.
+    Scanline,
+    /// Outwards from the center, so the middle of the image appears first.
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles next to each other.
+    Hilbert,
+}
+
+impl TileOrder {
+    /// Parses a tile order name as used on the command line.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+
+    /// Splits `area` into tiles of at most `tile_size` pixels square, in this order.
+    pub fn tiles(self, area: Tile, tile_size: i32) -> Vec<Tile> {
+        let size = tile_size.max(1);
+        let columns = (area.width + size - 1) / size;
+        let rows = (area.height + size - 1) / size;
+        let grid = match self {
+            TileOrder::Scanline => (0..rows)
+                .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                .collect(),
+            TileOrder::Spiral => spiral(columns, rows),
+            TileOrder::Hilbert => {
+                let mut grid: Vec<(i32, i32)> = (0..rows)
+                    .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                    .collect();
+                let n = (columns.max(rows) as u32).next_power_of_two();
+                grid.sort_by_key(|&(column, row)| hilbert_index(n, column as u32, row as u32));
+                grid
+            }
+        };
+        grid.into_iter()
+            .filter_map(|(column, row)| {
+                let tile = Tile::new(area.x + column * size, area.y + row * size, size, size);
+                tile.intersect(&area)
+            })
+            .collect()
+    }
+}
+
+// Visits the cells of a grid along a square spiral starting in the middle cell.
+fn spiral(columns: i32, rows: i32) -> Vec<(i32, i32)> {
+    let total = (columns * rows) as usize;
+    let mut cells = Vec::with_capacity(total);
+    let (mut x, mut y) = ((columns - 1) / 2, (rows - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg = 0;
+    while cells.len() < total {
+        // Legs grow by one every second turn: 1, 1, 2, 2, 3, 3, ...
+        let (dx, dy) = directions[leg % 4];
+        for _ in 0..leg / 2 + 1 {
+            if (0..columns).contains(&x) && (0..rows).contains(&y) {
+                cells.push((x, y));
+            }
+            x += dx;
+            y += dy;
+        }
+        leg += 1;
+    }
+    cells
+}
+
+// Returns the distance along the Hilbert curve filling an `n` by `n` grid of the cell (x, y).
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // Rotate the quadrant so the curve inside it has the standard orientation
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hilbert_curve_visits_neighbouring_cells_in_turn() {
+        let order: Vec<u64> = [(0, 0), (0, 1), (1, 1), (1, 0)]
+            .iter()
+            .map(|&(x, y)| hilbert_index(2, x, y))
+            .collect();
+        assert_eq!(order, [0, 1, 2, 3]);
+
+        let n = 8;
+        let mut cells = vec![(0, 0); (n * n) as usize];
+        for (x, y) in (0..n).flat_map(|y| (0..n).map(move |x| (x, y))) {
+            cells[hilbert_index(n, x, y) as usize] = (x as i32, y as i32);
+        }
+        for pair in cells.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!((a.0 - b.0).abs() + (a.1 - b.1).abs(), 1, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn spiral_winds_out_from_the_middle() {
+        assert_eq!(
+            spiral(3, 3),
+            [
+                (1, 1),
+                (2, 1),
+                (2, 2),
+                (1, 2),
+                (0, 2),
+                (0, 1),
+                (0, 0),
+                (1, 0),
+                (2, 0)
+            ]
+        );
+        for (columns, rows) in [(4, 3), (5, 1), (1, 6)] {
+            let mut cells = spiral(columns, rows);
+            cells.sort();
+            cells.dedup();
+            assert_eq!(cells.len(), (columns * rows) as usize);
+        }
+    }
+
+    #[test]
+    fn tiles_cover_the_area_once_in_every_order() {
+        let area = Tile::new(3, 5, 50, 37);
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = order.tiles(area, 16);
+            assert_eq!(tiles.len(), 4 * 3);
+            let mut pixels: Vec<(i32, i32)> = tiles.iter().flat_map(|t| t.pixels()).collect();
+            pixels.sort();
+            let mut expected: Vec<(i32, i32)> = area.pixels().collect();
+            expected.sort();
+            assert_eq!(pixels, expected, "{:?}", order);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{color::Color, rtweekend::*};
+use std::f64::consts::PI;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub};
 
 /// A 3D vector representation.
@@ -55,16 +56,16 @@ impl Vec3 {
     }
 
     /// Returns a random Vec3 with each element in the range [0, 1).
-    pub fn random() -> Vec3 {
-        Vec3::new(random_double(), random_double(), random_double())
+    pub fn random(rng: &mut Rng) -> Vec3 {
+        Vec3::new(random_double(rng), random_double(rng), random_double(rng))
     }
 
     /// Returns a random Vec3 with each element in the range [min, max).
-    pub fn random_r(min: f64, max: f64) -> Vec3 {
+    pub fn random_r(rng: &mut Rng, min: f64, max: f64) -> Vec3 {
         Vec3::new(
-            random_double_range(min, max),
-            random_double_range(min, max),
-            random_double_range(min, max),
+            random_double_range(rng, min, max),
+            random_double_range(rng, min, max),
+            random_double_range(rng, min, max),
         )
     }
 
@@ -88,33 +89,19 @@ impl Vec3 {
         v
     }
 
-    /// Returns a random Vec3 in the unit disk.
-    pub fn random_in_unit_disk() -> Vec3 {
-        loop {
-            let p = Vec3::new(
-                random_double_range(-1.0, 1.0),
-                random_double_range(-1.0, 1.0),
-                0.0,
-            );
-            if p.length_squared() < 1.0 {
-                return p;
-            }
-        }
-    }
-
-    /// Returns a random Vec3 in the unit sphere.
-    pub fn random_in_unit_sphere() -> Vec3 {
-        loop {
-            let p = Vec3::random_r(-1.0, 1.0);
-            if p.length_squared() < 1.0 {
-                return p;
-            }
-        }
+    /// Returns a random Vec3 in the unit disk, using exactly two random numbers.
+    pub fn random_in_unit_disk(rng: &mut Rng) -> Vec3 {
+        let r = random_double(rng).sqrt();
+        let phi = 2.0 * PI * random_double(rng);
+        Vec3::new(r * phi.cos(), r * phi.sin(), 0.0)
     }
 
-    /// Returns a random unit vector.
-    pub fn random_unit_vector() -> Vec3 {
-        Vec3::unit_vector(Vec3::random_in_unit_sphere())
+    /// Returns a random unit vector, using exactly two random numbers.
+    pub fn random_unit_vector(rng: &mut Rng) -> Vec3 {
+        let z = 1.0 - 2.0 * random_double(rng);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_double(rng);
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
     }
 
     /// Returns the reflection of the Vec3.
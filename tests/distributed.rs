@@ -0,0 +1,117 @@
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+// Settings shared by every process, rendering a small part of the image quickly.
+const RENDER_ARGS: [&str; 9] = [
+    "--region",
+    "580,300,24,20",
+    "--crop",
+    "--spp",
+    "2",
+    "--tile-size",
+    "8",
+    "--seed",
+    "7",
+];
+
+// Creates an empty directory for one process of one test.
+fn scratch_dir(test: &str, name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "ray-tracer-{}-{}-{}",
+        test,
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Starts the renderer in `dir` with the shared settings and the given arguments.
+fn spawn(dir: &Path, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_ray_tracer"))
+        .current_dir(dir)
+        .args(RENDER_ARGS)
+        .arg("--quiet")
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+// Waits for a process to exit successfully, killing it after a minute.
+fn wait(mut child: Child) {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            assert!(status.success(), "renderer failed: {}", status);
+            return;
+        }
+        if start.elapsed() > Duration::from_secs(60) {
+            let _ = child.kill();
+            panic!("renderer did not finish");
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+// Renders with a coordinator and three workers at `address` and returns the coordinator's
+// image.
+fn render_distributed(test: &str, address: &str) -> Vec<u8> {
+    let coordinator_dir = scratch_dir(test, "coordinator");
+    let coordinator = spawn(&coordinator_dir, &["--coordinator", address]);
+    let worker_dirs: Vec<PathBuf> = (0..3)
+        .map(|k| scratch_dir(test, &format!("worker{}", k)))
+        .collect();
+    let workers: Vec<Child> = worker_dirs
+        .iter()
+        .map(|dir| spawn(dir, &["--worker", address]))
+        .collect();
+    wait(coordinator);
+    for worker in workers {
+        wait(worker);
+    }
+    let image = std::fs::read(coordinator_dir.join("output.ppm")).unwrap();
+    for dir in worker_dirs.iter().chain([&coordinator_dir]) {
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    image
+}
+
+// Renders locally and returns the image.
+fn render_local(test: &str) -> Vec<u8> {
+    let dir = scratch_dir(test, "local");
+    wait(spawn(&dir, &[]));
+    let image = std::fs::read(dir.join("output.ppm")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    image
+}
+
+#[test]
+fn workers_render_the_same_image_as_a_local_render() {
+    // Find a free port for the coordinator
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let address = format!("127.0.0.1:{}", port);
+    let distributed = render_distributed("tcp", &address);
+    let local = render_local("tcp");
+    // Guard against comparing two black images
+    assert!(local.iter().skip(16).any(|&byte| byte > 0));
+    assert!(distributed == local);
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_file_is_removed_after_the_render() {
+    let socket = std::env::temp_dir().join(format!("ray-tracer-{}.sock", std::process::id()));
+    let address = format!("unix:{}", socket.display());
+    let distributed = render_distributed("unix", &address);
+    assert!(distributed == render_local("unix"));
+    assert!(!socket.exists());
+}